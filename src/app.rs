@@ -1,50 +1,82 @@
-use std::sync::atomic::AtomicBool;
-use std::thread;
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  path::{Path, PathBuf},
+  sync::Arc,
+  thread,
+};
 
 use chrono::{Duration, FixedOffset};
 use eframe::{egui, CreationContext};
 
-use crate::config::Config;
+use crate::config::{Config, APP_NAME};
 use crate::hook::HookExecutor;
 use crate::util::shared;
 use crate::{
-  backend::Backend,
+  backend::{
+    Backend, BackendDriver, BackendError, DirWatcher, ParseErrorCallback,
+    ReadOnly,
+  },
+  event::{Event, EventId},
   notifier::Notifier,
-  util::{now, today, Result, Shared},
+  util::{now, today, Result, Shared, Zone},
   widget,
 };
 
+const VIEW_STATE_KEY: &str = "malakal_view_state";
+
+// the event files failing to parse, with their errors, as reported by
+// the backends
+#[derive(Clone, Default)]
+pub struct ParseErrors(Shared<BTreeMap<PathBuf, String>>);
+
+impl ParseErrors {
+  pub fn reporter(&self) -> ParseErrorCallback {
+    let errors = self.0.clone();
+    Arc::new(move |path: &Path, e: &BackendError| {
+      let mut errors = errors.lock().unwrap();
+      errors.insert(path.to_owned(), format!("{}: {e}", path.display()));
+    })
+  }
+}
+
 pub struct App {
   scheduler_ui: widget::ScheduleUi,
-  backend: Shared<dyn Backend>,
+  driver: BackendDriver,
   notifier: Shared<Notifier>,
   refresh_timer: Option<thread::JoinHandle<()>>,
   hook: HookExecutor,
   last_rect: Option<egui::Rect>,
+  watchers: Vec<DirWatcher>,
+  // events changed elsewhere while being edited here
+  conflicts: Vec<EventId>,
+  export_location: PathBuf,
+  // names the focused event, if any
+  window_title: String,
+  parse_errors: ParseErrors,
+  dismissed_parse_errors: BTreeSet<PathBuf>,
 }
 
-static SCROLL: AtomicBool = AtomicBool::new(true);
-
 impl eframe::App for App {
   fn update(
     &mut self,
     ctx: &eframe::egui::Context,
     _frame: &mut eframe::Frame,
   ) {
+    self.check_watcher();
     self.refresh_events();
     self.load_events();
+    self.count_events();
+    self.look_up_imports();
+    self.delete_events_in_range();
+    self.receive_replies();
+    self.show_conflicts(ctx);
+    self.show_parse_errors(ctx);
+    self.export_events();
 
     self.scheduler_ui.update_current_time();
 
     egui::CentralPanel::default().show(ctx, |ui| {
-      let mut scroll_area = egui::ScrollArea::both();
-
-      if SCROLL.fetch_and(false, std::sync::atomic::Ordering::SeqCst) {
-        let now = self.scheduler_ui.scroll_position_for_now();
-        scroll_area = scroll_area.vertical_scroll_offset(now);
-      }
-
-      scroll_area.show(ui, |ui| {
+      egui::ScrollArea::both().show(ui, |ui| {
         let just_resized = match self.last_rect {
           None => true,
           Some(rect) => rect != ui.max_rect(),
@@ -58,13 +90,31 @@ impl eframe::App for App {
       });
     });
 
-    self.apply_event_changes().expect("Failed applying changes");
+    self.show_focused_event(ctx);
+    self.apply_event_changes();
+  }
+
+  fn save(&mut self, storage: &mut dyn eframe::Storage) {
+    eframe::set_value(storage, VIEW_STATE_KEY, &self.scheduler_ui.save_state());
   }
 }
 
 impl App {
   pub fn setup(mut self, ctx: &CreationContext) -> Self {
+    if let Some(state) = ctx
+      .storage
+      .and_then(|storage| eframe::get_value(storage, VIEW_STATE_KEY))
+    {
+      self.scheduler_ui.load_state(state);
+    }
+
     let ctx = ctx.egui_ctx.clone();
+
+    let repaint_ctx = ctx.clone();
+    self
+      .driver
+      .set_on_reply(move || repaint_ctx.request_repaint());
+
     self.refresh_timer = Some(thread::spawn(move || loop {
       thread::sleep(std::time::Duration::from_millis(1000));
       ctx.request_repaint();
@@ -79,21 +129,52 @@ impl App {
     backend: impl Backend + 'static,
   ) -> Result<Self> {
     let first_day = today(&timezone) - Duration::days(day_count as i64 / 2);
-    let backend: Shared<dyn Backend> = shared(backend);
+    let read_only = config.read_only || backend.is_read_only();
+    let backend: Shared<dyn Backend> = if config.read_only {
+      shared(ReadOnly::new(backend))
+    } else {
+      shared(backend)
+    };
     let notifier = shared(Notifier::start(config, &backend)?);
+    let driver = BackendDriver::new(Arc::new(backend));
 
     let min_width = config.day_column_width.unwrap_or(260.0);
+    // main() already refused invalid timezone names
+    let zone = match config.timezone.as_deref().map(str::parse) {
+      Some(Ok(tz)) => Zone::Named(tz),
+      _ => Zone::Local,
+    };
 
     let scheduler_ui = widget::ScheduleUiBuilder::default()
       .new_event_calendar(config.calendar_name.clone())
       .first_day(first_day)
       .current_time(now(&timezone))
       .timezone(timezone)
+      .zone(Some(zone))
       .day_count(day_count)
       .refresh_requested(true)
       .scope_updated(true)
       .day_width(min_width)
       .day_min_width((min_width - 100.0).min(200.0))
+      .snapping_duration(config.snapping_duration)
+      .magnet_distance(config.magnet_distance)
+      .default_event_title(config.default_event_title.clone())
+      .default_event_duration(config.default_event_duration)
+      .week_start(config.week_start())
+      .weekend_days(config.weekend_days())
+      .show_week_numbers(config.show_week_numbers)
+      .working_hours(config.working_hours())
+      .visible_time_range(config.visible_hours())
+      .time_format(if config.twelve_hour_clock {
+        widget::TimeFormat::Twelve
+      } else {
+        widget::TimeFormat::TwentyFour
+      })
+      .transparent_events(config.transparent_events)
+      .resizer_hint_anchor(config.resizer_hint_anchor)
+      .max_overlapping_events(config.max_overlapping_events)
+      .context_menu(Some(widget::ContextMenu::new(copy_details_menu)))
+      .read_only(read_only)
       .build()
       .expect("failed to build scheduler");
 
@@ -101,26 +182,48 @@ impl App {
 
     Ok(Self {
       scheduler_ui,
-      backend,
+      driver,
       notifier,
       hook,
       last_rect: None,
       refresh_timer: None,
+      watchers: vec![],
+      conflicts: vec![],
+      export_location: PathBuf::from(&config.export_location),
+      window_title: APP_NAME.into(),
+      parse_errors: ParseErrors::default(),
+      dismissed_parse_errors: BTreeSet::new(),
     })
   }
 
+  // show the files the backends fail to parse
+  pub fn show_parse_errors_of(&mut self, errors: ParseErrors) {
+    self.parse_errors = errors;
+  }
+
+  pub fn watch_files(&mut self, watcher: DirWatcher) {
+    self.watchers.push(watcher);
+  }
+
+  // re-fetch the events edited by other programs
+  fn check_watcher(&mut self) {
+    // take the flags of all watchers
+    let changed = self
+      .watchers
+      .iter()
+      .fold(false, |changed, w| w.take_changed() || changed);
+    if changed {
+      self.scheduler_ui.refresh_requested = true;
+      self.scheduler_ui.scope_updated = true;
+    }
+  }
+
   pub fn refresh_events(&mut self) {
     if !self.scheduler_ui.refresh_requested {
       return;
     }
 
-    self
-      .backend
-      .lock()
-      .unwrap()
-      .force_refresh()
-      .expect("failed to reload event");
-
+    self.driver.force_refresh();
     self.load_events();
 
     self.scheduler_ui.refresh_requested = false;
@@ -132,44 +235,234 @@ impl App {
     }
 
     let (start, end) = self.scheduler_ui.time_range();
-    let events = self
-      .backend
+    self.driver.fetch_events(start, end);
+    self.scheduler_ui.scope_updated = false;
+  }
+
+  fn count_events(&mut self) {
+    if let Some((from, to)) = self.scheduler_ui.event_counts_requested.take() {
+      self.driver.count_events(from, to);
+    }
+  }
+
+  fn look_up_imports(&mut self) {
+    if let Some(events) = self.scheduler_ui.import_requested.take() {
+      self.driver.look_up_imports(events);
+    }
+  }
+
+  fn delete_events_in_range(&mut self) {
+    if let Some((from, to)) = self.scheduler_ui.range_deletion_requested.take()
+    {
+      self.driver.delete_events_in_range(from, to);
+    }
+  }
+
+  // merge the results of the backend requests finished since the last
+  // frame
+  fn receive_replies(&mut self) {
+    let replies = self.driver.poll();
+
+    if let Some(events) = replies.events {
+      self.scheduler_ui.load_events(events);
+    }
+
+    if let Some(counts) = replies.event_counts {
+      self.scheduler_ui.load_event_counts(counts);
+    }
+
+    for (old_id, new_id) in replies.rekeyed {
+      self.scheduler_ui.rekey_event(&old_id, new_id);
+    }
+
+    for (events, stored) in replies.imports {
+      self.scheduler_ui.import_events(events, stored);
+    }
+
+    for deletion in replies.range_deletions {
+      let failed = deletion.failed.len();
+      self
+        .scheduler_ui
+        .deleted_events_in_range(deletion.deleted, failed);
+    }
+
+    if replies.saved {
+      self.scheduler_ui.invalidate_event_counts();
+      self.notifier.lock().unwrap().events_updated();
+      self.hook.report_updated();
+    }
+
+    self.conflicts.extend(replies.conflicts);
+  }
+
+  fn export_events(&mut self) {
+    if !std::mem::take(&mut self.scheduler_ui.export_requested) {
+      return;
+    }
+
+    let content = match self.scheduler_ui.export_ics() {
+      Some(content) => content,
+      None => {
+        log::warn!("No events to export");
+        return;
+      }
+    };
+
+    match std::fs::write(&self.export_location, content) {
+      Ok(()) => log::info!("Exported events to {:?}", self.export_location),
+      Err(e) => log::error!("Failed exporting events: {e}"),
+    }
+  }
+
+  // name the focused event in the window title
+  fn show_focused_event(&mut self, ctx: &egui::Context) {
+    for event in self.scheduler_ui.emitted_events() {
+      log::debug!("{event:?}");
+    }
+
+    let focused = self.scheduler_ui.focused_event().cloned();
+    let title = focused
+      .and_then(|event_id| {
+        let events = self.scheduler_ui.events_mut();
+        events
+          .iter()
+          .find(|e| e.id == event_id)
+          .map(|e| e.title.clone())
+      })
+      .filter(|title| !title.is_empty())
+      .map(|title| format!("{title} - {APP_NAME}"))
+      .unwrap_or_else(|| APP_NAME.into());
+
+    if title != self.window_title {
+      ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+      self.window_title = title;
+    }
+  }
+
+  // ask whether to overwrite the events changed elsewhere or to take
+  // their stored version
+  fn show_conflicts(&mut self, ctx: &egui::Context) {
+    let event_id = match self.conflicts.first() {
+      Some(event_id) => event_id.clone(),
+      None => return,
+    };
+    let title = self
+      .scheduler_ui
+      .events_mut()
+      .iter()
+      .find(|e| e.id == event_id)
+      .map(|e| e.title.clone())
+      .unwrap_or_else(|| event_id.clone());
+
+    let mut resolved = false;
+    egui::Window::new("Conflicting change")
+      .collapsible(false)
+      .resizable(false)
+      .show(ctx, |ui| {
+        ui.label(format!(
+          "\"{title}\" was changed by another program since it was loaded."
+        ));
+        ui.horizontal(|ui| {
+          if ui.button("Keep mine").clicked() {
+            let mine = self
+              .scheduler_ui
+              .events_mut()
+              .iter()
+              .find(|e| e.id == event_id)
+              .cloned();
+            if let Some(mine) = mine {
+              // saved without a read time, it overwrites the other one
+              self.driver.update_events(vec![mine]);
+            }
+            resolved = true;
+          }
+          if ui.button("Reload").clicked() {
+            self.scheduler_ui.refresh_requested = true;
+            self.scheduler_ui.scope_updated = true;
+            resolved = true;
+          }
+        });
+      });
+
+    if resolved {
+      self.conflicts.retain(|id| id != &event_id);
+    }
+  }
+
+  // list the event files failing to parse until dismissed, as their
+  // events are missing
+  fn show_parse_errors(&mut self, ctx: &egui::Context) {
+    let errors: Vec<_> = self
+      .parse_errors
+      .0
       .lock()
       .unwrap()
-      .get_events(start, end)
-      .expect("load events");
+      .iter()
+      .filter(|(path, _)| !self.dismissed_parse_errors.contains(*path))
+      .map(|(path, e)| (path.clone(), e.clone()))
+      .collect();
+    if errors.is_empty() {
+      return;
+    }
 
-    self.scheduler_ui.load_events(events);
-    self.scheduler_ui.scope_updated = false;
+    egui::Window::new("Unreadable event files")
+      .collapsible(false)
+      .show(ctx, |ui| {
+        for (_, e) in &errors {
+          ui.label(e);
+        }
+        if ui.button("Dismiss").clicked() {
+          let paths = errors.into_iter().map(|(path, _)| path);
+          self.dismissed_parse_errors.extend(paths);
+        }
+      });
   }
 
-  fn apply_event_changes(&mut self) -> Result<()> {
-    let mut anything_changed = false;
-    let mut backend = self.backend.lock().unwrap();
+  fn apply_event_changes(&mut self) {
     let events = self.scheduler_ui.events_mut();
+    let mut changed = vec![];
     for event in events.iter() {
       if event.deleted {
-        backend.delete_event(&event.id)?;
-        anything_changed = true;
+        self.driver.delete_event(&event.id);
+      } else if event.created {
+        self.driver.create_event(event.clone());
       } else if event.changed {
-        backend.update_event(event)?;
-        anything_changed = true;
+        changed.push(event.clone());
       }
     }
 
+    // the changes of a frame are saved together
+    if !changed.is_empty() {
+      self.driver.update_events(changed);
+    }
+
     events.retain(|e| !e.deleted);
 
     for event in events.iter_mut() {
       event.reset_dirty_flags();
     }
+  }
+}
 
-    drop(backend);
-
-    if anything_changed {
-      self.notifier.lock().unwrap().events_updated();
-      self.hook.report_updated();
+// copies the title, time and location of the event as text, e.g. to
+// paste them into a message
+fn copy_details_menu(
+  ui: &mut egui::Ui,
+  event: &Event,
+) -> Option<widget::ContextMenuAction> {
+  if ui.button("Copy details").clicked() {
+    let mut details = format!(
+      "{} {}-{}",
+      event.title,
+      event.start.format("%Y-%m-%d %H:%M"),
+      event.end.format("%H:%M")
+    );
+    if let Some(location) = &event.location {
+      details += &format!(" @ {location}");
     }
-
-    Ok(())
+    ui.output_mut(|output| output.copied_text = details);
+    ui.close_menu();
   }
+
+  None
 }
@@ -1,25 +1,198 @@
+mod async_backend;
+mod caldav;
 mod indexed_local_dir;
 mod local_dir;
+mod multi_backend;
+mod read_only;
+// a conformance check for the backends
+#[cfg(any(test, feature = "testing"))]
+#[allow(unused)]
+pub mod testing;
 
-use super::event::{Event, EventId};
-use crate::util::{DateTime, Result};
+use std::fmt;
 
+use super::event::{new_event_id, Event, EventId};
+use crate::util::DateTime;
+
+pub use async_backend::{AsyncBackend, BackendDriver};
+pub use caldav::{CalDav, CalDavBuilder};
 pub use indexed_local_dir::IndexedLocalDir;
-pub use local_dir::{LocalDir, LocalDirBuilder};
+pub use local_dir::{
+  DirWatcher, LocalDir, LocalDirBuilder, ParseErrorCallback,
+};
+pub use multi_backend::MultiBackend;
+pub use read_only::ReadOnly;
+
+#[derive(Debug)]
+pub enum BackendError {
+  Io(std::io::Error),
+  // the directory of the events can't be listed
+  Dir(std::path::PathBuf, std::io::Error),
+  // the event is not valid utf-8 or ics
+  Parse(anyhow::Error),
+  NotFound(EventId),
+  // an event with the same id already exists, or the event was changed
+  // by someone else since it was read
+  Conflict(EventId),
+  // the backend doesn't accept modifications
+  ReadOnly,
+  // failures of the backend itself, e.g. its index
+  Other(anyhow::Error),
+}
+
+pub type BackendResult<T> = std::result::Result<T, BackendError>;
+
+// the outcome of deleting the events within a range
+#[derive(Debug, Default)]
+pub struct RangeDeletion {
+  pub deleted: usize,
+  // the events that couldn't be deleted, and why
+  pub failed: Vec<(EventId, BackendError)>,
+}
+
+impl fmt::Display for BackendError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Io(e) => write!(f, "I/O error: {e}"),
+      Self::Dir(path, e) => {
+        write!(f, "failed reading directory {}: {e}", path.display())
+      }
+      Self::Parse(e) => write!(f, "failed parsing event: {e:#}"),
+      Self::NotFound(id) => write!(f, "event {id} not found"),
+      Self::Conflict(id) => {
+        write!(f, "event {id} conflicts with the stored one")
+      }
+      Self::ReadOnly => write!(f, "the calendar is read-only"),
+      Self::Other(e) => write!(f, "{e:#}"),
+    }
+  }
+}
+
+impl std::error::Error for BackendError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Self::Io(e) | Self::Dir(_, e) => Some(e),
+      Self::Parse(e) | Self::Other(e) => Some(e.as_ref()),
+      Self::NotFound(_) | Self::Conflict(_) | Self::ReadOnly => None,
+    }
+  }
+}
+
+impl From<std::io::Error> for BackendError {
+  fn from(e: std::io::Error) -> Self {
+    Self::Io(e)
+  }
+}
+
+impl From<anyhow::Error> for BackendError {
+  fn from(e: anyhow::Error) -> Self {
+    // keep the variant of backend errors passed through anyhow
+    match e.downcast::<BackendError>() {
+      Ok(e) => e,
+      Err(e) => Self::Other(e),
+    }
+  }
+}
 
 pub trait Backend: Send {
-  fn get_event(&mut self, event_id: &EventId) -> Result<Event>;
+  fn get_event(&mut self, event_id: &EventId) -> BackendResult<Event>;
+
+  // get events which overlap with the half-open from..to interval.
+  fn get_events(
+    &mut self,
+    from: DateTime,
+    to: DateTime,
+  ) -> BackendResult<Vec<Event>>;
+
+  // visit the events which overlap with the from..to interval, without
+  // collecting them all first where the backend allows it
+  fn for_each_event(
+    &mut self,
+    from: DateTime,
+    to: DateTime,
+    f: &mut dyn FnMut(Event),
+  ) -> BackendResult<()> {
+    self.get_events(from, to)?.into_iter().for_each(f);
+    Ok(())
+  }
 
-  // get events which overlap with the from..to interval.
-  fn get_events(&mut self, from: DateTime, to: DateTime) -> Result<Vec<Event>>;
+  fn delete_event(&mut self, event_id: &EventId) -> BackendResult<()>;
 
-  fn delete_event(&mut self, event_id: &EventId) -> Result<()>;
+  fn update_event(&mut self, updated_event: &Event) -> BackendResult<()>;
 
-  fn update_event(&mut self, updated_event: &Event) -> Result<()>;
+  // returns the id the event is stored under, which backends minting
+  // their own ids may choose
+  fn create_event(&mut self, event: &Event) -> BackendResult<EventId>;
+
+  // Save several events at once, which backends may do faster than
+  // one by one. The results are in the order of the events, so that a
+  // failure doesn't hide the other events being saved.
+  fn create_events(&mut self, events: &[Event]) -> Vec<BackendResult<EventId>> {
+    events
+      .iter()
+      .map(|event| self.create_event(event))
+      .collect()
+  }
+
+  fn update_events(&mut self, events: &[Event]) -> Vec<BackendResult<()>> {
+    events
+      .iter()
+      .map(|event| self.update_event(event))
+      .collect()
+  }
 
-  fn create_event(&mut self, event: &Event) -> Result<()>;
+  // Delete the events which overlap with the from..to interval, the
+  // occurrences of recurring events being excluded from their series.
+  // A failure to delete an event doesn't stop the others from being
+  // deleted.
+  fn delete_events_in_range(
+    &mut self,
+    from: DateTime,
+    to: DateTime,
+  ) -> BackendResult<RangeDeletion> {
+    let mut deletion = RangeDeletion::default();
+    for event in self.get_events(from, to)? {
+      match self.delete_event(&event.id) {
+        Ok(()) => deletion.deleted += 1,
+        Err(e) => deletion.failed.push((event.id, e)),
+      }
+    }
 
-  fn force_refresh(&mut self) -> Result<()> {
+    Ok(deletion)
+  }
+
+  fn force_refresh(&mut self) -> BackendResult<()> {
     Ok(())
   }
+
+  // whether all modifications are refused
+  fn is_read_only(&self) -> bool {
+    false
+  }
+
+  // copy the events within from..to from another backend, returns the
+  // number of events imported. Events whose id already exists are
+  // imported under a fresh id, and occurrences of recurring events
+  // become standalone events.
+  fn import_from(
+    &mut self,
+    source: &mut dyn Backend,
+    from: DateTime,
+    to: DateTime,
+  ) -> BackendResult<usize> {
+    let mut events = source.get_events(from, to)?;
+    for event in &mut events {
+      let occurrence = event.recurrence_id.take().is_some();
+      if occurrence || self.get_event(&event.id).is_ok() {
+        event.id = new_event_id();
+      }
+    }
+
+    let ids = self
+      .create_events(&events)
+      .into_iter()
+      .collect::<BackendResult<Vec<_>>>()?;
+
+    Ok(ids.len())
+  }
 }
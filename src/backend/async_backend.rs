@@ -0,0 +1,502 @@
+use std::{
+  collections::{BTreeSet, HashMap},
+  future::Future,
+  pin::Pin,
+  sync::{
+    mpsc::{channel, Receiver, Sender},
+    Arc, Mutex, OnceLock,
+  },
+  task::{Context, Poll, Wake, Waker},
+  thread,
+};
+
+use crate::{
+  backend::{Backend, BackendError, BackendResult, RangeDeletion},
+  event::{Event, EventId},
+  recurrence,
+  util::{Date, DateTime, Shared},
+};
+
+const POOL_SIZE: usize = 4;
+
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = BackendResult<T>> + Send>>;
+
+// The non-blocking counterpart of Backend, for backends doing network
+// I/O.
+pub trait AsyncBackend: Send + Sync {
+  fn get_event(&self, event_id: EventId) -> BoxFuture<Event>;
+
+  // get events which overlap with the from..to interval.
+  fn get_events(&self, from: DateTime, to: DateTime) -> BoxFuture<Vec<Event>>;
+
+  fn delete_event(&self, event_id: EventId) -> BoxFuture<()>;
+
+  // see Backend::delete_events_in_range
+  fn delete_events_in_range(
+    &self,
+    from: DateTime,
+    to: DateTime,
+  ) -> BoxFuture<RangeDeletion>;
+
+  // one result per event, see Backend::update_events
+  fn update_events(
+    &self,
+    events: Vec<Event>,
+  ) -> BoxFuture<Vec<BackendResult<()>>>;
+
+  fn create_event(&self, event: Event) -> BoxFuture<EventId>;
+
+  fn force_refresh(&self) -> BoxFuture<()>;
+}
+
+// sync backends run on the thread pool
+impl<B: Backend + ?Sized + 'static> AsyncBackend for Shared<B> {
+  fn get_event(&self, event_id: EventId) -> BoxFuture<Event> {
+    run(self, move |backend| backend.get_event(&event_id))
+  }
+
+  fn get_events(&self, from: DateTime, to: DateTime) -> BoxFuture<Vec<Event>> {
+    run(self, move |backend| backend.get_events(from, to))
+  }
+
+  fn delete_event(&self, event_id: EventId) -> BoxFuture<()> {
+    run(self, move |backend| backend.delete_event(&event_id))
+  }
+
+  fn delete_events_in_range(
+    &self,
+    from: DateTime,
+    to: DateTime,
+  ) -> BoxFuture<RangeDeletion> {
+    run(self, move |backend| {
+      backend.delete_events_in_range(from, to)
+    })
+  }
+
+  fn update_events(
+    &self,
+    events: Vec<Event>,
+  ) -> BoxFuture<Vec<BackendResult<()>>> {
+    run(self, move |backend| Ok(backend.update_events(&events)))
+  }
+
+  fn create_event(&self, event: Event) -> BoxFuture<EventId> {
+    run(self, move |backend| backend.create_event(&event))
+  }
+
+  fn force_refresh(&self) -> BoxFuture<()> {
+    run(self, |backend| backend.force_refresh())
+  }
+}
+
+fn run<B, T, F>(backend: &Shared<B>, f: F) -> BoxFuture<T>
+where
+  B: Backend + ?Sized + 'static,
+  T: Send + 'static,
+  F: FnOnce(&mut B) -> BackendResult<T> + Send + 'static,
+{
+  let backend = backend.clone();
+  spawn(move || {
+    let mut backend = backend.lock().unwrap();
+    f(&mut backend)
+  })
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct ThreadPool {
+  sender: Mutex<Sender<Job>>,
+}
+
+impl ThreadPool {
+  fn new(size: usize) -> Self {
+    let (sender, receiver) = channel::<Job>();
+    let receiver = Arc::new(Mutex::new(receiver));
+
+    for i in 0..size {
+      let receiver = receiver.clone();
+      thread::Builder::new()
+        .name(format!("backend-{i}"))
+        .spawn(move || loop {
+          let job = receiver.lock().unwrap().recv();
+          match job {
+            Ok(job) => job(),
+            Err(_) => break,
+          }
+        })
+        .expect("failed spawning backend thread");
+    }
+
+    Self {
+      sender: Mutex::new(sender),
+    }
+  }
+
+  fn execute(&self, job: Job) {
+    self
+      .sender
+      .lock()
+      .unwrap()
+      .send(job)
+      .expect("thread pool is gone");
+  }
+}
+
+fn pool() -> &'static ThreadPool {
+  static POOL: OnceLock<ThreadPool> = OnceLock::new();
+  POOL.get_or_init(|| ThreadPool::new(POOL_SIZE))
+}
+
+struct TaskState<T> {
+  output: Option<BackendResult<T>>,
+  waker: Option<Waker>,
+}
+
+// resolves once the job on the thread pool finishes
+struct Task<T> {
+  state: Shared<TaskState<T>>,
+}
+
+impl<T> Future for Task<T> {
+  type Output = BackendResult<T>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let mut state = self.state.lock().unwrap();
+    match state.output.take() {
+      Some(output) => Poll::Ready(output),
+      None => {
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+      }
+    }
+  }
+}
+
+fn spawn<T, F>(f: F) -> BoxFuture<T>
+where
+  T: Send + 'static,
+  F: FnOnce() -> BackendResult<T> + Send + 'static,
+{
+  let state = Arc::new(Mutex::new(TaskState {
+    output: None,
+    waker: None,
+  }));
+
+  let task_state = state.clone();
+  pool().execute(Box::new(move || {
+    let output = f();
+    let mut state = task_state.lock().unwrap();
+    state.output = Some(output);
+    if let Some(waker) = state.waker.take() {
+      waker.wake();
+    }
+  }));
+
+  Box::pin(Task { state })
+}
+
+struct ThreadWaker(thread::Thread);
+
+impl Wake for ThreadWaker {
+  fn wake(self: Arc<Self>) {
+    self.0.unpark();
+  }
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+  let mut future = std::pin::pin!(future);
+  let waker: Waker = Arc::new(ThreadWaker(thread::current())).into();
+  let mut cx = Context::from_waker(&waker);
+
+  loop {
+    match future.as_mut().poll(&mut cx) {
+      Poll::Ready(output) => return output,
+      Poll::Pending => thread::park(),
+    }
+  }
+}
+
+enum Reply {
+  Events {
+    generation: u64,
+    range: (DateTime, DateTime),
+    events: BackendResult<Vec<Event>>,
+  },
+  Counted(BackendResult<HashMap<Date, usize>>),
+  LookedUp {
+    events: Vec<Event>,
+    stored: HashMap<EventId, Event>,
+  },
+  Saved(BackendResult<()>),
+  Created {
+    event_id: EventId,
+    stored_id: BackendResult<EventId>,
+  },
+  SavedMany(BackendResult<Vec<BackendResult<()>>>),
+  DeletedRange(BackendResult<RangeDeletion>),
+  Refreshed(BackendResult<()>),
+}
+
+type DriverJob = Pin<Box<dyn Future<Output = Reply> + Send>>;
+
+#[derive(Default)]
+pub struct Replies {
+  // the latest events fetched
+  pub events: Option<Vec<Event>>,
+  // the latest number of events by day counted
+  pub event_counts: Option<HashMap<Date, usize>>,
+  // events to import along with the stored versions of their series
+  pub imports: Vec<(Vec<Event>, HashMap<EventId, Event>)>,
+  // whether any change got saved
+  pub saved: bool,
+  // the created events stored under another id, and that id
+  pub rekeyed: Vec<(EventId, EventId)>,
+  // the outcomes of deleting the events within ranges
+  pub range_deletions: Vec<RangeDeletion>,
+  // the events not saved because they were changed elsewhere
+  pub conflicts: Vec<EventId>,
+}
+
+impl Replies {
+  fn add_saved(&mut self, saved: BackendResult<()>) {
+    match saved {
+      Ok(()) => self.saved = true,
+      Err(BackendError::Conflict(id)) => {
+        log::warn!("Event {id} was changed elsewhere, not saving it");
+        self.conflicts.push(id);
+      }
+      Err(e) => log::error!("Failed saving event: {e}"),
+    }
+  }
+}
+
+// Runs the backend requests in order on a separate thread so the UI
+// never waits for them. The replies are collected with poll() between
+// frames.
+pub struct BackendDriver {
+  backend: Arc<dyn AsyncBackend>,
+  jobs: Sender<DriverJob>,
+  replies: Receiver<Reply>,
+  on_reply: Arc<OnceLock<Box<dyn Fn() + Send + Sync>>>,
+  // bumped on every change, fetches from before it are outdated
+  generation: u64,
+  pending_fetch: Option<(DateTime, DateTime)>,
+}
+
+impl BackendDriver {
+  pub fn new(backend: Arc<dyn AsyncBackend>) -> Self {
+    let (jobs, job_receiver) = channel::<DriverJob>();
+    let (reply_sender, replies) = channel();
+    let on_reply: Arc<OnceLock<Box<dyn Fn() + Send + Sync>>> =
+      Arc::new(OnceLock::new());
+
+    let notify = on_reply.clone();
+    thread::Builder::new()
+      .name("backend-driver".into())
+      .spawn(move || {
+        for job in job_receiver {
+          if reply_sender.send(block_on(job)).is_err() {
+            break;
+          }
+
+          if let Some(notify) = notify.get() {
+            notify();
+          }
+        }
+      })
+      .expect("failed spawning backend driver");
+
+    Self {
+      backend,
+      jobs,
+      replies,
+      on_reply,
+      generation: 0,
+      pending_fetch: None,
+    }
+  }
+
+  // called from the driver thread whenever a reply is ready, e.g. to
+  // request a repaint
+  pub fn set_on_reply(&self, f: impl Fn() + Send + Sync + 'static) {
+    if self.on_reply.set(Box::new(f)).is_err() {
+      log::warn!("Reply callback is already set");
+    }
+  }
+
+  pub fn fetch_events(&mut self, from: DateTime, to: DateTime) {
+    let backend = self.backend.clone();
+    let generation = self.generation;
+    self.pending_fetch = Some((from, to));
+
+    self.submit(async move {
+      Reply::Events {
+        generation,
+        range: (from, to),
+        events: backend.get_events(from, to).await,
+      }
+    });
+  }
+
+  // the number of events starting on each day within the range, in
+  // the timezone of its start
+  pub fn count_events(&mut self, from: DateTime, to: DateTime) {
+    let backend = self.backend.clone();
+    self.submit(async move {
+      let counts = backend.get_events(from, to).await.map(|events| {
+        let mut counts = HashMap::new();
+        for event in events {
+          let date = event.start.with_timezone(&from.timezone()).date_naive();
+          *counts.entry(date).or_default() += 1;
+        }
+        counts
+      });
+      Reply::Counted(counts)
+    });
+  }
+
+  // the stored versions of the series of the events, for deciding
+  // whether to import them
+  pub fn look_up_imports(&mut self, events: Vec<Event>) {
+    let backend = self.backend.clone();
+    self.submit(async move {
+      let series_ids: BTreeSet<_> =
+        events.iter().map(recurrence::series_id).collect();
+      let mut stored = HashMap::new();
+      for series_id in series_ids {
+        match backend.get_event(series_id.clone()).await {
+          Ok(event) => {
+            stored.insert(series_id, event);
+          }
+          Err(BackendError::NotFound(_)) => (),
+          Err(e) => log::error!("Failed looking up {series_id}: {e}"),
+        }
+      }
+      Reply::LookedUp { events, stored }
+    });
+  }
+
+  pub fn force_refresh(&mut self) {
+    let backend = self.backend.clone();
+    self.submit(async move { Reply::Refreshed(backend.force_refresh().await) });
+    self.invalidate_fetch();
+  }
+
+  pub fn update_events(&mut self, events: Vec<Event>) {
+    let backend = self.backend.clone();
+    self.submit(async move {
+      Reply::SavedMany(backend.update_events(events).await)
+    });
+    self.invalidate_fetch();
+  }
+
+  pub fn create_event(&mut self, event: Event) {
+    let backend = self.backend.clone();
+    self.submit(async move {
+      let event_id = event.id.clone();
+      let stored_id = backend.create_event(event).await;
+      Reply::Created {
+        event_id,
+        stored_id,
+      }
+    });
+    self.invalidate_fetch();
+  }
+
+  pub fn delete_event(&mut self, event_id: &EventId) {
+    let backend = self.backend.clone();
+    let event_id = event_id.clone();
+    self.submit(
+      async move { Reply::Saved(backend.delete_event(event_id).await) },
+    );
+    self.invalidate_fetch();
+  }
+
+  pub fn delete_events_in_range(&mut self, from: DateTime, to: DateTime) {
+    let backend = self.backend.clone();
+    self.submit(async move {
+      Reply::DeletedRange(backend.delete_events_in_range(from, to).await)
+    });
+    self.invalidate_fetch();
+  }
+
+  pub fn poll(&mut self) -> Replies {
+    let mut result = Replies::default();
+    let replies: Vec<_> = self.replies.try_iter().collect();
+
+    for reply in replies {
+      match reply {
+        Reply::Events {
+          generation,
+          range,
+          events,
+        } if generation == self.generation => {
+          if self.pending_fetch == Some(range) {
+            self.pending_fetch = None;
+          }
+
+          match events {
+            Ok(events) => result.events = Some(events),
+            Err(e) => log::error!("Failed loading events: {e}"),
+          }
+        }
+        Reply::Events { .. } => (),
+        Reply::Counted(Ok(counts)) => result.event_counts = Some(counts),
+        Reply::Counted(Err(e)) => log::error!("Failed counting events: {e}"),
+        Reply::LookedUp { events, stored } => {
+          result.imports.push((events, stored))
+        }
+        Reply::Saved(saved) => result.add_saved(saved),
+        Reply::Created {
+          event_id,
+          stored_id,
+        } => match stored_id {
+          Ok(stored_id) => {
+            if stored_id != event_id {
+              result.rekeyed.push((event_id, stored_id));
+            }
+            result.add_saved(Ok(()));
+          }
+          Err(e) => result.add_saved(Err(e)),
+        },
+        Reply::SavedMany(Ok(results)) => {
+          for saved in results {
+            result.add_saved(saved);
+          }
+        }
+        Reply::SavedMany(Err(e)) => log::error!("Failed saving events: {e}"),
+        Reply::DeletedRange(Ok(deletion)) => {
+          for (event_id, e) in &deletion.failed {
+            log::error!("Failed deleting event {event_id}: {e}");
+          }
+          result.saved |= deletion.deleted > 0;
+          result.range_deletions.push(deletion);
+        }
+        Reply::DeletedRange(Err(e)) => {
+          log::error!("Failed deleting events: {e}")
+        }
+        Reply::Refreshed(Ok(())) => (),
+        Reply::Refreshed(Err(e)) => {
+          log::error!("Failed refreshing events: {e}")
+        }
+      }
+    }
+
+    result
+  }
+
+  fn submit(&self, job: impl Future<Output = Reply> + Send + 'static) {
+    if self.jobs.send(Box::pin(job)).is_err() {
+      log::error!("Backend driver is gone");
+    }
+  }
+
+  // events fetched before a change may not reflect it, fetch again
+  fn invalidate_fetch(&mut self) {
+    self.generation += 1;
+
+    if let Some((from, to)) = self.pending_fetch {
+      self.fetch_events(from, to);
+    }
+  }
+}
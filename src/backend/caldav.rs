@@ -0,0 +1,415 @@
+use std::{
+  collections::HashMap,
+  sync::{Mutex, OnceLock},
+};
+
+use base64::Engine;
+use derive_builder::Builder;
+use regex::Regex;
+
+use crate::{
+  backend::{local_dir::event_file_name, Backend, BackendError, BackendResult},
+  event::{Event, EventId},
+  ical::ICal,
+  recurrence,
+  util::{anyhow, DateTime},
+};
+
+// the location and version of an event on the server
+#[derive(Clone, Debug)]
+struct Resource {
+  href: String,
+  etag: Option<String>,
+}
+
+#[derive(Builder)]
+#[builder(try_setter, setter(into))]
+pub struct CalDav {
+  // the url of the calendar collection
+  url: String,
+  calendar: String,
+
+  #[builder(default)]
+  username: Option<String>,
+  #[builder(default)]
+  password: Option<String>,
+
+  #[builder(default = "ureq::Agent::new()", setter(skip))]
+  agent: ureq::Agent,
+
+  // resources of the events seen so far
+  #[builder(default, setter(skip))]
+  resources: HashMap<EventId, Resource>,
+}
+
+impl CalDav {
+  fn request(&self, method: &str, url: &str) -> ureq::Request {
+    let request = self.agent.request(method, url);
+
+    match &self.username {
+      Some(username) => {
+        let password = self.password.as_deref().unwrap_or_default();
+        let credentials = base64::engine::general_purpose::STANDARD
+          .encode(format!("{username}:{password}"));
+        request.set("Authorization", &format!("Basic {credentials}"))
+      }
+      None => request,
+    }
+  }
+
+  fn event_url(&self, event_id: &EventId) -> String {
+    match self.resources.get(event_id) {
+      Some(resource) => resolve_href(&self.url, &resource.href),
+      None => format!(
+        "{}/{}",
+        self.url.trim_end_matches('/'),
+        event_file_name(event_id)
+      ),
+    }
+  }
+
+  fn etag(&self, event_id: &EventId) -> Option<&str> {
+    self
+      .resources
+      .get(event_id)
+      .and_then(|resource| resource.etag.as_deref())
+  }
+
+  // remember the etag returned after a modification
+  fn record_etag(&mut self, event_id: &EventId, response: &ureq::Response) {
+    let resource = Resource {
+      href: self.event_url(event_id),
+      etag: response.header("ETag").map(Into::into),
+    };
+    self.resources.insert(event_id.clone(), resource);
+  }
+
+  // If-Match guards against overwriting changes made elsewhere since we
+  // fetched the event, If-None-Match against overwriting an event we
+  // haven't seen. Servers changing the stored data don't have to return
+  // its etag (RFC 4791 5.3.4), updates without one aren't guarded.
+  fn put_event(&mut self, event: &Event, create: bool) -> BackendResult<()> {
+    let body = ICal.generate(event)?;
+    let url = self.event_url(&event.id);
+    let mut request = self
+      .request("PUT", &url)
+      .set("Content-Type", "text/calendar; charset=utf-8");
+
+    request = match (self.etag(&event.id), create) {
+      (_, true) => request.set("If-None-Match", "*"),
+      (Some(etag), false) => request.set("If-Match", etag),
+      (None, false) => request,
+    };
+
+    log::debug!("Putting event {url}");
+    let response = request
+      .send_string(&body)
+      .map_err(|e| http_error(e, &event.id))?;
+    self.record_etag(&event.id, &response);
+
+    Ok(())
+  }
+
+  fn exclude_occurrence(
+    &mut self,
+    series_id: &EventId,
+    recurrence_id: DateTime,
+  ) -> BackendResult<()> {
+    let mut series = self.get_event(series_id)?;
+    if !series.exdates.contains(&recurrence_id) {
+      series.exdates.push(recurrence_id);
+      self.update_event(&series)?;
+    }
+
+    Ok(())
+  }
+
+  // Rewrite the resource holding the event along with the others in
+  // it, e.g. a recurring event along with its overrides.
+  fn update_resource(
+    &mut self,
+    event_id: &EventId,
+    update: impl FnOnce(&mut Vec<Event>),
+  ) -> BackendResult<()> {
+    let url = self.event_url(event_id);
+    let response = self
+      .request("GET", &url)
+      .call()
+      .map_err(|e| http_error(e, event_id))?;
+    // the etag we know of guards against changes made since we fetched
+    // the event
+    let etag = match self.etag(event_id) {
+      Some(etag) => Some(etag.to_string()),
+      None => response.header("ETag").map(String::from),
+    };
+    let body = response.into_string()?;
+    let mut events = ICal
+      .parse(&self.calendar, &body)
+      .map_err(BackendError::Parse)?;
+    update(&mut events);
+
+    let mut request = self
+      .request("PUT", &url)
+      .set("Content-Type", "text/calendar; charset=utf-8");
+    if let Some(etag) = &etag {
+      request = request.set("If-Match", etag);
+    }
+
+    log::debug!("Putting events of {url}");
+    let response = request
+      .send_string(&ICal.generate_many(&events)?)
+      .map_err(|e| http_error(e, event_id))?;
+
+    let resource = Resource {
+      href: url,
+      etag: response.header("ETag").map(Into::into),
+    };
+    for event in events {
+      self.resources.insert(event.id, resource.clone());
+    }
+
+    Ok(())
+  }
+
+  // see LocalDir::override_occurrence
+  fn override_occurrence(&mut self, occurrence: &Event) -> BackendResult<()> {
+    let (series_id, _) = recurrence::split_occurrence_id(&occurrence.id)
+      .ok_or_else(|| BackendError::NotFound(occurrence.id.clone()))?;
+
+    self.update_resource(&series_id, |events| {
+      events.retain(|e| e.id != occurrence.id);
+      events.push(occurrence.clone());
+    })
+  }
+
+  // the series and the original start of an occurrence, unless it's
+  // an event of its own
+  fn series_of(&self, event_id: &EventId) -> Option<(EventId, DateTime)> {
+    let (series_id, recurrence_id) = recurrence::split_occurrence_id(event_id)?;
+    let href = |id: &EventId| self.resources.get(id).map(|r| &r.href);
+    let standalone =
+      href(event_id).is_some_and(|h| href(&series_id) != Some(h));
+    (!standalone).then_some((series_id, recurrence_id))
+  }
+}
+
+impl Backend for CalDav {
+  fn get_events(
+    &mut self,
+    from: DateTime,
+    to: DateTime,
+  ) -> BackendResult<Vec<Event>> {
+    let format = |t: DateTime| t.naive_utc().format("%Y%m%dT%H%M%SZ");
+    let query = format!(
+      r#"<?xml version="1.0" encoding="utf-8" ?>
+<c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+  <d:prop>
+    <d:getetag/>
+    <c:calendar-data/>
+  </d:prop>
+  <c:filter>
+    <c:comp-filter name="VCALENDAR">
+      <c:comp-filter name="VEVENT">
+        <c:time-range start="{}" end="{}"/>
+      </c:comp-filter>
+    </c:comp-filter>
+  </c:filter>
+</c:calendar-query>"#,
+      format(from),
+      format(to)
+    );
+
+    let response = self
+      .request("REPORT", &self.url)
+      .set("Depth", "1")
+      .set("Content-Type", "application/xml; charset=utf-8")
+      .send_string(&query)
+      .map_err(|e| http_error(e, &self.url))?;
+    let body = response.into_string()?;
+
+    let mut events = vec![];
+    for (href, etag, data) in parse_multistatus(&body) {
+      let parsed = match ICal.parse(&self.calendar, &data) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+          log::warn!("Failed parsing {href}: {e:?}");
+          continue;
+        }
+      };
+
+      for event in &parsed {
+        let resource = Resource {
+          href: href.clone(),
+          etag: etag.clone(),
+        };
+        self.resources.insert(event.id.clone(), resource);
+      }
+      // overrides are stored in the resource of their series
+      events.extend(recurrence::expand_all(parsed, from, to));
+    }
+
+    Ok(events)
+  }
+
+  fn delete_event(&mut self, event_id: &EventId) -> BackendResult<()> {
+    // an override is removed from the resource of its series, and the
+    // occurrence excluded so that it doesn't show up again
+    if let Some((series_id, recurrence_id)) = self.series_of(event_id) {
+      if self.resources.contains_key(event_id) {
+        self.update_resource(&series_id, |events| {
+          events.retain(|e| &e.id != event_id)
+        })?;
+      }
+      return self.exclude_occurrence(&series_id, recurrence_id);
+    }
+
+    let url = self.event_url(event_id);
+    let mut request = self.request("DELETE", &url);
+    if let Some(etag) = self.etag(event_id) {
+      request = request.set("If-Match", etag);
+    }
+
+    log::debug!("Deleting event {url}");
+    request.call().map_err(|e| http_error(e, event_id))?;
+    self.resources.remove(event_id);
+
+    Ok(())
+  }
+
+  fn update_event(&mut self, updated_event: &Event) -> BackendResult<()> {
+    if updated_event.recurrence_id.is_some() {
+      return self.override_occurrence(updated_event);
+    }
+
+    // keep the overrides stored along with a recurring event, one not
+    // on the server yet has none, e.g. an imported one
+    if updated_event.rrule.is_some() {
+      if !self.resources.contains_key(&updated_event.id) {
+        return self.put_event(updated_event, true);
+      }
+
+      let updated = self.update_resource(&updated_event.id, |events| {
+        events.retain(|e| e.id != updated_event.id);
+        events.push(updated_event.clone());
+      });
+      return match updated {
+        Err(BackendError::NotFound(_)) => self.put_event(updated_event, true),
+        updated => updated,
+      };
+    }
+
+    self.put_event(updated_event, false)
+  }
+
+  // the UID is part of the body, so the server keeps it
+  fn create_event(&mut self, event: &Event) -> BackendResult<EventId> {
+    self.put_event(event, true)?;
+    Ok(event.id.clone())
+  }
+
+  fn get_event(&mut self, event_id: &EventId) -> BackendResult<Event> {
+    let url = self.event_url(event_id);
+    let response = self
+      .request("GET", &url)
+      .call()
+      .map_err(|e| http_error(e, event_id))?;
+
+    let etag = response.header("ETag").map(String::from);
+    let body = response.into_string()?;
+    let event = ICal
+      .parse(&self.calendar, &body)
+      .map_err(BackendError::Parse)?
+      .into_iter()
+      .find(|event| &event.id == event_id)
+      .ok_or_else(|| BackendError::NotFound(event_id.clone()))?;
+
+    let resource = Resource { href: url, etag };
+    self.resources.insert(event_id.clone(), resource);
+
+    Ok(event)
+  }
+}
+
+fn http_error(e: ureq::Error, subject: &str) -> BackendError {
+  match e {
+    ureq::Error::Status(404, _) => BackendError::NotFound(subject.into()),
+    // the precondition (etag) failed, the event was changed elsewhere
+    ureq::Error::Status(412, _) => BackendError::Conflict(subject.into()),
+    ureq::Error::Status(code, response) => BackendError::Other(anyhow!(
+      "{subject}: HTTP {code} {}",
+      response.status_text()
+    )),
+    ureq::Error::Transport(e) => BackendError::Io(std::io::Error::other(e)),
+  }
+}
+
+// the hrefs are usually absolute paths on the server
+fn resolve_href(base: &str, href: &str) -> String {
+  if href.starts_with("http://") || href.starts_with("https://") {
+    return href.into();
+  }
+
+  let origin = match base.find("://") {
+    Some(i) => match base[i + 3..].find('/') {
+      Some(j) => &base[..i + 3 + j],
+      None => base,
+    },
+    None => base,
+  };
+
+  format!("{origin}{href}")
+}
+
+// (href, etag, calendar data) of every response in a multistatus
+// reply
+fn parse_multistatus(xml: &str) -> Vec<(String, Option<String>, String)> {
+  let response = xml_element_regex("response");
+
+  response
+    .captures_iter(xml)
+    .filter_map(|cap| {
+      let response = &cap[1];
+      let href = xml_element(response, "href")?;
+      let etag = xml_element(response, "getetag");
+      let data = xml_element(response, "calendar-data")?;
+      Some((href, etag, data))
+    })
+    .collect()
+}
+
+// matches an element with any namespace prefix, compiled once per
+// element name
+fn xml_element_regex(name: &'static str) -> Regex {
+  static REGEXES: OnceLock<Mutex<HashMap<&str, Regex>>> = OnceLock::new();
+
+  let mut regexes = REGEXES.get_or_init(Default::default).lock().unwrap();
+  let regex = regexes.entry(name).or_insert_with(|| {
+    let pattern =
+      format!(r"(?s)<(?:[\w-]+:)?{name}\b[^>]*>(.*?)</(?:[\w-]+:)?{name}>");
+    Regex::new(&pattern).expect("invalid element regex")
+  });
+  regex.clone()
+}
+
+fn xml_element(xml: &str, name: &'static str) -> Option<String> {
+  let cap = xml_element_regex(name).captures(xml)?;
+  let text = cap[1].trim();
+
+  match text
+    .strip_prefix("<![CDATA[")
+    .and_then(|s| s.strip_suffix("]]>"))
+  {
+    Some(cdata) => Some(cdata.into()),
+    None => Some(xml_unescape(text)),
+  }
+}
+
+fn xml_unescape(s: &str) -> String {
+  s.replace("&lt;", "<")
+    .replace("&gt;", ">")
+    .replace("&quot;", "\"")
+    .replace("&apos;", "'")
+    .replace("&#13;", "\r")
+    .replace("&#xD;", "\r")
+    .replace("&amp;", "&")
+}
@@ -2,13 +2,15 @@ use chrono::{Timelike, Utc};
 use rusqlite::{params, Connection};
 use std::time::Duration;
 use std::{
-  cell::RefCell, fs::create_dir_all, fs::Metadata, path::Path, time::Instant,
+  cell::RefCell, collections::BTreeSet, fs::create_dir_all, fs::Metadata,
+  path::Path, time::Instant,
 };
 
 use crate::util::DateTime;
 use crate::{
-  backend::Backend,
+  backend::{Backend, BackendError, BackendResult, RangeDeletion},
   event::{Event, EventId},
+  recurrence,
   util::Result,
 };
 
@@ -120,15 +122,26 @@ COMMIT;
     Ok(())
   }
 
+  // files are indexed by their name, covering the span of all events
+  // inside
   fn upsert(
     &self,
     conn: &Connection,
-    event: &Event,
+    event_id: &str,
+    events: &[Event],
     metadata: &Metadata,
   ) -> Result<()> {
-    let event_id = &event.id;
-    let start = event.start.timestamp();
-    let end = &event.end.timestamp();
+    let start = events.iter().map(|e| e.start.timestamp()).min();
+    let start = start.unwrap_or_default();
+    // recurring events are indexed as open-ended and expanded on query
+    let end = events
+      .iter()
+      .map(|e| match e.rrule {
+        Some(_) => i64::MAX,
+        None => e.end.timestamp(),
+      })
+      .max()
+      .unwrap_or_default();
     let length = metadata.len() as usize;
     let modification_date = metadata.modified()?;
     let modification_timestamp = modification_date
@@ -155,25 +168,34 @@ DO UPDATE SET start=?2, end=?3, content_length=?4, modification_date=?5
     Ok(())
   }
 
-  fn refresh(&mut self) {
+  fn refresh(&mut self) -> BackendResult<()> {
     if Instant::now() < self.next_refresh_at {
-      return;
+      return Ok(());
     }
 
-    if let Err(e) = self.force_refresh() {
+    let result = self.force_refresh();
+    if let Err(e) = &result {
       log::error!("Failed refreshing {:?}", e);
     }
 
     self.next_refresh_at = Instant::now() + self.refresh_interval;
+    result
   }
 
   fn refresh_updated_files(&self) -> Result<()> {
     let mut conn = self.conn.borrow_mut();
     let tx = conn.transaction()?;
 
-    for file_entry in self.backend.all_event_file_entries() {
+    for file_entry in self.backend.all_event_file_entries()? {
       let path = file_entry.path();
-      let metadata = file_entry.metadata().unwrap();
+      // the file may have been removed since the directory was read
+      let metadata = match file_entry.metadata() {
+        Ok(metadata) => metadata,
+        Err(e) => {
+          log::debug!("Skipping {:?}: {}", path, e);
+          continue;
+        }
+      };
       let file_stem = path.file_stem().unwrap();
       let event_id = file_stem.to_str().unwrap();
       if let Ok(event_entry) = self.get_single_event_entry(&tx, event_id) {
@@ -203,11 +225,38 @@ DO UPDATE SET start=?2, end=?3, content_length=?4, modification_date=?5
     Ok(())
   }
 
+  // index the files of the events saved successfully, in a single
+  // transaction
+  fn index_saved_events(
+    &self,
+    events: &[Event],
+    results: &mut [BackendResult<()>],
+  ) -> Result<()> {
+    let mut conn = self.conn.borrow_mut();
+    let tx = conn.transaction()?;
+
+    for (event, result) in events.iter().zip(results.iter_mut()) {
+      if result.is_err() {
+        continue;
+      }
+
+      let path = self.backend.event_path(&event.id);
+      if let Err(e) = self.sync_event_entry(&tx, &path) {
+        *result = Err(e.into());
+      }
+    }
+
+    tx.commit()?;
+
+    Ok(())
+  }
+
   fn refresh_deleted_files(&self) -> Result<()> {
     let mut conn = self.conn.borrow_mut();
     let tx = conn.transaction()?;
     for event_id in self.all_event_entry_ids(&tx)? {
-      let path = self.backend.event_path(&event_id);
+      // entries are keyed by file name, which may differ from the id
+      let path = self.backend.file_path(&format!("{event_id}.ics"));
       if !path.exists() {
         log::debug!("Deleting event {:?}", event_id);
         self.delete_event_entry(&tx, &event_id)?;
@@ -232,9 +281,26 @@ DO UPDATE SET start=?2, end=?3, content_length=?4, modification_date=?5
     conn: &Connection,
     file: P,
   ) -> Result<()> {
-    let metadata = file.as_ref().metadata().unwrap();
-    let event = self.backend.parse_event(file)?;
-    self.upsert(conn, &event, &metadata)
+    let metadata = file.as_ref().metadata()?;
+    // broken files are left out of the index instead of failing the
+    // whole refresh
+    let events = match self.backend.parse_events_or_report(&file) {
+      Some(events) => events,
+      None => return Ok(()),
+    };
+    let file_stem = file.as_ref().file_stem().unwrap();
+    let event_id = file_stem.to_str().unwrap();
+    self.upsert(conn, event_id, &events, &metadata)
+  }
+
+  // re-index the file after it got changed or removed
+  fn sync_event_entry(&self, conn: &Connection, file: &Path) -> Result<()> {
+    if file.exists() {
+      return self.update_event_entry(conn, file);
+    }
+
+    let file_stem = file.file_stem().unwrap();
+    self.delete_event_entry(conn, &file_stem.to_string_lossy().into_owned())
   }
 
   fn all_event_entry_ids_between(
@@ -246,8 +312,10 @@ DO UPDATE SET start=?2, end=?3, content_length=?4, modification_date=?5
     let end = to.timestamp();
 
     let conn = self.conn.borrow();
+    // a closed interval to also find the events without duration, the
+    // exact range is checked after parsing
     let mut stmt = conn.prepare_cached(
-      "SELECT event_id FROM events WHERE start >= ? AND end <= ?",
+      "SELECT event_id FROM events WHERE start <= ?2 AND end >= ?1",
     )?;
     let event_ids = stmt
       .query_map([start, end], |row| row.get::<_, EventId>(0))?
@@ -258,43 +326,100 @@ DO UPDATE SET start=?2, end=?3, content_length=?4, modification_date=?5
 }
 
 impl Backend for IndexedLocalDir {
-  fn get_events(&mut self, from: DateTime, to: DateTime) -> Result<Vec<Event>> {
-    self.refresh();
+  fn get_events(
+    &mut self,
+    from: DateTime,
+    to: DateTime,
+  ) -> BackendResult<Vec<Event>> {
+    self.refresh()?;
 
     let event_ids = self.all_event_entry_ids_between(from, to)?;
 
-    let events = event_ids.into_iter().filter_map(|id| {
-      let path = self.backend.event_path(&id);
-      self.backend.parse_event(path).ok()
-    });
+    let events = event_ids
+      .into_iter()
+      .filter_map(|id| {
+        let path = self.backend.file_path(&format!("{id}.ics"));
+        self.backend.parse_events_or_report(path)
+      })
+      .flat_map(|events| recurrence::expand_all(events, from, to))
+      // other events in the same file may be out of range
+      .filter(|event| event.visible_in_range(from, to));
 
     Ok(events.collect())
   }
 
-  fn delete_event(&mut self, event_id: &EventId) -> Result<()> {
-    self.backend.delete_event(event_id)?;
-    self.delete_event_entry(&self.conn.borrow(), event_id)?;
+  fn delete_event(&mut self, event_id: &EventId) -> BackendResult<()> {
+    let path = self.backend.event_path(event_id);
+    match self.backend.delete_event(event_id) {
+      // the file is already gone, only its index entry is left
+      Ok(()) | Err(BackendError::NotFound(_)) => (),
+      Err(e) => return Err(e),
+    }
+    self.sync_event_entry(&self.conn.borrow(), &path)?;
     Ok(())
   }
 
-  fn update_event(&mut self, event: &Event) -> Result<()> {
+  // only the file of the event is indexed again, the others are left
+  // as they are
+  fn update_event(&mut self, event: &Event) -> BackendResult<()> {
     self.backend.update_event(event)?;
     let path = self.backend.event_path(&event.id);
-    self.update_event_entry(&self.conn.borrow(), path)?;
+    self.sync_event_entry(&self.conn.borrow(), &path)?;
     Ok(())
   }
 
-  fn create_event(&mut self, event: &Event) -> Result<()> {
-    self.backend.create_event(event)?;
-    let path = self.backend.event_path(&event.id);
-    self.create_event_entry(&self.conn.borrow(), path)
+  fn update_events(&mut self, events: &[Event]) -> Vec<BackendResult<()>> {
+    let mut results = self.backend.update_events(events);
+    if let Err(e) = self.index_saved_events(events, &mut results) {
+      // the next refresh picks up the files anyway
+      log::error!("Failed indexing events: {e:#}");
+    }
+    results
   }
 
-  fn get_event(&mut self, event_id: &EventId) -> Result<Event> {
+  // each file is indexed again once, however many of its events were
+  // deleted
+  fn delete_events_in_range(
+    &mut self,
+    from: DateTime,
+    to: DateTime,
+  ) -> BackendResult<RangeDeletion> {
+    let mut deletion = RangeDeletion::default();
+    let mut paths = BTreeSet::new();
+    for event in self.get_events(from, to)? {
+      let path = self.backend.event_path(&event.id);
+      match self.backend.delete_event(&event.id) {
+        Ok(()) => {
+          deletion.deleted += 1;
+          paths.insert(path);
+        }
+        Err(e) => deletion.failed.push((event.id, e)),
+      }
+    }
+
+    let conn = self.conn.borrow();
+    for path in paths {
+      if let Err(e) = self.sync_event_entry(&conn, &path) {
+        // the next refresh picks up the files anyway
+        log::error!("Failed indexing {path:?}: {e:#}");
+      }
+    }
+
+    Ok(deletion)
+  }
+
+  fn create_event(&mut self, event: &Event) -> BackendResult<EventId> {
+    let event_id = self.backend.create_event(event)?;
+    let path = self.backend.event_path(&event_id);
+    self.sync_event_entry(&self.conn.borrow(), &path)?;
+    Ok(event_id)
+  }
+
+  fn get_event(&mut self, event_id: &EventId) -> BackendResult<Event> {
     self.backend.get_event(event_id)
   }
 
-  fn force_refresh(&mut self) -> Result<()> {
+  fn force_refresh(&mut self) -> BackendResult<()> {
     self.refresh_updated_files()?;
     self.refresh_deleted_files()?;
     Ok(())
@@ -306,3 +431,108 @@ fn from_unix_timestamp(i: i64) -> chrono::DateTime<Utc> {
   let d = UNIX_EPOCH + Duration::from_secs(i as u64);
   chrono::DateTime::<Utc>::from(d)
 }
+
+#[cfg(test)]
+mod tests {
+  use std::{fs, path::PathBuf};
+
+  use chrono::{Duration, TimeZone};
+
+  use super::*;
+  use crate::{
+    backend::LocalDirBuilder,
+    config::APP_NAME,
+    event::{new_event_id, EventBuilder},
+    util::local_tz,
+  };
+
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+      .join(format!("{APP_NAME}-{name}-{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("failed creating the directory");
+    dir
+  }
+
+  fn indexed(dir: &Path, index_path: &Path) -> IndexedLocalDir {
+    let backend = LocalDirBuilder::default()
+      .dir(dir)
+      .calendar("test")
+      .build()
+      .expect("failed building backend");
+    IndexedLocalDir::new(backend, index_path).expect("failed indexing")
+  }
+
+  fn event(title: &str, hour: u32) -> Event {
+    let start = local_tz().with_ymd_and_hms(2024, 3, 1, hour, 0, 0).unwrap();
+    EventBuilder::default()
+      .id(new_event_id())
+      .calendar("test")
+      .title(title)
+      .start(start)
+      .end(start + Duration::hours(1))
+      .build()
+      .expect("failed building event")
+  }
+
+  fn entries(backend: &IndexedLocalDir) -> Vec<(EventId, i64, i64, i64)> {
+    let conn = backend.conn.borrow();
+    let mut stmt = conn
+      .prepare(
+        "SELECT event_id, start, end, content_length FROM events
+         ORDER BY event_id",
+      )
+      .unwrap();
+    stmt
+      .query_map([], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+      })
+      .unwrap()
+      .collect::<rusqlite::Result<_>>()
+      .unwrap()
+  }
+
+  #[test]
+  fn index_matches_a_full_scan_after_changes() {
+    let dir = temp_dir("index-dir");
+    let index_dir = temp_dir("index");
+    let mut backend = indexed(&dir, &index_dir.join("changed.sqlite"));
+
+    let kept = event("Kept", 9);
+    let mut moved = event("Moved", 10);
+    let deleted = event("Deleted", 11);
+    for event in [&kept, &moved, &deleted] {
+      backend.create_event(event).expect("failed creating event");
+    }
+    moved.start += Duration::days(1);
+    moved.end += Duration::days(1);
+    backend.update_event(&moved).expect("failed updating event");
+    backend
+      .delete_event(&deleted.id)
+      .expect("failed deleting event");
+
+    let scanned = indexed(&dir, &index_dir.join("scanned.sqlite"));
+    assert_eq!(entries(&backend).len(), 2);
+    assert_eq!(entries(&backend), entries(&scanned));
+
+    let _ = fs::remove_dir_all(&dir);
+    let _ = fs::remove_dir_all(&index_dir);
+  }
+
+  #[test]
+  fn missing_directories_fail_listing() {
+    let dir = temp_dir("index-missing");
+    let index_dir = temp_dir("index-of-missing");
+    let mut backend = indexed(&dir, &index_dir.join("missing.sqlite"));
+    fs::remove_dir_all(&dir).expect("failed removing the directory");
+
+    // due for a refresh
+    backend.next_refresh_at = Instant::now();
+    let event = event("Missing", 9);
+    match backend.get_events(event.start, event.end) {
+      Err(BackendError::Dir(path, _)) => assert_eq!(path, dir),
+      other => panic!("listed a missing directory: {other:?}"),
+    }
+
+    let _ = fs::remove_dir_all(&index_dir);
+  }
+}
@@ -1,123 +1,645 @@
 use anyhow::Context;
 use derive_builder::Builder;
 use filetime::FileTime;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use std::{
+  collections::HashMap,
   ffi::OsStr,
-  fs::DirEntry,
+  fs::{create_dir_all, DirEntry},
+  io::Write,
   path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{channel, RecvTimeoutError},
+    Arc,
+  },
+  thread,
+  time::{Duration, Instant},
 };
 
 use crate::{
-  backend::Backend,
+  backend::{Backend, BackendError, BackendResult},
   event::{Event, EventId},
   ical::ICal,
-  util::{DateTime, Result},
+  recurrence,
+  util::{DateTime, Result, Shared},
 };
 
+// wait for the file changes to settle before reporting them
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
+// changes to files we wrote ourselves within this window are ignored
+const OWN_WRITE_WINDOW: Duration = Duration::from_secs(2);
+
+pub type ParseErrorCallback = Arc<dyn Fn(&Path, &BackendError) + Send + Sync>;
+
 #[derive(Builder)]
 #[builder(try_setter, setter(into))]
 pub struct LocalDir {
   dir: PathBuf,
   calendar: String,
+
+  // store events of other calendars in a subdirectory named after
+  // the calendar, e.g. "<dir>/<calendar>/<event_id>.ics"
+  #[builder(default = "false")]
+  partitioned: bool,
+
+  // files recently written by us, to tell them apart from external
+  // edits when watching
+  #[builder(default, setter(skip))]
+  own_writes: Shared<HashMap<PathBuf, Instant>>,
+
+  // events stored in files not named after them, e.g. files holding
+  // several events
+  #[builder(default, setter(skip))]
+  containers: Shared<HashMap<EventId, PathBuf>>,
+
+  // called for every file that can't be read or parsed when listing
+  // events, besides logging it
+  #[builder(default, setter(strip_option))]
+  on_parse_error: Option<ParseErrorCallback>,
+}
+
+// Keeps watching the directory until dropped.
+pub struct DirWatcher {
+  _watcher: RecommendedWatcher,
+  changed: Arc<AtomicBool>,
+}
+
+impl DirWatcher {
+  // whether any event file was changed externally since the last call
+  pub fn take_changed(&self) -> bool {
+    self.changed.swap(false, Ordering::Relaxed)
+  }
 }
 
 impl LocalDir {
+  // Fails if the directory can't be read, e.g. it was removed or isn't
+  // accessible. Unreadable partitions are skipped, leaving the others.
   pub(crate) fn all_event_file_entries(
     &self,
-  ) -> impl Iterator<Item = DirEntry> + '_ {
-    let entries = self.dir.read_dir().expect("read_dir failed");
-    entries
+  ) -> BackendResult<impl Iterator<Item = DirEntry>> {
+    let mut dirs = vec![read_dir(&self.dir)?];
+    if self.partitioned {
+      for partition in self.partition_dirs()? {
+        match read_dir(&partition) {
+          Ok(entries) => dirs.push(entries),
+          Err(e) => log::error!("Skipping partition: {e}"),
+        }
+      }
+    }
+
+    let entries = dirs
       .into_iter()
+      .flatten()
       .filter_map(|entry| entry.ok())
-      .filter(|entry| entry.file_type().unwrap().is_file())
+      .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
       .filter(|entry| {
         entry.path().extension().and_then(OsStr::to_str) == Some("ics")
-      })
+      });
+    Ok(entries)
+  }
+
+  fn partition_dirs(&self) -> BackendResult<Vec<PathBuf>> {
+    let entries = read_dir(&self.dir)?
+      .filter_map(|entry| entry.ok())
+      .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+      .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+      .map(|entry| entry.path())
+      .collect();
+    Ok(entries)
   }
 
-  pub(crate) fn parse_event<P: AsRef<Path>>(&self, path: P) -> Result<Event> {
+  pub(crate) fn parse_events<P: AsRef<Path>>(
+    &self,
+    path: P,
+  ) -> BackendResult<Vec<Event>> {
     let path = path.as_ref().to_owned();
     let content = std::fs::read(&path)?;
-    let string = String::from_utf8(content)?;
 
-    ICal
-      .parse(&self.calendar, &string)
+    let parse = || -> Result<Vec<Event>> {
+      let string = String::from_utf8(content)?;
+      ICal.parse(&self.calendar_of(&path), &string)
+    };
+
+    let events = parse()
       .with_context(|| format!("parse ics file: {}", path.display()))
+      .map_err(BackendError::Parse)?;
+
+    let file_name = path.file_name().and_then(OsStr::to_str);
+    let mut containers = self.containers.lock().unwrap();
+    for event in &events {
+      let own_file = file_name == Some(event_file_name(&event.id).as_str());
+      if events.len() > 1 || !own_file {
+        containers.insert(event.id.clone(), path.clone());
+      }
+    }
+
+    Ok(events)
   }
 
-  fn all_events(&self) -> impl Iterator<Item = Event> + '_ {
-    self
-      .all_event_file_entries()
-      .filter_map(|entry| self.parse_event(entry.path()).ok())
+  // the calendar an event file belongs to, derived from its partition
+  fn calendar_of(&self, path: &Path) -> String {
+    match path.parent() {
+      Some(parent) if self.partitioned && parent != self.dir => parent
+        .file_name()
+        .map(|name| decode_file_name(&name.to_string_lossy()))
+        .unwrap_or_else(|| self.calendar.clone()),
+      _ => self.calendar.clone(),
+    }
   }
 
+  // the events of every file, kept together so that recurring events
+  // can be matched with their overrides
+  fn all_events_by_file(
+    &self,
+  ) -> BackendResult<impl Iterator<Item = Vec<Event>> + '_> {
+    let entries = self.all_event_file_entries()?;
+    Ok(entries.filter_map(|entry| self.parse_events_or_report(entry.path())))
+  }
+
+  // files failing to parse are skipped but reported, so that missing
+  // events can be explained
+  pub(crate) fn parse_events_or_report<P: AsRef<Path>>(
+    &self,
+    path: P,
+  ) -> Option<Vec<Event>> {
+    match self.parse_events(&path) {
+      Ok(events) => Some(events),
+      Err(e) => {
+        self.report_parse_error(path.as_ref(), &e);
+        None
+      }
+    }
+  }
+
+  pub(crate) fn report_parse_error(&self, path: &Path, e: &BackendError) {
+    log::warn!("Skipping {}: {e}", path.display());
+    if let Some(callback) = &self.on_parse_error {
+      callback(path, e);
+    }
+  }
+
+  // the location of an existing event, or where a new event would be
+  // stored in the top-level directory
   pub(crate) fn event_path(&self, event_id: &EventId) -> PathBuf {
+    let file_name = event_file_name(event_id);
     let mut path = self.dir.clone();
-    path.push(format!("{event_id}.ics"));
-    path
+    path.push(&file_name);
+
+    if !path.exists() {
+      if let Some(container) = self.container_of(event_id) {
+        return container;
+      }
+    }
+
+    self.file_path(&file_name)
   }
-}
 
-impl Backend for LocalDir {
-  fn get_events(&mut self, from: DateTime, to: DateTime) -> Result<Vec<Event>> {
-    let mut events = vec![];
-    for event in self.all_events() {
-      if event_visible_in_range(&event, from, to) {
-        events.push(event);
+  // the location of an existing file in any partition, or in the
+  // top-level directory
+  pub(crate) fn file_path(&self, file_name: &str) -> PathBuf {
+    let mut path = self.dir.clone();
+    path.push(file_name);
+
+    if self.partitioned && !path.exists() {
+      // an unreadable directory has no file to find
+      let partitions = match self.partition_dirs() {
+        Ok(partitions) => partitions,
+        Err(e) => {
+          log::warn!("Failed looking for {file_name} in partitions: {e}");
+          vec![]
+        }
+      };
+      let found = partitions.into_iter().find_map(|mut dir| {
+        dir.push(file_name);
+        dir.exists().then_some(dir)
+      });
+
+      if let Some(found) = found {
+        return found;
       }
     }
 
-    Ok(events)
+    path
   }
 
-  fn delete_event(&mut self, event_id: &EventId) -> Result<()> {
-    let path = self.event_path(event_id);
-    if path.exists() {
-      log::debug!("Removing event {:?}", path);
+  // the file holding the event along with others, if it still exists
+  fn container_of(&self, event_id: &EventId) -> Option<PathBuf> {
+    let containers = self.containers.lock().unwrap();
+    containers
+      .get(event_id)
+      .filter(|path| path.exists())
+      .cloned()
+  }
+
+  // Replace or remove (if None) a single event in a file holding
+  // several ones, keeping the others as they are.
+  fn update_container(
+    &self,
+    path: &Path,
+    event_id: &EventId,
+    updated_event: Option<&Event>,
+  ) -> BackendResult<()> {
+    let mut events = self.parse_events(path)?;
+    if !events.iter().any(|e| &e.id == event_id) {
+      return Err(BackendError::NotFound(event_id.clone()));
+    }
+
+    events.retain(|e| &e.id != event_id);
+    events.extend(updated_event.cloned());
+    self.record_own_write(path);
+
+    if events.is_empty() {
+      log::debug!("Removing event file {:?}", path);
       std::fs::remove_file(path)?;
     } else {
-      // TODO: log
+      log::debug!("Updating event {event_id} in {:?}", path);
+      write_atomically(path, ICal.generate_many(&events)?)?;
+    }
+
+    if updated_event.is_none() {
+      self.containers.lock().unwrap().remove(event_id);
     }
 
     Ok(())
   }
 
-  fn update_event(&mut self, updated_event: &Event) -> Result<()> {
+  // the location an event should be stored at according to its calendar
+  fn target_event_path(&self, event: &Event) -> PathBuf {
+    let mut path = self.dir.clone();
+    if self.partitioned && event.calendar != self.calendar {
+      path.push(encode_file_name(&event.calendar));
+    }
+    path.push(event_file_name(&event.id));
+    path
+  }
+
+  // refuse overwriting an event changed on disk since it was read,
+  // comparing whole seconds as LAST-MODIFIED has no fractions
+  fn check_unchanged(&mut self, event: &Event) -> BackendResult<()> {
+    let read_at = match event.saved_modified_at {
+      Some(read_at) => read_at,
+      None => return Ok(()),
+    };
+
+    match self.get_event(&event.id) {
+      // no telling when a file without LAST-MODIFIED was changed
+      Ok(stored) if !stored.modified_at_known => Ok(()),
+      Ok(stored) if stored.modified_at.timestamp() > read_at.timestamp() => {
+        Err(BackendError::Conflict(event.id.clone()))
+      }
+      _ => Ok(()),
+    }
+  }
+
+  // write the event without touching the directory
+  fn write_event(&mut self, updated_event: &Event) -> BackendResult<()> {
+    if updated_event.recurrence_id.is_some() {
+      return self.override_occurrence(updated_event);
+    }
+
+    self.check_unchanged(updated_event)?;
+
+    if let Some(container) = self.container_of(&updated_event.id) {
+      return self.update_container(
+        &container,
+        &updated_event.id,
+        Some(updated_event),
+      );
+    }
+
     let ics_content = ICal.generate(updated_event)?;
-    let path = self.event_path(&updated_event.id);
+    let path = self.relocate_event(updated_event)?;
 
     if !path.exists() {
       // TODO: show warning
     }
 
     log::debug!("Updating event {:?}", path);
-    std::fs::write(path, ics_content)?;
-    touch_dir(&self.dir);
+    self.record_own_write(&path);
+    write_atomically(&path, ics_content)?;
+
+    Ok(())
+  }
+
+  // move the event file if its calendar has changed
+  fn relocate_event(&self, event: &Event) -> BackendResult<PathBuf> {
+    let target = self.target_event_path(event);
+    let current = self.event_path(&event.id);
+
+    if current != target && current.exists() {
+      log::debug!("Moving event {:?} to {:?}", current, target);
+      self.record_own_write(&current);
+      self.record_own_write(&target);
+      create_dir_all(target.parent().unwrap())?;
+      // rename is atomic within the same filesystem
+      std::fs::rename(&current, &target)?;
+    }
+
+    Ok(target)
+  }
+
+  fn record_own_write(&self, path: &Path) {
+    let mut own_writes = self.own_writes.lock().unwrap();
+    own_writes.retain(|_, at| at.elapsed() < OWN_WRITE_WINDOW);
+    own_writes.insert(path.to_owned(), Instant::now());
+  }
+
+  // Watch for event files created, modified or removed by other
+  // programs.
+  pub fn watch(&self) -> Result<DirWatcher> {
+    let (sender, receiver) = channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher.watch(&self.dir, RecursiveMode::Recursive)?;
+
+    let changed = Arc::new(AtomicBool::new(false));
+    let own_writes = self.own_writes.clone();
+    let changed_flag = changed.clone();
+
+    thread::spawn(move || {
+      let is_external_change = |event: &notify::Event| {
+        let own_writes = own_writes.lock().unwrap();
+        let written_by_us = |path: &PathBuf| {
+          own_writes
+            .get(path)
+            .is_some_and(|at| at.elapsed() < OWN_WRITE_WINDOW)
+        };
+
+        matches!(
+          event.kind,
+          EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ) && event
+          .paths
+          .iter()
+          .any(|path| is_event_file(path) && !written_by_us(path))
+      };
+
+      while let Ok(event) = receiver.recv() {
+        match event {
+          Ok(event) if is_external_change(&event) => (),
+          Ok(_) => continue,
+          Err(e) => {
+            log::warn!("Failed watching event files: {e:?}");
+            continue;
+          }
+        }
+
+        // wait until no more changes come in
+        loop {
+          match receiver.recv_timeout(WATCH_DEBOUNCE) {
+            Ok(_) => continue,
+            Err(RecvTimeoutError::Timeout) => break,
+            Err(RecvTimeoutError::Disconnected) => return,
+          }
+        }
+
+        log::debug!("Event files changed externally");
+        changed_flag.store(true, Ordering::Relaxed);
+      }
+    });
+
+    Ok(DirWatcher {
+      _watcher: watcher,
+      changed,
+    })
+  }
+
+  // remove a single occurrence from a recurring event
+  fn exclude_occurrence(
+    &mut self,
+    series_id: &EventId,
+    recurrence_id: DateTime,
+  ) -> BackendResult<()> {
+    let mut series = self.get_event(series_id)?;
+    if !series.exdates.contains(&recurrence_id) {
+      series.exdates.push(recurrence_id);
+      self.update_event(&series)?;
+    }
+
+    Ok(())
+  }
+
+  // An edited occurrence is stored as an override (with its
+  // RECURRENCE-ID) in the file of its series, replacing the previous
+  // override if any.
+  fn override_occurrence(&mut self, occurrence: &Event) -> BackendResult<()> {
+    let (series_id, _) = recurrence::split_occurrence_id(&occurrence.id)
+      .ok_or_else(|| BackendError::NotFound(occurrence.id.clone()))?;
+
+    let path = self.event_path(&series_id);
+    if !path.exists() {
+      return Err(BackendError::NotFound(series_id));
+    }
+
+    let mut events = self.parse_events(&path)?;
+    if !events.iter().any(|e| e.id == series_id) {
+      return Err(BackendError::NotFound(series_id));
+    }
+
+    events.retain(|e| e.id != occurrence.id);
+    events.push(occurrence.clone());
+
+    log::debug!("Updating occurrence {} in {:?}", occurrence.id, path);
+    self.record_own_write(&path);
+    write_atomically(&path, ICal.generate_many(&events)?)?;
+    self
+      .containers
+      .lock()
+      .unwrap()
+      .insert(occurrence.id.clone(), path);
+
+    Ok(())
+  }
+}
+
+impl Backend for LocalDir {
+  fn get_events(
+    &mut self,
+    from: DateTime,
+    to: DateTime,
+  ) -> BackendResult<Vec<Event>> {
+    let mut events = vec![];
+    self.for_each_event(from, to, &mut |event| events.push(event))?;
+    Ok(events)
+  }
+
+  fn for_each_event(
+    &mut self,
+    from: DateTime,
+    to: DateTime,
+    f: &mut dyn FnMut(Event),
+  ) -> BackendResult<()> {
+    // the files are read one at a time as the events are visited
+    self
+      .all_events_by_file()?
+      .flat_map(|events| recurrence::expand_all(events, from, to))
+      .filter(|event| event.visible_in_range(from, to))
+      .for_each(f);
+    Ok(())
+  }
+
+  fn delete_event(&mut self, event_id: &EventId) -> BackendResult<()> {
+    if let Some(container) = self.container_of(event_id) {
+      self.update_container(&container, event_id, None)?;
+
+      // without its override, the occurrence would show up again
+      if let Some((series_id, recurrence_id)) =
+        recurrence::split_occurrence_id(event_id)
+      {
+        if self.container_of(&series_id) == Some(container) {
+          self.exclude_occurrence(&series_id, recurrence_id)?;
+        }
+      }
+
+      return Ok(());
+    }
+
+    let path = self.event_path(event_id);
+    if path.exists() {
+      log::debug!("Removing event {:?}", path);
+      self.record_own_write(&path);
+      std::fs::remove_file(path)?;
+    } else if let Some((series_id, recurrence_id)) =
+      recurrence::split_occurrence_id(event_id)
+    {
+      self.exclude_occurrence(&series_id, recurrence_id)?;
+    } else {
+      return Err(BackendError::NotFound(event_id.clone()));
+    }
 
     Ok(())
   }
 
-  fn create_event(&mut self, event: &Event) -> Result<()> {
+  fn update_event(&mut self, updated_event: &Event) -> BackendResult<()> {
+    self.write_event(updated_event)?;
+    touch_dir(&self.dir);
+    Ok(())
+  }
+
+  // the directory is touched once for all the events
+  fn update_events(&mut self, events: &[Event]) -> Vec<BackendResult<()>> {
+    let results = events.iter().map(|e| self.write_event(e)).collect();
+    touch_dir(&self.dir);
+    results
+  }
+
+  // the files are named after the event ids
+  fn create_event(&mut self, event: &Event) -> BackendResult<EventId> {
+    let path = self.target_event_path(event);
+    if path.exists() {
+      return Err(BackendError::Conflict(event.id.clone()));
+    }
+
     let ics_content = ICal.generate(event)?;
-    let path = self.event_path(&event.id);
+    create_dir_all(path.parent().unwrap())?;
 
     log::debug!("Creating event {:?}", path);
-    std::fs::write(path, ics_content)?;
+    self.record_own_write(&path);
+    write_atomically(&path, ics_content)?;
 
-    Ok(())
+    Ok(event.id.clone())
   }
 
-  fn get_event(&mut self, event_id: &EventId) -> Result<Event> {
+  fn get_event(&mut self, event_id: &EventId) -> BackendResult<Event> {
     let path = self.event_path(event_id);
-    let buffer = std::fs::read(path)?;
-    let string = String::from_utf8(buffer)?;
+    if !path.exists() {
+      return Err(BackendError::NotFound(event_id.clone()));
+    }
+
+    self
+      .parse_events(path)?
+      .into_iter()
+      .find(|event| &event.id == event_id)
+      .ok_or_else(|| BackendError::NotFound(event_id.clone()))
+  }
+}
+
+// also the name of the resources of new events on CalDAV servers, as
+// it is safe in the path of a URL
+pub(super) fn event_file_name(event_id: &EventId) -> String {
+  encode_file_name(event_id) + ".ics"
+}
+
+// UIDs and calendar names may contain anything, characters not safe
+// in file names are percent-encoded so that distinct names never share
+// a file
+fn encode_file_name(name: &str) -> String {
+  let mut encoded = String::with_capacity(name.len());
+  for (i, byte) in name.bytes().enumerate() {
+    let safe = byte.is_ascii_alphanumeric()
+      || matches!(byte, b'-' | b'_' | b'@' | b'+' | b'=')
+      // no hidden files
+      || (byte == b'.' && i > 0);
+    if safe {
+      encoded.push(byte as char);
+    } else {
+      encoded.push_str(&format!("%{byte:02X}"));
+    }
+  }
+  encoded
+}
+
+// names not made by encode_file_name are kept as they are
+fn decode_file_name(name: &str) -> String {
+  let bytes = name.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let hex = bytes.get(i + 1..i + 3).and_then(|hex| {
+      let hex = std::str::from_utf8(hex).ok()?;
+      u8::from_str_radix(hex, 16).ok()
+    });
+    match hex {
+      Some(byte) if bytes[i] == b'%' => {
+        decoded.push(byte);
+        i += 3;
+      }
+      _ => {
+        decoded.push(bytes[i]);
+        i += 1;
+      }
+    }
+  }
+  String::from_utf8(decoded).unwrap_or_else(|_| name.to_owned())
+}
+
+// skip hidden, backup and swap files of editors
+fn is_event_file(path: &Path) -> bool {
+  let name = match path.file_name().and_then(OsStr::to_str) {
+    Some(name) => name,
+    None => return false,
+  };
+
+  !name.starts_with('.')
+    && path.extension().and_then(OsStr::to_str) == Some("ics")
+}
 
-    ICal.parse(&self.calendar, &string)
+// Write to a hidden file next to the target first and rename it over
+// the target, so the file is never left half-written.
+fn write_atomically(path: &Path, content: String) -> std::io::Result<()> {
+  let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+  let tmp_path =
+    path.with_file_name(format!(".{file_name}.{}.tmp", std::process::id()));
+
+  let write = || -> std::io::Result<()> {
+    let mut file = std::fs::File::create(&tmp_path)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    // rename is atomic within the same filesystem
+    std::fs::rename(&tmp_path, path)
+  };
+
+  let result = write();
+  if result.is_err() {
+    let _ = std::fs::remove_file(&tmp_path);
   }
+  result
 }
 
-fn event_visible_in_range(e: &Event, start: DateTime, end: DateTime) -> bool {
-  e.start.max(start) <= e.end.min(end)
+fn read_dir(dir: &Path) -> BackendResult<std::fs::ReadDir> {
+  dir
+    .read_dir()
+    .map_err(|e| BackendError::Dir(dir.to_owned(), e))
 }
 
 fn touch_dir(path: &Path) {
@@ -129,3 +651,228 @@ fn touch_dir(path: &Path) {
     Err(e) => log::warn!("Failed updating directory mtime {path:?}: #{e:?}"),
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashSet;
+
+  use chrono::{Duration, TimeZone};
+
+  use super::*;
+  use crate::{
+    config::APP_NAME,
+    event::{new_event_id, EventBuilder},
+    util::local_tz,
+  };
+
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+      .join(format!("{APP_NAME}-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("failed creating the directory");
+    dir
+  }
+
+  fn event(calendar: &str) -> Event {
+    let start = local_tz().with_ymd_and_hms(2024, 3, 1, 9, 0, 0).unwrap();
+    EventBuilder::default()
+      .id("moved")
+      .calendar(calendar)
+      .title("Moved")
+      .start(start)
+      .end(start + Duration::hours(1))
+      .build()
+      .expect("failed building event")
+  }
+
+  #[test]
+  fn changing_the_calendar_moves_the_file() {
+    let dir = temp_dir("partitions");
+    let mut backend = LocalDirBuilder::default()
+      .dir(dir.clone())
+      .calendar("test")
+      .partitioned(true)
+      .build()
+      .expect("failed building backend");
+
+    let mut event = event("test");
+    backend.create_event(&event).expect("failed creating event");
+    assert!(dir.join("moved.ics").exists());
+
+    event.calendar = "Work/Home".into();
+    backend.update_event(&event).expect("failed updating event");
+    assert!(!dir.join("moved.ics").exists());
+    assert!(dir.join("Work%2FHome").join("moved.ics").exists());
+
+    let stored = backend.get_event(&event.id).expect("failed loading event");
+    assert_eq!(stored.calendar, "Work/Home");
+
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn event_ids_roundtrip_through_file_names() {
+    let dir = temp_dir("ids");
+    let backend = LocalDirBuilder::default()
+      .dir(dir.clone())
+      .calendar("test")
+      .build()
+      .expect("failed building backend");
+
+    let ids: HashSet<_> = (0..1000).map(|_| new_event_id()).collect();
+    assert_eq!(ids.len(), 1000);
+    for id in ids.iter().chain([&"../odd/uid: 1".to_string()]) {
+      let path = backend.event_path(id);
+      assert_eq!(path.parent(), Some(dir.as_path()));
+      let file_name = path.file_name().unwrap().to_str().unwrap();
+      let stem = file_name.strip_suffix(".ics").unwrap();
+      assert_eq!(&decode_file_name(stem), id);
+    }
+    // generated ids are safe as they are
+    let id = ids.iter().next().unwrap();
+    assert_eq!(event_file_name(id), format!("{id}.ics"));
+
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn ranges_are_half_open() {
+    let event = event("test");
+    let (start, end) = (event.start, event.end);
+    let hour = Duration::hours(1);
+
+    // touching the range on either side
+    assert!(!event.visible_in_range(end, end + hour));
+    assert!(!event.visible_in_range(start - hour, start));
+    // overlapping it by a minute
+    let minute = Duration::minutes(1);
+    assert!(event.visible_in_range(end - minute, end + hour));
+    assert!(event.visible_in_range(start - hour, start + minute));
+
+    // without duration, only shown when starting in the range
+    let mut instant = event.clone();
+    instant.end = instant.start;
+    assert!(instant.visible_in_range(start, end));
+    assert!(!instant.visible_in_range(start - hour, start));
+  }
+
+  #[test]
+  fn files_with_several_events_are_read_whole() {
+    let dir = temp_dir("exported");
+    let mut backend = LocalDirBuilder::default()
+      .dir(dir.clone())
+      .calendar("test")
+      .build()
+      .expect("failed building backend");
+
+    // as exported by another app, all in one file
+    let mut content = String::from(
+      "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Other//App//EN\r\n",
+    );
+    for (uid, hour) in [("first", 9), ("second", 11), ("third", 13)] {
+      content.push_str(&format!(
+        "BEGIN:VEVENT\r\nUID:{uid}\r\nDTSTAMP:20240301T080000Z\r\n\
+         DTSTART:20240301T{hour:02}0000Z\r\n\
+         DTEND:20240301T{hour:02}3000Z\r\n\
+         SUMMARY:{uid}\r\nEND:VEVENT\r\n"
+      ));
+    }
+    content.push_str("END:VCALENDAR\r\n");
+    std::fs::write(dir.join("export.ics"), content).expect("failed writing");
+
+    let from = DateTime::parse_from_rfc3339("2024-02-29T00:00:00Z").unwrap();
+    let to = DateTime::parse_from_rfc3339("2024-03-02T00:00:00Z").unwrap();
+    let events = backend.get_events(from, to).expect("failed listing");
+    let mut ids: Vec<_> = events.iter().map(|e| e.id.as_str()).collect();
+    ids.sort();
+    assert_eq!(ids, ["first", "second", "third"]);
+
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn bad_files_are_reported() {
+    let dir = temp_dir("bad-file");
+    let reported = Arc::new(std::sync::Mutex::new(vec![]));
+    let on_parse_error: ParseErrorCallback = {
+      let reported = reported.clone();
+      Arc::new(move |path: &Path, _: &BackendError| {
+        reported.lock().unwrap().push(path.to_owned());
+      })
+    };
+    let mut backend = LocalDirBuilder::default()
+      .dir(dir.clone())
+      .calendar("test")
+      .on_parse_error(on_parse_error)
+      .build()
+      .expect("failed building backend");
+
+    let event = event("test");
+    backend.create_event(&event).expect("failed creating event");
+    let bad_path = dir.join("bad.ics");
+    // not even text
+    std::fs::write(&bad_path, [0xff, 0xfe, 0xfd]).expect("failed writing file");
+
+    let (from, to) = (event.start - Duration::days(1), event.end);
+    let events = backend.get_events(from, to).expect("failed listing");
+    assert_eq!(events.len(), 1);
+    assert_eq!(*reported.lock().unwrap(), vec![bad_path]);
+
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn importing_copies_the_events() {
+    let source_dir = temp_dir("import-source");
+    let target_dir = temp_dir("import-target");
+    let mut source = LocalDirBuilder::default()
+      .dir(source_dir.clone())
+      .calendar("source")
+      .build()
+      .expect("failed building backend");
+    let mut target = LocalDirBuilder::default()
+      .dir(target_dir.clone())
+      .calendar("target")
+      .build()
+      .expect("failed building backend");
+
+    let event = event("source");
+    source.create_event(&event).expect("failed creating event");
+    target.create_event(&event).expect("failed creating event");
+
+    let (from, to) = (event.start - Duration::days(1), event.end);
+    let count = target
+      .import_from(&mut source, from, to)
+      .expect("failed importing");
+    assert_eq!(count, 1);
+
+    // the clashing id is imported under a fresh one
+    let events = target.get_events(from, to).expect("failed listing");
+    assert_eq!(events.len(), 2);
+    assert!(events.iter().any(|e| e.id == "moved"));
+    assert!(events.iter().all(|e| e.title == "Moved"));
+    assert!(events
+      .iter()
+      .all(|e| target_dir.join(event_file_name(&e.id)).exists()));
+
+    let _ = std::fs::remove_dir_all(&source_dir);
+    let _ = std::fs::remove_dir_all(&target_dir);
+  }
+
+  #[test]
+  fn missing_directories_fail_listing() {
+    let dir = temp_dir("missing").join("missing");
+    let mut backend = LocalDirBuilder::default()
+      .dir(dir.clone())
+      .calendar("test")
+      .build()
+      .expect("failed building backend");
+
+    let event = event("test");
+    match backend.get_events(event.start, event.end) {
+      Err(BackendError::Dir(path, _)) => assert_eq!(path, dir),
+      other => panic!("listed a missing directory: {other:?}"),
+    }
+
+    let _ = std::fs::remove_dir_all(dir.parent().unwrap());
+  }
+}
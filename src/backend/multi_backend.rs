@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use crate::{
+  backend::{Backend, BackendResult},
+  event::{Event, EventId},
+  util::DateTime,
+};
+
+// Shows the events of several backends together, e.g. calendars kept
+// in separate directories. Events are tagged with the backend they
+// came from (Event::origin), where their changes go back. Of the
+// events sharing an id, the one of the earliest backend is shown.
+pub struct MultiBackend {
+  sources: Vec<Box<dyn Backend>>,
+  // the source of every event seen so far, for deleting them by id
+  routes: HashMap<EventId, usize>,
+  // the source of every calendar seen so far, for new events
+  calendars: HashMap<String, usize>,
+}
+
+impl MultiBackend {
+  pub fn new(sources: Vec<Box<dyn Backend>>) -> Self {
+    assert!(!sources.is_empty(), "no backend given");
+
+    Self {
+      sources,
+      routes: HashMap::new(),
+      calendars: HashMap::new(),
+    }
+  }
+
+  fn record_source(&mut self, event: &mut Event, source: usize) {
+    event.origin = Some(source);
+    self.routes.insert(event.id.clone(), source);
+    self
+      .calendars
+      .entry(event.calendar.clone())
+      .or_insert(source);
+  }
+
+  // events without an origin, e.g. imported ones, go to the source
+  // their id was seen in, or else of their calendar, or the first one
+  fn source_of(&self, event: &Event) -> usize {
+    event
+      .origin
+      .filter(|&source| source < self.sources.len())
+      .or_else(|| self.routes.get(&event.id).copied())
+      .or_else(|| self.calendars.get(&event.calendar).copied())
+      .unwrap_or(0)
+  }
+}
+
+impl Backend for MultiBackend {
+  fn get_event(&mut self, event_id: &EventId) -> BackendResult<Event> {
+    if let Some(&source) = self.routes.get(event_id) {
+      let mut event = self.sources[source].get_event(event_id)?;
+      event.origin = Some(source);
+      return Ok(event);
+    }
+
+    let mut result = None;
+    for (source, backend) in self.sources.iter_mut().enumerate() {
+      match backend.get_event(event_id) {
+        Ok(event) => {
+          result = Some(Ok((event, source)));
+          break;
+        }
+        Err(e) => {
+          result.get_or_insert(Err(e));
+        }
+      }
+    }
+
+    let (mut event, source) = result.expect("no backend given")?;
+    self.record_source(&mut event, source);
+    Ok(event)
+  }
+
+  fn get_events(
+    &mut self,
+    from: DateTime,
+    to: DateTime,
+  ) -> BackendResult<Vec<Event>> {
+    let mut events: Vec<Event> = vec![];
+    let mut sources: HashMap<EventId, usize> = HashMap::new();
+
+    for source in 0..self.sources.len() {
+      for mut event in self.sources[source].get_events(from, to)? {
+        // the earlier source wins when the same id shows up twice
+        if let Some(&other) = sources.get(&event.id) {
+          if other != source {
+            log::warn!(
+              "Ignoring event {} from source {source}, already found in \
+               source {other}",
+              event.id
+            );
+            continue;
+          }
+        }
+
+        sources.insert(event.id.clone(), source);
+        self.record_source(&mut event, source);
+        events.push(event);
+      }
+    }
+
+    Ok(events)
+  }
+
+  fn delete_event(&mut self, event_id: &EventId) -> BackendResult<()> {
+    let source = self.routes.get(event_id).copied().unwrap_or(0);
+    self.sources[source].delete_event(event_id)?;
+    self.routes.remove(event_id);
+    Ok(())
+  }
+
+  fn update_event(&mut self, updated_event: &Event) -> BackendResult<()> {
+    let source = self.source_of(updated_event);
+    self.sources[source].update_event(updated_event)?;
+    self.routes.insert(updated_event.id.clone(), source);
+    Ok(())
+  }
+
+  fn create_event(&mut self, event: &Event) -> BackendResult<EventId> {
+    let source = self.source_of(event);
+    let event_id = self.sources[source].create_event(event)?;
+    self.routes.insert(event_id.clone(), source);
+    self
+      .calendars
+      .entry(event.calendar.clone())
+      .or_insert(source);
+    Ok(event_id)
+  }
+
+  fn force_refresh(&mut self) -> BackendResult<()> {
+    // refresh all sources even if some of them fail, returning the
+    // first error
+    let mut result = Ok(());
+    for backend in self.sources.iter_mut() {
+      match backend.force_refresh() {
+        Ok(()) => (),
+        Err(e) if result.is_ok() => result = Err(e),
+        Err(e) => log::error!("Failed refreshing events: {e}"),
+      }
+    }
+    result
+  }
+
+  fn is_read_only(&self) -> bool {
+    self.sources.iter().all(|backend| backend.is_read_only())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::{Path, PathBuf};
+
+  use chrono::{Duration, TimeZone};
+
+  use super::*;
+  use crate::{
+    backend::{LocalDir, LocalDirBuilder},
+    config::APP_NAME,
+    event::EventBuilder,
+    util::local_tz,
+  };
+
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+      .join(format!("{APP_NAME}-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("failed creating the directory");
+    dir
+  }
+
+  fn local_dir(dir: &Path, calendar: &str) -> LocalDir {
+    LocalDirBuilder::default()
+      .dir(dir)
+      .calendar(calendar)
+      .build()
+      .expect("failed building backend")
+  }
+
+  fn event(calendar: &str, title: &str) -> Event {
+    let start = local_tz().with_ymd_and_hms(2024, 3, 1, 9, 0, 0).unwrap();
+    EventBuilder::default()
+      .id("same")
+      .calendar(calendar)
+      .title(title)
+      .start(start)
+      .end(start + Duration::hours(1))
+      .build()
+      .expect("failed building event")
+  }
+
+  #[test]
+  fn colliding_ids_go_to_the_first_source() {
+    let (work_dir, home_dir) = (temp_dir("multi-work"), temp_dir("multi-home"));
+    let mut work = local_dir(&work_dir, "work");
+    let mut home = local_dir(&home_dir, "home");
+    work
+      .create_event(&event("work", "Work"))
+      .expect("failed creating");
+    home
+      .create_event(&event("home", "Home"))
+      .expect("failed creating");
+
+    let mut backend = MultiBackend::new(vec![Box::new(work), Box::new(home)]);
+    let (from, to) = {
+      let event = event("work", "Work");
+      (event.start, event.end)
+    };
+    let mut events = backend.get_events(from, to).expect("failed listing");
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].title, "Work");
+    assert_eq!(events[0].origin, Some(0));
+
+    events[0].title = "Edited".into();
+    backend.update_event(&events[0]).expect("failed updating");
+    let mut work = local_dir(&work_dir, "work");
+    assert_eq!(work.get_event(&"same".into()).unwrap().title, "Edited");
+    let mut home = local_dir(&home_dir, "home");
+    assert_eq!(home.get_event(&"same".into()).unwrap().title, "Home");
+
+    backend
+      .delete_event(&"same".into())
+      .expect("failed deleting");
+    assert!(!work_dir.join("same.ics").exists());
+    assert!(home_dir.join("same.ics").exists());
+
+    let _ = std::fs::remove_dir_all(&work_dir);
+    let _ = std::fs::remove_dir_all(&home_dir);
+  }
+}
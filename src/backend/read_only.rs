@@ -0,0 +1,68 @@
+use crate::{
+  backend::{Backend, BackendError, BackendResult, RangeDeletion},
+  event::{Event, EventId},
+  util::DateTime,
+};
+
+// Refuses any modification of the wrapped backend, e.g. for subscribed
+// calendars.
+pub struct ReadOnly<B> {
+  inner: B,
+}
+
+impl<B: Backend> ReadOnly<B> {
+  pub fn new(inner: B) -> Self {
+    Self { inner }
+  }
+}
+
+impl<B: Backend> Backend for ReadOnly<B> {
+  fn get_event(&mut self, event_id: &EventId) -> BackendResult<Event> {
+    self.inner.get_event(event_id)
+  }
+
+  fn get_events(
+    &mut self,
+    from: DateTime,
+    to: DateTime,
+  ) -> BackendResult<Vec<Event>> {
+    self.inner.get_events(from, to)
+  }
+
+  fn for_each_event(
+    &mut self,
+    from: DateTime,
+    to: DateTime,
+    f: &mut dyn FnMut(Event),
+  ) -> BackendResult<()> {
+    self.inner.for_each_event(from, to, f)
+  }
+
+  fn delete_event(&mut self, _event_id: &EventId) -> BackendResult<()> {
+    Err(BackendError::ReadOnly)
+  }
+
+  fn update_event(&mut self, _updated_event: &Event) -> BackendResult<()> {
+    Err(BackendError::ReadOnly)
+  }
+
+  fn create_event(&mut self, _event: &Event) -> BackendResult<EventId> {
+    Err(BackendError::ReadOnly)
+  }
+
+  fn delete_events_in_range(
+    &mut self,
+    _from: DateTime,
+    _to: DateTime,
+  ) -> BackendResult<RangeDeletion> {
+    Err(BackendError::ReadOnly)
+  }
+
+  fn force_refresh(&mut self) -> BackendResult<()> {
+    self.inner.force_refresh()
+  }
+
+  fn is_read_only(&self) -> bool {
+    true
+  }
+}
@@ -0,0 +1,151 @@
+use chrono::{Duration, NaiveDate, TimeZone};
+
+use crate::{
+  backend::{Backend, BackendError},
+  event::{new_event_id, Event, EventBuilder, EventStatus, Reminder},
+  util::{local_tz, DateTime},
+};
+
+// Check that a backend stores and returns events losslessly: every
+// sample event is created, read, updated, listed and deleted, and
+// must come back as it was saved. Panics on the first difference.
+pub fn assert_backend_roundtrip(backend: &mut dyn Backend, calendar: &str) {
+  for mut event in sample_events(calendar) {
+    // the backend may store it under an id of its own
+    event.id = backend
+      .create_event(&event)
+      .unwrap_or_else(|e| panic!("creating {}: {e}", event.id));
+    assert_stored(backend, &event);
+
+    let mut updated = event.clone();
+    updated.title = format!("{} (updated)", event.title);
+    if !event.all_day {
+      // all-day events only keep the date
+      updated.end = event.end + Duration::minutes(30);
+    }
+    updated.modified_at = event.modified_at + Duration::seconds(1);
+    backend
+      .update_event(&updated)
+      .unwrap_or_else(|e| panic!("updating {}: {e}", event.id));
+    assert_stored(backend, &updated);
+
+    let listed = backend
+      .get_events(updated.start, updated.end + Duration::seconds(1))
+      .unwrap_or_else(|e| panic!("listing {}: {e}", event.id));
+    assert!(
+      listed.iter().any(|e| e.id == updated.id),
+      "{} is missing from the listed events",
+      event.id
+    );
+
+    backend
+      .delete_event(&updated.id)
+      .unwrap_or_else(|e| panic!("deleting {}: {e}", event.id));
+    match backend.get_event(&updated.id) {
+      Err(BackendError::NotFound(_)) => (),
+      Ok(_) => panic!("{} is still stored after deleting it", event.id),
+      Err(e) => panic!("reading deleted {}: {e}", event.id),
+    }
+  }
+}
+
+fn assert_stored(backend: &mut dyn Backend, expected: &Event) {
+  let mut stored = backend
+    .get_event(&expected.id)
+    .unwrap_or_else(|e| panic!("reading {}: {e}", expected.id));
+
+  // DTSTAMP is regenerated on every save
+  stored.timestamp = expected.timestamp;
+  assert_eq!(&stored, expected, "{} changed when stored", expected.id);
+}
+
+// edge cases of the ics encoding, with the times in whole seconds as
+// kept by the files
+fn sample_events(calendar: &str) -> Vec<Event> {
+  let tz = local_tz();
+  let day = NaiveDate::from_ymd_opt(2024, 3, 1).expect("invalid date");
+  let next_day = day.succ_opt().expect("date overflow");
+  let at = |date: NaiveDate, h, m, s| -> DateTime {
+    let time = date.and_hms_opt(h, m, s).expect("invalid time");
+    tz.from_local_datetime(&time).unwrap()
+  };
+  let event = |title: &str, start: DateTime, end: DateTime| {
+    EventBuilder::default()
+      .id(new_event_id())
+      .calendar(calendar)
+      .title(title)
+      .start(start)
+      .end(end)
+      .timestamp(start)
+      .created_at(start)
+      .modified_at(start)
+      .build()
+      .expect("failed building event")
+  };
+
+  let mut escaped = event(
+    "Line one\nline two, with; escapes \\ and ünïcode ⚠",
+    at(day, 9, 0, 0),
+    at(day, 10, 0, 0),
+  );
+  escaped.description = Some("first\nsecond, third; fourth".into());
+  escaped.location = Some("Room 1; floor 2".into());
+  escaped.categories = vec!["Work, mostly".into(), "Focus".into()];
+  escaped.color = Some([0.0, 0.2, 1.0]);
+  escaped.status = Some(EventStatus::Tentative);
+  escaped.priority = Some(1);
+  escaped.sequence = 3;
+  escaped.transparent = true;
+  escaped.reminders = vec![Reminder {
+    offset: Duration::minutes(-10),
+    description: Some("Leave now; really".into()),
+  }];
+  escaped.extra_alarms = vec![vec![
+    "ACTION:AUDIO".into(),
+    "TRIGGER;VALUE=DATE-TIME:20240301T080000Z".into(),
+  ]];
+  escaped.extra_properties = vec![
+    "X-APPLE-TRAVEL-DURATION;VALUE=DURATION:PT30M".into(),
+    "ORGANIZER;CN=\"Doe, Jane\":mailto:jane@example.com".into(),
+  ];
+
+  let whole_day = event(
+    "Midnight to midnight",
+    at(day, 0, 0, 0),
+    at(next_day, 0, 0, 0),
+  );
+  let instant = event(
+    "At the end of the day",
+    at(day, 23, 59, 59),
+    at(day, 23, 59, 59),
+  );
+
+  let mut all_day = event("All day", at(day, 0, 0, 0), at(next_day, 0, 0, 0));
+  all_day.all_day = true;
+
+  vec![escaped, whole_day, instant, all_day]
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use super::*;
+  use crate::{backend::LocalDirBuilder, config::APP_NAME};
+
+  #[test]
+  fn local_dir_roundtrip() {
+    let dir = std::env::temp_dir()
+      .join(format!("{APP_NAME}-roundtrip-{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("failed creating the directory");
+
+    let mut backend = LocalDirBuilder::default()
+      .dir(dir.clone())
+      .calendar("test")
+      .build()
+      .expect("failed building backend");
+    assert_backend_roundtrip(&mut backend, "test");
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+}
@@ -1,17 +1,21 @@
-use std::path::PathBuf;
+use std::{collections::HashSet, ops::Range, path::PathBuf};
 
 use anyhow::{anyhow, Context};
-use chrono::Duration;
+use chrono::{Duration, NaiveTime, Weekday};
 use serde::{Deserialize, Serialize};
 use serde_with::{formats::Flexible, serde_as};
 use toml::ser::to_string_pretty;
 
+use crate::widget::{ResizerHintAnchor, TransparentEvents};
+
 #[serde_as]
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(default)]
 pub struct Config {
   pub calendar_name: String,
   pub calendar_location: String,
+  // directories of other calendars to show along
+  pub extra_calendar_locations: Vec<String>,
   pub timezone: Option<String>,
   pub notifier_switch: bool,
   pub notifier_blacklist_processes: Vec<String>,
@@ -21,6 +25,43 @@ pub struct Config {
   #[serde_as(as = "serde_with::DurationMilliSeconds<i64, Flexible>")]
   pub post_update_hook_delay: Duration,
   pub day_column_width: Option<f32>,
+  // the overlapping timed events drawn per day at most, the others are
+  // counted on a "+N more" chip
+  pub max_overlapping_events: Option<usize>,
+  pub partition_by_calendar: bool,
+  #[serde_as(as = "serde_with::DurationSeconds<i64, Flexible>")]
+  pub snapping_duration: Duration,
+  // how close (in points) a dragged edge must get to another event to
+  // stick to it, 0 to disable
+  pub magnet_distance: f32,
+  // for the events created by clicking or typing, e.g. "Busy"
+  pub default_event_title: String,
+  #[serde_as(as = "serde_with::DurationSeconds<i64, Flexible>")]
+  pub default_event_duration: Duration,
+  // e.g. "monday" or "sun"
+  pub week_start: String,
+  // the days whose columns are shaded, e.g. ["friday", "saturday"]
+  pub weekend_days: Vec<String>,
+  // show the ISO week numbers above the time marks
+  pub show_week_numbers: bool,
+  // e.g. "09:00-18:00", or "22:00-06:00" for night shifts
+  pub working_hours: Option<String>,
+  // e.g. "06:00-22:00", the other hours are compressed
+  pub visible_hours: Option<String>,
+  // show times like 2:30 PM instead of 14:30
+  pub twelve_hour_clock: bool,
+  // "shown", "dimmed" or "hidden" for the events marked as free time
+  // (TRANSP:TRANSPARENT)
+  pub transparent_events: TransparentEvents,
+  // "in_place", "gutter" or "follow_pointer" for the times shown while
+  // resizing or moving events
+  pub resizer_hint_anchor: ResizerHintAnchor,
+  pub caldav_url: Option<String>,
+  pub caldav_username: Option<String>,
+  pub caldav_password: Option<String>,
+  pub read_only: bool,
+  // where the shown events are exported to
+  pub export_location: String,
 }
 
 pub const APP_NAME: &str = env!("CARGO_PKG_NAME");
@@ -30,6 +71,7 @@ impl Default for Config {
     Self {
       calendar_name: "malakal".into(),
       calendar_location: format!("~/.calendar/{APP_NAME}"),
+      extra_calendar_locations: vec![],
       timezone: None,
       notifier_switch: true,
       notification_timeout: Duration::seconds(5),
@@ -37,18 +79,107 @@ impl Default for Config {
       post_update_hook: None,
       post_update_hook_delay: Duration::seconds(30),
       day_column_width: None,
+      max_overlapping_events: None,
+      partition_by_calendar: false,
+      snapping_duration: Duration::minutes(15),
+      magnet_distance: 8.0,
+      default_event_title: String::new(),
+      default_event_duration: Duration::minutes(15),
+      week_start: "monday".into(),
+      weekend_days: vec!["saturday".into(), "sunday".into()],
+      show_week_numbers: false,
+      working_hours: None,
+      visible_hours: None,
+      twelve_hour_clock: false,
+      transparent_events: TransparentEvents::Shown,
+      resizer_hint_anchor: ResizerHintAnchor::InPlace,
+      caldav_url: None,
+      caldav_username: None,
+      caldav_password: None,
+      read_only: false,
+      export_location: format!("~/{APP_NAME}-export.ics"),
     }
   }
 }
 
 impl Config {
   pub fn normalize(&mut self) -> anyhow::Result<()> {
-    self.calendar_location =
-      self.calendar_location.replace('~', &std::env::var("HOME")?);
+    let home = std::env::var("HOME")?;
+    self.calendar_location = self.calendar_location.replace('~', &home);
+    self.export_location = self.export_location.replace('~', &home);
+    for location in self.extra_calendar_locations.iter_mut() {
+      *location = location.replace('~', &home);
+    }
+
+    if self.snapping_duration <= Duration::zero() {
+      log::warn!("Invalid snapping_duration, falling back to 15 minutes");
+      self.snapping_duration = Duration::minutes(15);
+    }
+
+    if self.magnet_distance.is_nan() || self.magnet_distance < 0.0 {
+      log::warn!("Invalid magnet_distance, falling back to 8");
+      self.magnet_distance = 8.0;
+    }
+
+    if self.default_event_duration <= Duration::zero() {
+      log::warn!("Invalid default_event_duration, falling back to 15 minutes");
+      self.default_event_duration = Duration::minutes(15);
+    }
+
+    if self.week_start.parse::<Weekday>().is_err() {
+      log::warn!("Invalid week_start, falling back to monday");
+      self.week_start = "monday".into();
+    }
+
+    self.weekend_days.retain(|day| {
+      let valid = day.parse::<Weekday>().is_ok();
+      if !valid {
+        log::warn!("Ignoring invalid weekend day {day}");
+      }
+      valid
+    });
+
+    if self.working_hours.is_some() && self.working_hours().is_none() {
+      log::warn!("Invalid working_hours, expected e.g. \"09:00-18:00\"");
+      self.working_hours = None;
+    }
+
+    if self.max_overlapping_events == Some(0) {
+      log::warn!("Invalid max_overlapping_events, showing all the events");
+      self.max_overlapping_events = None;
+    }
+
+    let visible_hours_valid = self
+      .visible_hours()
+      .is_some_and(|range| range.start < range.end);
+    if self.visible_hours.is_some() && !visible_hours_valid {
+      log::warn!("Invalid visible_hours, expected e.g. \"06:00-22:00\"");
+      self.visible_hours = None;
+    }
 
     Ok(())
   }
 
+  pub fn week_start(&self) -> Weekday {
+    self.week_start.parse().unwrap_or(Weekday::Mon)
+  }
+
+  pub fn weekend_days(&self) -> HashSet<Weekday> {
+    self
+      .weekend_days
+      .iter()
+      .filter_map(|day| day.parse().ok())
+      .collect()
+  }
+
+  pub fn working_hours(&self) -> Option<Range<NaiveTime>> {
+    parse_time_range(self.working_hours.as_ref()?)
+  }
+
+  pub fn visible_hours(&self) -> Option<Range<NaiveTime>> {
+    parse_time_range(self.visible_hours.as_ref()?)
+  }
+
   pub fn read_or_initialize() -> anyhow::Result<Config> {
     let config_file = {
       let mut dir = dirs::config_dir()
@@ -87,3 +218,10 @@ impl Config {
     Ok(config)
   }
 }
+
+// e.g. "09:00-18:00"
+fn parse_time_range(s: &str) -> Option<Range<NaiveTime>> {
+  let (start, end) = s.split_once('-')?;
+  let parse = |s: &str| NaiveTime::parse_from_str(s.trim(), "%H:%M").ok();
+  Some(parse(start)?..parse(end)?)
+}
@@ -1,11 +1,18 @@
-use chrono::{FixedOffset, Offset, Timelike};
+use chrono::{Duration, FixedOffset, Offset};
 use derive_builder::Builder;
+use uuid::Uuid;
 
-use crate::util::{now, utc_now, DateTime};
+use crate::config::APP_NAME;
+use crate::util::{utc_now, DateTime};
 
-const SECS_PER_DAY: u64 = 24 * 3600;
 pub type EventId = String;
 
+// a globally unique UID (RFC 5545 3.8.4.7), also safe to use as a file
+// name
+pub(crate) fn new_event_id() -> EventId {
+  format!("{}@{APP_NAME}", Uuid::new_v4().hyphenated())
+}
+
 #[derive(Builder, Clone, Debug, PartialEq)]
 #[builder(try_setter, setter(into))]
 pub struct Event {
@@ -23,48 +30,236 @@ pub struct Event {
   #[builder(default = "utc_now()")]
   pub created_at: DateTime,
 
+  // RFC 5545 LAST-MODIFIED field, bumped on every change
   #[builder(default = "utc_now()")]
   pub modified_at: DateTime,
 
+  // modified_at as last read or saved, before the pending changes, to
+  // detect changes made by other programs in the meantime
+  #[builder(default, setter(skip))]
+  pub(crate) saved_modified_at: Option<DateTime>,
+
+  // whether modified_at was read from the file, other programs may
+  // leave LAST-MODIFIED out, making it the time the file was read
+  #[builder(default = "true", setter(skip))]
+  pub(crate) modified_at_known: bool,
+
+  // RFC 5545 SEQUENCE, the revision other clients tell updates by,
+  // bumped once per saved change
+  #[builder(default)]
+  pub sequence: u32,
+
   #[builder(default)]
   pub description: Option<String>,
 
-  #[builder(default = "[0.3; 3]")]
-  pub color: [f32; 3],
+  #[builder(default)]
+  pub location: Option<String>,
+
+  // rgb color in 0..1 from the COLOR property
+  #[builder(default)]
+  pub color: Option<[f32; 3]>,
+
+  // all-day events only use the date part of start and end, the end
+  // date being exclusive
+  #[builder(default = "false")]
+  pub all_day: bool,
+
+  // IANA name of the timezone the times were given in, used when
+  // saving the event
+  #[builder(default)]
+  pub timezone: Option<String>,
+
+  #[builder(default)]
+  pub status: Option<EventStatus>,
+
+  // RFC 5545 PRIORITY, from 1 (highest) to 9 (lowest), 0 is undefined
+  #[builder(default)]
+  pub priority: Option<u8>,
+
+  // TRANSP:TRANSPARENT, the event doesn't take up busy time
+  #[builder(default = "false")]
+  pub transparent: bool,
+
+  // from the CATEGORIES property, e.g. "Work" or "Health"
+  #[builder(default)]
+  pub categories: Vec<String>,
+
+  // display alarms relative to the start
+  #[builder(default)]
+  pub reminders: Vec<Reminder>,
+
+  // the content lines of each alarm not shown as a reminder, e.g. an
+  // audio one or one at a fixed time, written back as they were
+  #[builder(default)]
+  pub extra_alarms: Vec<Vec<String>>,
+
+  // raw RFC 5545 RRULE value, expanded by the backends
+  #[builder(default)]
+  pub rrule: Option<String>,
+
+  // start times excluded from the recurrence
+  #[builder(default)]
+  pub exdates: Vec<DateTime>,
+
+  // the original start of a materialized occurrence
+  #[builder(default)]
+  pub recurrence_id: Option<DateTime>,
+
+  // the content lines of the properties malakal doesn't know about,
+  // e.g. ORGANIZER or X- extensions, written back as they were
+  #[builder(default)]
+  pub extra_properties: Vec<String>,
+
+  // the index of the backend the event was read from when several are
+  // shown together, for its changes to go back there
+  #[builder(default, setter(skip))]
+  pub(crate) origin: Option<usize>,
+
+  // the event ended before it started in its file, and got its times
+  // swapped
+  #[builder(default = "false", setter(skip))]
+  pub(crate) times_swapped: bool,
 
   #[builder(default = "false", setter(skip))]
   pub(crate) deleted: bool,
 
   #[builder(default = "false", setter(skip))]
   pub(crate) changed: bool,
+
+  // not stored yet, saved with create_event
+  #[builder(default = "false", setter(skip))]
+  pub(crate) created: bool,
+}
+
+// a display alarm relative to the start of the event
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reminder {
+  // negative for before the start
+  pub offset: Duration,
+  // the text shown, the title of the event if missing
+  pub description: Option<String>,
+}
+
+// RFC 5545 STATUS of events
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventStatus {
+  Tentative,
+  Confirmed,
+  Cancelled,
+}
+
+impl EventStatus {
+  pub(crate) fn from_ical(s: &str) -> Option<Self> {
+    match s.to_ascii_uppercase().as_str() {
+      "TENTATIVE" => Some(Self::Tentative),
+      "CONFIRMED" => Some(Self::Confirmed),
+      "CANCELLED" => Some(Self::Cancelled),
+      _ => None,
+    }
+  }
+
+  pub(crate) fn to_ical(self) -> &'static str {
+    match self {
+      Self::Tentative => "TENTATIVE",
+      Self::Confirmed => "CONFIRMED",
+      Self::Cancelled => "CANCELLED",
+    }
+  }
 }
 
 impl Event {
-  pub(crate) fn start_position_of_day(&self) -> f32 {
-    (self.start.num_seconds_from_midnight() as f32 / SECS_PER_DAY as f32)
-      .clamp(0.0, 1.0)
+  // Both the event and the range are half-open, so that an event ending
+  // exactly at the start of the range (or starting at its end) isn't
+  // included. Events without duration are visible at their start.
+  pub(crate) fn visible_in_range(
+    &self,
+    start: DateTime,
+    end: DateTime,
+  ) -> bool {
+    if self.start == self.end {
+      return start <= self.start && self.start < end;
+    }
+
+    self.start < end && start < self.end
+  }
+
+  // 0 for undefined priority, up to 9 for the highest one
+  pub(crate) fn priority_rank(&self) -> u8 {
+    match self.priority {
+      Some(priority @ 1..=9) => 10 - priority,
+      _ => 0,
+    }
   }
 
-  pub(crate) fn mark_changed(&mut self) {
-    self.modified_at = now(&self.modified_at.offset().fix());
+  // now is the time of the change, as told by the clock of the caller
+  pub(crate) fn mark_changed(&mut self, now: DateTime) {
+    if self.saved_modified_at.is_none() {
+      self.sequence += 1;
+    }
+    self.touch(now);
+  }
+
+  // like mark_changed but keeping the sequence, which starts at 0 for
+  // events never saved before (RFC 5545 3.8.7.4)
+  pub(crate) fn mark_created(&mut self, now: DateTime) {
+    self.touch(now);
+    self.created = true;
+  }
+
+  fn touch(&mut self, now: DateTime) {
+    self.saved_modified_at.get_or_insert(self.modified_at);
+    self.modified_at = now.with_timezone(&self.modified_at.offset().fix());
     self.changed = true;
   }
 
-  pub(crate) fn mark_deleted(&mut self) {
-    self.modified_at = now(&self.modified_at.offset().fix());
+  pub(crate) fn mark_deleted(&mut self, now: DateTime) {
+    self.modified_at = now.with_timezone(&self.modified_at.offset().fix());
     self.deleted = true;
   }
 
+  // equal but for the bookkeeping of changes, e.g. an event committed
+  // again without being edited
+  pub(crate) fn same_content(&self, other: &Event) -> bool {
+    let mut this = self.clone();
+    this.reset_dirty_flags();
+    this.modified_at = other.modified_at;
+    this.sequence = other.sequence;
+    let mut other = other.clone();
+    other.reset_dirty_flags();
+    this == other
+  }
+
+  // the changes are on their way to the backend
   pub(crate) fn reset_dirty_flags(&mut self) {
     self.deleted = false;
     self.changed = false;
+    self.created = false;
+    self.saved_modified_at = None;
   }
 
   pub(crate) fn set_timezone(&mut self, tz: &FixedOffset) {
     self.created_at = self.created_at.with_timezone(tz);
     self.modified_at = self.modified_at.with_timezone(tz);
     self.timestamp = self.timestamp.with_timezone(tz);
-    self.start = self.start.with_timezone(tz);
-    self.end = self.end.with_timezone(tz);
+
+    if self.all_day {
+      // dates of all-day events are floating, keep them as they are
+      let keep_local = |t: DateTime| {
+        t.naive_local()
+          .and_local_timezone(*tz)
+          .single()
+          .expect("timezone conversion error")
+      };
+      self.start = keep_local(self.start);
+      self.end = keep_local(self.end);
+      self.exdates.iter_mut().for_each(|t| *t = keep_local(*t));
+    } else {
+      self.start = self.start.with_timezone(tz);
+      self.end = self.end.with_timezone(tz);
+      self
+        .exdates
+        .iter_mut()
+        .for_each(|t| *t = t.with_timezone(tz));
+    }
   }
 }
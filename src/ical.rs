@@ -1,139 +1,1126 @@
-use anyhow::{bail, ensure};
-use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use std::{collections::BTreeSet, ops::RangeInclusive};
+
+use anyhow::ensure;
+use chrono::{
+  DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, Utc,
+};
 use ical::property::Property;
 
-use crate::event::{Event, EventBuilder};
-use crate::util::{anyhow, Result};
+use crate::event::{Event, EventBuilder, EventStatus, Reminder};
+use crate::recurrence;
+use crate::util::{anyhow, local_tz, reorder_times, Result};
+
+use self::timezone::VTimeZone;
+
+mod timezone;
+
+// the product identifier written to the calendars (RFC 5545 3.7.3)
+pub(crate) const PRODID: &str = concat!(
+  "-//",
+  env!("CARGO_PKG_NAME"),
+  "//",
+  env!("CARGO_PKG_NAME"),
+  " ",
+  env!("CARGO_PKG_VERSION"),
+  "//EN"
+);
+
+// how many years the timezones of recurring events are described for
+const RECURRING_TIMEZONE_YEARS: i32 = 10;
 
 pub(crate) struct ICal;
 
 impl ICal {
   pub fn generate(&self, event: &Event) -> Result<String> {
+    self.generate_many(std::slice::from_ref(event))
+  }
+
+  // all events in a single calendar, as found in files holding several
+  // events
+  pub fn generate_many(&self, events: &[Event]) -> Result<String> {
     use ics::{properties::*, *};
 
-    let mut ical_cal = ICalendar::new("2.0", "malakal");
+    // VERSION, PRODID and CALSCALE make every file a valid calendar on
+    // its own
+    let mut ical_cal = ICalendar::new("2.0", PRODID);
     ical_cal.add_timezone(TimeZone::standard(
       "UTC",
       Standard::new("19700329T020000", "+0000", "+0000"),
     ));
     ical_cal.push(CalScale::new("GREGORIAN"));
 
-    let mut ical_event =
-      ics::Event::new(&event.id, to_timestamp(event.timestamp));
-    ical_event.push(DtStart::new(to_timestamp(event.start)));
-    ical_event.push(DtEnd::new(to_timestamp(event.end)));
-    ical_event.push(LastModified::new(to_timestamp(event.modified_at)));
-    ical_event.push(Created::new(to_timestamp(event.created_at)));
+    // the years the events of each timezone span, those of recurring
+    // events reaching further
+    let mut zones: Vec<(chrono_tz::Tz, RangeInclusive<i32>)> = vec![];
+    for event in events {
+      let tz = match event_timezone(event) {
+        Some(tz) => tz,
+        None => continue,
+      };
+      let first = event.start.with_timezone(&tz).year();
+      let mut last = event.end.with_timezone(&tz).year();
+      if event.rrule.is_some() {
+        last = last.max(first + RECURRING_TIMEZONE_YEARS);
+      }
 
-    ical_event.push(Summary::new(&event.title));
-    if let Some(desc) = &event.description {
-      ical_event.push(Description::new(desc));
+      match zones.iter_mut().find(|(zone, _)| *zone == tz) {
+        Some((_, years)) => {
+          *years = first.min(*years.start())..=last.max(*years.end())
+        }
+        None => zones.push((tz, first..=last)),
+      }
     }
 
-    ical_cal.add_event(ical_event);
+    // the actual offset changes, for the clients not looking IANA
+    // timezones up by their name
+    for (tz, years) in zones {
+      let mut vtimezone: Option<TimeZone> = None;
+      for transition in timezone::transitions(tz, years) {
+        let onset = transition.onset.format("%Y%m%dT%H%M%S").to_string();
+        let from = to_utc_offset(transition.from);
+        let to = to_utc_offset(transition.to);
+        match (vtimezone.as_mut(), transition.daylight) {
+          (None, false) => {
+            let standard = Standard::new(onset, from, to);
+            vtimezone = Some(TimeZone::standard(tz.name(), standard));
+          }
+          (None, true) => {
+            let daylight = Daylight::new(onset, from, to);
+            vtimezone = Some(TimeZone::daylight(tz.name(), daylight));
+          }
+          (Some(vtimezone), false) => {
+            vtimezone.add_standard(Standard::new(onset, from, to))
+          }
+          (Some(vtimezone), true) => {
+            vtimezone.add_daylight(Daylight::new(onset, from, to))
+          }
+        }
+      }
+
+      if let Some(vtimezone) = vtimezone {
+        ical_cal.add_timezone(vtimezone);
+      }
+    }
 
-    Ok(ical_cal.to_string())
+    for event in events {
+      ical_cal.add_event(to_ical_event(event));
+    }
+
+    Ok(fold_lines(&ical_cal.to_string()))
+  }
+
+  // a whole calendar for exporting the events, if there are any
+  pub fn generate_calendar(&self, events: &[Event]) -> Option<String> {
+    if events.is_empty() {
+      return None;
+    }
+
+    match self.generate_many(events) {
+      Ok(content) => Some(content),
+      Err(e) => {
+        log::error!("Failed generating calendar: {e:#}");
+        None
+      }
+    }
   }
 
-  pub fn parse(&self, calendar_name: &str, content: &str) -> Result<Event> {
+  pub fn parse(
+    &self,
+    calendar_name: &str,
+    content: &str,
+  ) -> Result<Vec<Event>> {
     use ical::parser::ical::IcalParser;
 
-    let ical_cal = IcalParser::new(content.as_bytes())
-      .next()
-      .ok_or_else(|| anyhow!("ics file contains only no calendar"))??;
+    // folded lines (continued by a leading space or tab) are joined
+    // by the line reader of the parser before reading the properties
+    let mut events = vec![];
+    for ical_cal in IcalParser::new(content.as_bytes()) {
+      let ical_cal = ical_cal?;
+      let timezones: Vec<_> = ical_cal
+        .timezones
+        .iter()
+        .filter_map(VTimeZone::from_ical)
+        .collect();
 
-    ensure!(!ical_cal.events.is_empty(), "ics file contains no events");
-    ensure!(
-      ical_cal.events.len() == 1,
-      "ics file contains more than one events"
-    );
+      for ical_event in ical_cal.events {
+        events.push(parse_event(calendar_name, ical_event, &timezones)?);
+      }
+    }
+
+    // overrides share the UID of their series, they are told apart by
+    // the id of the occurrence they replace
+    for event in &mut events {
+      if let Some(recurrence_id) = event.recurrence_id {
+        event.id = recurrence::occurrence_id(&event.id, &recurrence_id);
+      }
+    }
+
+    ensure!(!events.is_empty(), "ics file contains no events");
 
-    let ical_event = ical_cal.events.into_iter().next().unwrap();
-    let mut event = EventBuilder::default();
+    // once per file rather than for every event having them
+    let unsupported: BTreeSet<&str> = events
+      .iter()
+      .flat_map(|event| &event.extra_properties)
+      .filter_map(|line| line.split([':', ';']).next())
+      .collect();
+    if !unsupported.is_empty() {
+      let names: Vec<_> = unsupported.into_iter().collect();
+      log::warn!("Keeping unsupported properties {} as is", names.join(", "));
+    }
 
-    let value = |p: Property| -> Result<String> {
-      p.value
-        .ok_or_else(|| anyhow!("property {} doesn't have value", &p.name))
+    Ok(events)
+  }
+}
+
+fn to_ical_event(event: &Event) -> ics::Event<'_> {
+  use ics::{properties::*, *};
+
+  // an override is stored under the UID of its series
+  let uid = match event.recurrence_id {
+    Some(_) => recurrence::split_occurrence_id(&event.id)
+      .map(|(series_id, _)| series_id)
+      .unwrap_or_else(|| event.id.clone()),
+    None => event.id.clone(),
+  };
+
+  let mut ical_event = ics::Event::new(uid, to_timestamp(event.timestamp));
+  if event.all_day {
+    let mut dtstart = DtStart::new(to_date(event.start));
+    dtstart.add(Parameter::new("VALUE", "DATE"));
+    ical_event.push(dtstart);
+    let mut dtend = DtEnd::new(to_date(event.end));
+    dtend.add(Parameter::new("VALUE", "DATE"));
+    ical_event.push(dtend);
+  } else if let Some(tz) = event_timezone(event) {
+    let local = |t: DateTime<FixedOffset>| {
+      t.with_timezone(&tz).format("%Y%m%dT%H%M%S").to_string()
+    };
+    let mut dtstart = DtStart::new(local(event.start));
+    dtstart.add(Parameter::new("TZID", tz.name()));
+    ical_event.push(dtstart);
+    let mut dtend = DtEnd::new(local(event.end));
+    dtend.add(Parameter::new("TZID", tz.name()));
+    ical_event.push(dtend);
+  } else {
+    ical_event.push(DtStart::new(to_timestamp(event.start)));
+    ical_event.push(DtEnd::new(to_timestamp(event.end)));
+  }
+  ical_event.push(LastModified::new(to_timestamp(event.modified_at)));
+  ical_event.push(Created::new(to_timestamp(event.created_at)));
+  ical_event.push(Sequence::new(event.sequence.to_string()));
+
+  ical_event.push(Summary::new(escape_text(&event.title)));
+  if let Some(color) = event.color {
+    ical_event.push(Property::new("COLOR", to_color_hex(color)));
+  }
+  if let Some(desc) = &event.description {
+    ical_event.push(Description::new(escape_text(desc)));
+  }
+  if let Some(location) = &event.location {
+    ical_event.push(Location::new(escape_text(location)));
+  }
+  if let Some(status) = event.status {
+    ical_event.push(Property::new("STATUS", status.to_ical()));
+  }
+  if let Some(priority) = event.priority {
+    ical_event.push(Property::new("PRIORITY", priority.to_string()));
+  }
+  if event.transparent {
+    ical_event.push(Property::new("TRANSP", "TRANSPARENT"));
+  }
+  if !event.categories.is_empty() {
+    let categories: Vec<_> =
+      event.categories.iter().map(|c| escape_text(c)).collect();
+    ical_event.push(Property::new("CATEGORIES", categories.join(",")));
+  }
+  if let Some(rrule) = &event.rrule {
+    ical_event.push(RRule::new(rrule));
+  }
+  for reminder in &event.reminders {
+    let trigger = Trigger::new(to_duration(reminder.offset));
+    let description = reminder.description.as_ref().unwrap_or(&event.title);
+    let description = Description::new(escape_text(description));
+    ical_event.add_alarm(Alarm::display(trigger, description));
+  }
+  for lines in &event.extra_alarms {
+    match to_alarm(lines) {
+      Some(alarm) => ical_event.add_alarm(alarm),
+      None => log::warn!("Dropping malformed alarm {lines:?}"),
+    }
+  }
+  if let Some(recurrence_id) = event.recurrence_id {
+    // the original start of the occurrence, in the form of DTSTART
+    let (value, param) = if event.all_day {
+      (
+        to_date(recurrence_id),
+        Some(Parameter::new("VALUE", "DATE")),
+      )
+    } else if let Some(tz) = event_timezone(event) {
+      let local = recurrence_id.with_timezone(&tz).format("%Y%m%dT%H%M%S");
+      (local.to_string(), Some(Parameter::new("TZID", tz.name())))
+    } else {
+      (to_timestamp(recurrence_id), None)
     };
-    let parse_time = |p: Property| -> Result<DateTime<Utc>> {
-      let s = value(p.clone())?;
-      let tzid = p.params.and_then(|params| {
-        params.into_iter().find_map(|(n, v)| {
-          (n == "TZID")
-            .then_some(())
-            .and_then(|_| v.into_iter().next())
-        })
-      });
-      from_timestamp(&s, tzid.as_deref())
+
+    let mut property = Property::new("RECURRENCE-ID", value);
+    if let Some(param) = param {
+      property.add(param);
+    }
+    ical_event.push(property);
+  }
+  if !event.exdates.is_empty() {
+    let format = if event.all_day {
+      to_date::<FixedOffset>
+    } else {
+      to_timestamp::<FixedOffset>
     };
+    let exdates: Vec<_> = event.exdates.iter().map(|t| format(*t)).collect();
+    let mut exdate = ExDate::new(exdates.join(","));
+    if event.all_day {
+      exdate.add(Parameter::new("VALUE", "DATE"));
+    }
+    ical_event.push(exdate);
+  }
+  for line in &event.extra_properties {
+    match from_content_line(line) {
+      Some(property) => ical_event.push(property),
+      None => log::warn!("Dropping malformed property {line}"),
+    }
+  }
+
+  ical_event
+}
+
+// NAME;PARAM=VALUE:value, with the value kept escaped as in the file
+fn to_content_line(p: &Property) -> String {
+  let mut line = p.name.clone();
+  for (name, values) in p.params.iter().flatten() {
+    let values: Vec<String> = values
+      .iter()
+      .map(|v| {
+        let special = v.contains([':', ';', ',']);
+        if special && !v.starts_with('"') {
+          format!("\"{v}\"")
+        } else {
+          v.clone()
+        }
+      })
+      .collect();
+    line.push_str(&format!(";{name}={}", values.join(",")));
+  }
+  line.push(':');
+  line.push_str(p.value.as_deref().unwrap_or_default());
+  line
+}
 
-    event.calendar(calendar_name);
+fn from_content_line(line: &str) -> Option<ics::Property<'_>> {
+  use ics::{Parameter, Property};
 
-    let mut start = None;
+  let (name, params, value) = split_content_line(line)?;
+  let mut property = Property::new(name, value);
+  for (name, value) in params {
+    property.add(Parameter::new(name, value));
+  }
+  Some(property)
+}
 
-    for p in ical_event.properties {
-      match p.name.as_str() {
-        "UID" => event.id(value(p)?),
-        "SUMMARY" => event.title(value(p)?),
-        "DTSTAMP" => event.created_at(parse_time(p)?),
-        "DTSTART" => {
-          start = Some(parse_time(p)?);
-          event.start(start.unwrap())
+// the name, parameters and value of a content line
+fn split_content_line(line: &str) -> Option<(&str, Vec<(&str, &str)>, &str)> {
+  // split on the delimiters outside of quoted parameter values
+  let mut quoted = false;
+  let mut parts = vec![];
+  let mut part_start = 0;
+  let mut value = None;
+  for (i, c) in line.char_indices() {
+    match c {
+      '"' => quoted = !quoted,
+      ';' if !quoted => {
+        parts.push(&line[part_start..i]);
+        part_start = i + 1;
+      }
+      ':' if !quoted => {
+        parts.push(&line[part_start..i]);
+        value = Some(&line[i + 1..]);
+        break;
+      }
+      _ => (),
+    }
+  }
+
+  let (name, params) = parts.split_first()?;
+  let params = params
+    .iter()
+    .map(|param| param.split_once('='))
+    .collect::<Option<_>>()?;
+  Some((*name, params, value?))
+}
+
+// an alarm kept as content lines, which must have an ACTION and a
+// TRIGGER
+fn to_alarm(lines: &[String]) -> Option<ics::Alarm<'_>> {
+  use ics::{properties::*, *};
+
+  let mut action = None;
+  let mut trigger = None;
+  let mut properties = vec![];
+  for line in lines {
+    let (name, params, value) = split_content_line(line)?;
+    match name {
+      "ACTION" => action = Some(Action::new(value)),
+      "TRIGGER" => {
+        let mut t = Trigger::new(value);
+        for (name, value) in params {
+          t.add(Parameter::new(name, value));
         }
-        "DTEND" => event.end(parse_time(p)?),
-        "DURATION" => {
-          let value = value(p)?;
-          let start =
-            start.ok_or_else(|| anyhow!("duration: start not defined yet"))?;
-          let end = start + parse_duration(&value)?;
-          event.end(end)
+        trigger = Some(t);
+      }
+      _ => properties.push(from_content_line(line)?),
+    }
+  }
+
+  let mut alarm = Alarm::new(action?, trigger?);
+  for property in properties {
+    alarm.push(property);
+  }
+  Some(alarm)
+}
+
+// the timezone the event was created in, if known
+fn event_timezone(event: &Event) -> Option<chrono_tz::Tz> {
+  event.timezone.as_deref()?.parse().ok()
+}
+
+fn parse_event(
+  calendar_name: &str,
+  ical_event: ical::parser::ical::component::IcalEvent,
+  timezones: &[VTimeZone],
+) -> Result<Event> {
+  let mut event = EventBuilder::default();
+
+  let mut reminders = vec![];
+  let mut extra_alarms = vec![];
+  for alarm in ical_event.alarms {
+    match parse_reminder(&alarm.properties) {
+      Ok(reminder) => reminders.push(reminder),
+      Err(e) => {
+        log::debug!("Keeping unsupported alarm as is: {e:?}");
+        extra_alarms
+          .push(alarm.properties.iter().map(to_content_line).collect());
+      }
+    }
+  }
+  event.reminders(reminders);
+  event.extra_alarms(extra_alarms);
+
+  let value = |p: Property| -> Result<String> {
+    p.value
+      .ok_or_else(|| anyhow!("property {} doesn't have value", &p.name))
+  };
+  let tzid = |p: &Property| -> Option<String> {
+    p.params.iter().flatten().find_map(|(n, v)| {
+      (n == "TZID").then_some(()).and_then(|_| v.first().cloned())
+    })
+  };
+  let parse_time = |p: Property| -> Result<DateTime<Utc>> {
+    let tzid = tzid(&p);
+    from_timestamp(&value(p)?, tzid.as_deref(), timezones)
+  };
+  // returns the time and whether it's a date without time
+  let parse_date_time = |p: Property| -> Result<(DateTime<FixedOffset>, bool)> {
+    if is_date_value(&p) {
+      Ok((from_date(&value(p)?)?, true))
+    } else {
+      Ok((parse_time(p)?.into(), false))
+    }
+  };
+
+  event.calendar(calendar_name);
+
+  let mut start = None;
+  let mut all_day = false;
+  let mut has_end = false;
+  let mut duration = None;
+  let mut exdates = vec![];
+  let mut categories = vec![];
+  let mut extra_properties = vec![];
+  let mut has_modified_at = false;
+
+  for p in ical_event.properties {
+    match p.name.as_str() {
+      "UID" => event.id(value(p)?),
+      "SUMMARY" => event.title(unescape_text(&value(p)?)),
+      "DESCRIPTION" => event.description(Some(unescape_text(&value(p)?))),
+      "LOCATION" => event.location(Some(unescape_text(&value(p)?))),
+      "DTSTAMP" => event.created_at(parse_time(p)?),
+      "DTSTART" => {
+        // keep the timezone to write the event back in it
+        let timezone = tzid(&p)
+          .and_then(|id| timezone::TimeZone::resolve(Some(&id), timezones))
+          .and_then(|tz| tz.iana_name())
+          .map(String::from);
+        event.timezone(timezone);
+
+        let (time, is_date) = parse_date_time(p)?;
+        start = Some(time);
+        all_day = is_date;
+        event.all_day(all_day);
+        event.start(time)
+      }
+      "DTEND" => {
+        has_end = true;
+        event.end(parse_date_time(p)?.0)
+      }
+      "DURATION" => {
+        // applied once DTSTART is known, which may come later
+        duration = Some(parse_duration(&value(p)?)?);
+        &mut event
+      }
+      "COLOR" => {
+        let value = value(p)?;
+        match parse_color(&value) {
+          Some(color) => event.color(Some(color)),
+          None => {
+            log::warn!("Ignoring unsupported color {value}");
+            &mut event
+          }
         }
-        "CREATED" => event.created_at(parse_time(p)?),
-        "LAST-MODIFIED" => event.modified_at(parse_time(p)?),
-        _ => &mut event,
-      };
+      }
+      "STATUS" => {
+        let value = value(p)?;
+        match EventStatus::from_ical(&value) {
+          Some(status) => event.status(Some(status)),
+          None => {
+            log::warn!("Ignoring unknown status {value}");
+            &mut event
+          }
+        }
+      }
+      "PRIORITY" => {
+        let value = value(p)?;
+        match value.trim().parse::<u8>() {
+          Ok(priority) if priority <= 9 => event.priority(Some(priority)),
+          _ => {
+            log::warn!("Ignoring invalid priority {value}");
+            &mut event
+          }
+        }
+      }
+      // OPAQUE by default
+      "TRANSP" => {
+        event.transparent(value(p)?.eq_ignore_ascii_case("TRANSPARENT"))
+      }
+      "CATEGORIES" => {
+        // a comma separated list, possibly spread over several lines
+        categories.extend(split_text_list(&value(p)?));
+        &mut event
+      }
+      "RRULE" => event.rrule(Some(value(p)?)),
+      "RECURRENCE-ID" => event.recurrence_id(Some(parse_date_time(p)?.0)),
+      "EXDATE" => {
+        // a comma separated list, possibly spread over several lines
+        for time in value(p.clone())?.split(',') {
+          let mut p = p.clone();
+          p.value = Some(time.trim().to_string());
+          exdates.push(parse_date_time(p)?.0);
+        }
+        &mut event
+      }
+      "CREATED" => event.created_at(parse_time(p)?),
+      "LAST-MODIFIED" => {
+        has_modified_at = true;
+        event.modified_at(parse_time(p)?)
+      }
+      "SEQUENCE" => {
+        let value = value(p)?;
+        match value.trim().parse::<u32>() {
+          Ok(sequence) => event.sequence(sequence),
+          Err(_) => {
+            log::warn!("Ignoring invalid sequence {value}");
+            &mut event
+          }
+        }
+      }
+      _ => {
+        extra_properties.push(to_content_line(&p));
+        &mut event
+      }
+    };
+  }
+
+  match (start, has_end, duration) {
+    // DTEND takes precedence over DURATION
+    (Some(start), false, Some(duration)) => event.end(start + duration),
+    // an all-day event without an end lasts for one day
+    (Some(start), false, None) if all_day => {
+      event.end(start + Duration::days(1))
     }
+    // and a timed one ends when it starts
+    (Some(start), false, None) => event.end(start),
+    _ => &mut event,
+  };
+
+  event.exdates(exdates);
+  event.categories(categories);
+  event.extra_properties(extra_properties);
 
-    Ok(event.build()?)
+  let mut event = event.build()?;
+  event.modified_at_known = has_modified_at;
+  // reminders showing the title keep following it when it changes
+  for reminder in &mut event.reminders {
+    if reminder.description.as_ref() == Some(&event.title) {
+      reminder.description = None;
+    }
   }
+  if event.end < event.start {
+    log::warn!(
+      "Event {} ends before it starts, swapping its times",
+      event.id
+    );
+    reorder_times(&mut event.start, &mut event.end);
+    event.times_swapped = true;
+  }
+
+  Ok(event)
+}
+
+fn is_date_value(p: &Property) -> bool {
+  let value_param = p.params.iter().flatten().find(|(n, _)| n == "VALUE");
+  match value_param {
+    Some((_, v)) => v.iter().any(|v| v == "DATE"),
+    None => p.value.as_ref().is_some_and(|v| v.len() == 8),
+  }
+}
+
+fn to_date<Tz: chrono::TimeZone>(time: DateTime<Tz>) -> String {
+  time.naive_local().format("%Y%m%d").to_string()
+}
+
+// dates are interpreted as the midnight in local timezone
+fn from_date(s: &str) -> Result<DateTime<FixedOffset>> {
+  let date = NaiveDate::parse_from_str(s, "%Y%m%d")?;
+  date
+    .and_hms_opt(0, 0, 0)
+    .and_then(|t| t.and_local_timezone(local_tz()).single())
+    .ok_or_else(|| anyhow!("failed to parse date {}", s))
 }
 
 fn to_timestamp<Tz: chrono::TimeZone>(time: DateTime<Tz>) -> String {
   time.naive_utc().format("%Y%m%dT%H%M%SZ").to_string()
 }
 
-fn from_timestamp(s: &str, tzid: Option<&str>) -> Result<DateTime<Utc>> {
-  use chrono_tz::Tz;
-  use std::str::FromStr;
+// e.g. +0100 or -0500
+fn to_utc_offset(offset: FixedOffset) -> String {
+  let secs = offset.local_minus_utc();
+  let sign = if secs < 0 { '-' } else { '+' };
+  let mins = secs.abs() / 60;
+  format!("{sign}{:02}{:02}", mins / 60, mins % 60)
+}
 
+fn from_timestamp(
+  s: &str,
+  tzid: Option<&str>,
+  timezones: &[VTimeZone],
+) -> Result<DateTime<Utc>> {
   if let Ok(t) = NaiveDateTime::parse_from_str(s, "%Y%m%dT%H%M%SZ") {
     return Ok(t.and_utc());
   }
 
-  if let Some(tz) = tzid.and_then(|tz| Tz::from_str(tz).ok()) {
-    if let Ok(t) = NaiveDateTime::parse_from_str(s, "%Y%m%dT%H%M%S") {
-      return Ok(t.and_local_timezone(tz).unwrap().with_timezone(&Utc));
-    }
-  }
+  let t = NaiveDateTime::parse_from_str(s, "%Y%m%dT%H%M%S")
+    .map_err(|_| anyhow!("failed to parse timestamp {}", s))?;
+
+  let tz = timezone::TimeZone::resolve(tzid, timezones).unwrap_or_else(|| {
+    log::warn!("Unknown timezone {tzid:?}, using the local timezone");
+    timezone::TimeZone::Floating
+  });
 
-  bail!("failed to parse timestamp {}", s)
+  let time = tz
+    .localize(t)
+    .ok_or_else(|| anyhow!("invalid local time {} in {:?}", s, tzid))?;
+  Ok(time.with_timezone(&Utc))
 }
 
+// RFC 5545 3.3.6 duration, e.g. -PT15M or P1DT2H
 fn parse_duration(s: &str) -> Result<Duration> {
-  let reg = regex::Regex::new(r"PT((?P<h>\d+)H)?((?P<m>\d+)M)?")?;
+  let reg = regex::Regex::new(
+    r"^(?P<sign>[+-])?P((?P<w>\d+)W)?((?P<d>\d+)D)?(T((?P<h>\d+)H)?((?P<m>\d+)M)?((?P<s>\d+)S)?)?$",
+  )?;
   let cap = reg
-    .captures(s)
+    .captures(s.trim())
     .ok_or_else(|| anyhow!("Invalid duration parsed {}", s))?;
 
+  let units: [(&str, fn(i64) -> Duration); 5] = [
+    ("w", Duration::weeks),
+    ("d", Duration::days),
+    ("h", Duration::hours),
+    ("m", Duration::minutes),
+    ("s", Duration::seconds),
+  ];
+
   let mut dur = Duration::zero();
-  if let Some(m) = cap.name("h") {
-    let hours = m.as_str().parse::<i64>()?;
-    dur += Duration::hours(hours);
+  for (name, unit) in units {
+    if let Some(m) = cap.name(name) {
+      dur += unit(m.as_str().parse::<i64>()?);
+    }
   }
-  if let Some(m) = cap.name("m") {
-    let mins = m.as_str().parse::<i64>()?;
-    dur += Duration::minutes(mins);
+
+  if cap.name("sign").is_some_and(|m| m.as_str() == "-") {
+    dur = -dur;
   }
 
   Ok(dur)
 }
+
+fn to_duration(dur: Duration) -> String {
+  let sign = if dur < Duration::zero() { "-" } else { "" };
+  let secs = dur.num_seconds().abs();
+  let (days, secs) = (secs / 86400, secs % 86400);
+  let (hours, mins, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+  let mut out = format!("{sign}P");
+  if days > 0 {
+    out.push_str(&format!("{days}D"));
+  }
+  if hours > 0 || mins > 0 || secs > 0 || days == 0 {
+    out.push('T');
+    if hours > 0 {
+      out.push_str(&format!("{hours}H"));
+    }
+    if mins > 0 {
+      out.push_str(&format!("{mins}M"));
+    }
+    if secs > 0 || (hours == 0 && mins == 0) {
+      out.push_str(&format!("{secs}S"));
+    }
+  }
+  out
+}
+
+// the alarms shown as reminders: displayed ones relative to the start
+fn parse_reminder(properties: &[Property]) -> Result<Reminder> {
+  let property = |name: &str| properties.iter().find(|p| p.name == name);
+
+  let action = property("ACTION").and_then(|p| p.value.as_deref());
+  if let Some(action) = action {
+    ensure!(action.eq_ignore_ascii_case("DISPLAY"), "{action} alarm");
+  }
+
+  let trigger = property("TRIGGER")
+    .ok_or_else(|| anyhow!("alarm without trigger"))?
+    .clone();
+  let description = property("DESCRIPTION")
+    .and_then(|p| p.value.as_deref())
+    .map(unescape_text);
+
+  Ok(Reminder {
+    offset: parse_trigger(trigger)?,
+    description,
+  })
+}
+
+// only triggers relative to the start of the event are supported
+fn parse_trigger(p: Property) -> Result<Duration> {
+  let params = p.params.unwrap_or_default();
+  let param = |name: &str| {
+    params
+      .iter()
+      .find(|(n, _)| n == name)
+      .and_then(|(_, v)| v.first().cloned())
+  };
+
+  let value_type = param("VALUE").unwrap_or_else(|| "DURATION".into());
+  ensure!(value_type == "DURATION", "absolute alarm trigger");
+
+  let related = param("RELATED").unwrap_or_else(|| "START".into());
+  ensure!(related == "START", "alarm trigger relative to the end");
+
+  let value = p.value.ok_or_else(|| anyhow!("trigger without value"))?;
+  parse_duration(&value)
+}
+
+// a css color name or a hex color (#rrggbb)
+fn parse_color(s: &str) -> Option<[f32; 3]> {
+  const NAMED_COLORS: [(&str, &str); 16] = [
+    ("black", "000000"),
+    ("silver", "c0c0c0"),
+    ("gray", "808080"),
+    ("white", "ffffff"),
+    ("maroon", "800000"),
+    ("red", "ff0000"),
+    ("purple", "800080"),
+    ("fuchsia", "ff00ff"),
+    ("green", "008000"),
+    ("lime", "00ff00"),
+    ("olive", "808000"),
+    ("yellow", "ffff00"),
+    ("navy", "000080"),
+    ("blue", "0000ff"),
+    ("teal", "008080"),
+    ("aqua", "00ffff"),
+  ];
+
+  let s = s.trim().to_lowercase();
+  let hex = match s.strip_prefix('#') {
+    Some(hex) => hex,
+    None => NAMED_COLORS.iter().find(|(n, _)| *n == s)?.1,
+  };
+
+  if hex.len() != 6 {
+    return None;
+  }
+
+  let channel = |i: usize| {
+    let v = u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()?;
+    Some(v as f32 / 255.0)
+  };
+
+  Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+fn to_color_hex([r, g, b]: [f32; 3]) -> String {
+  let to_u8 = |x: f32| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+  format!("#{:02x}{:02x}{:02x}", to_u8(r), to_u8(g), to_u8(b))
+}
+
+// escape a TEXT value (RFC 5545 3.3.11)
+fn escape_text(s: &str) -> String {
+  let mut out = String::with_capacity(s.len());
+  for c in s.chars() {
+    match c {
+      '\\' => out.push_str("\\\\"),
+      ';' => out.push_str("\\;"),
+      ',' => out.push_str("\\,"),
+      '\n' => out.push_str("\\n"),
+      '\r' => (),
+      c => out.push(c),
+    }
+  }
+  out
+}
+
+// split a list of TEXT values on the commas that are not escaped
+fn split_text_list(s: &str) -> Vec<String> {
+  let mut items = vec![];
+  let mut item = String::new();
+  let mut chars = s.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      '\\' => {
+        item.push(c);
+        item.extend(chars.next());
+      }
+      ',' => items.push(std::mem::take(&mut item)),
+      c => item.push(c),
+    }
+  }
+  items.push(item);
+
+  items
+    .iter()
+    .map(|item| unescape_text(item.trim()))
+    .filter(|item| !item.is_empty())
+    .collect()
+}
+
+fn unescape_text(s: &str) -> String {
+  let mut out = String::with_capacity(s.len());
+  let mut chars = s.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      out.push(c);
+      continue;
+    }
+
+    match chars.next() {
+      Some('n') | Some('N') => out.push('\n'),
+      Some(c) => out.push(c),
+      None => out.push('\\'),
+    }
+  }
+  out
+}
+
+// fold content lines longer than 75 octets (RFC 5545 3.1)
+fn fold_lines(s: &str) -> String {
+  const MAX_OCTETS: usize = 75;
+
+  let mut out = String::with_capacity(s.len());
+  for line in s.split_inclusive("\r\n") {
+    let (content, ending) = match line.strip_suffix("\r\n") {
+      Some(content) => (content, "\r\n"),
+      None => (line, ""),
+    };
+
+    let mut octets = 0;
+    for c in content.chars() {
+      if octets + c.len_utf8() > MAX_OCTETS {
+        out.push_str("\r\n ");
+        // the leading space counts towards the limit
+        octets = 1;
+      }
+      octets += c.len_utf8();
+      out.push(c);
+    }
+    out.push_str(ending);
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // a calendar as written by another app, one content line per item
+  fn calendar(lines: &[&str]) -> String {
+    let mut content = String::from(
+      "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Other//App//EN\r\n",
+    );
+    for line in lines {
+      content.push_str(line);
+      content.push_str("\r\n");
+    }
+    content.push_str("END:VCALENDAR\r\n");
+    content
+  }
+
+  #[test]
+  fn multiline_descriptions_roundtrip() {
+    let content = calendar(&[
+      "BEGIN:VEVENT",
+      "UID:notes@example.com",
+      "DTSTAMP:20240301T080000Z",
+      "DTSTART:20240301T090000Z",
+      "DTEND:20240301T100000Z",
+      "SUMMARY:Planning",
+      "DESCRIPTION:Agenda:\\n- budget\\, Q3\\; Q4\\n- hiring",
+      "END:VEVENT",
+    ]);
+
+    let events = ICal.parse("test", &content).unwrap();
+    let description = "Agenda:\n- budget, Q3; Q4\n- hiring";
+    assert_eq!(events[0].description.as_deref(), Some(description));
+
+    let generated = ICal.generate(&events[0]).unwrap();
+    assert!(generated.contains(
+      "\r\nDESCRIPTION:Agenda:\\n- budget\\, Q3\\; Q4\\n- hiring\r\n"
+    ));
+    let reparsed = ICal.parse("test", &generated).unwrap();
+    assert_eq!(reparsed[0].description.as_deref(), Some(description));
+  }
+
+  #[test]
+  fn special_characters_roundtrip() {
+    let title = "Meeting; room A, bldg 2\nnote";
+    assert_eq!(escape_text(title), "Meeting\\; room A\\, bldg 2\\nnote");
+    assert_eq!(unescape_text(&escape_text(title)), title);
+
+    let start = DateTime::parse_from_rfc3339("2024-03-01T09:00:00Z").unwrap();
+    let event = EventBuilder::default()
+      .id("escaped@example.com")
+      .calendar("test")
+      .title(title)
+      .start(start)
+      .end(start + Duration::hours(1))
+      .build()
+      .unwrap();
+
+    let generated = ICal.generate(&event).unwrap();
+    let parsed = ICal.parse("test", &generated).unwrap();
+    assert_eq!(parsed[0].title, title);
+  }
+
+  #[test]
+  fn times_keep_their_timezone() {
+    let content = calendar(&[
+      "BEGIN:VEVENT",
+      "UID:new-york@example.com",
+      "DTSTAMP:20240301T080000Z",
+      // daylight saving time started on March 10 in New York
+      "DTSTART;TZID=America/New_York:20240315T090000",
+      "DTEND;TZID=America/New_York:20240315T100000",
+      "SUMMARY:Standup",
+      "END:VEVENT",
+    ]);
+
+    let events = ICal.parse("test", &content).unwrap();
+    let start = DateTime::parse_from_rfc3339("2024-03-15T13:00:00Z").unwrap();
+    assert_eq!(events[0].start, start);
+    assert_eq!(events[0].end, start + Duration::hours(1));
+    assert_eq!(events[0].timezone.as_deref(), Some("America/New_York"));
+
+    let generated = ICal.generate(&events[0]).unwrap();
+    for line in [
+      "DTSTART;TZID=America/New_York:20240315T090000",
+      "DTEND;TZID=America/New_York:20240315T100000",
+    ] {
+      assert!(generated.contains(&format!("\r\n{line}\r\n")), "{line}");
+    }
+  }
+
+  #[test]
+  fn durations_give_the_end() {
+    let content = calendar(&[
+      "BEGIN:VEVENT",
+      "UID:duration@example.com",
+      "DTSTAMP:20240301T080000Z",
+      "DTSTART:20240301T090000Z",
+      "DURATION:PT45M",
+      "SUMMARY:Review",
+      "END:VEVENT",
+    ]);
+
+    let events = ICal.parse("test", &content).unwrap();
+    assert_eq!(events[0].end - events[0].start, Duration::minutes(45));
+
+    // written back with DTEND
+    let generated = ICal.generate(&events[0]).unwrap();
+    assert!(generated.contains("\r\nDTEND:20240301T094500Z\r\n"));
+    assert!(!generated.contains("DURATION"));
+  }
+
+  #[test]
+  fn reminders_keep_their_description() {
+    let content = calendar(&[
+      "BEGIN:VEVENT",
+      "UID:alarm@example.com",
+      "DTSTAMP:20240301T080000Z",
+      "DTSTART:20240301T090000Z",
+      "DTEND:20240301T100000Z",
+      "SUMMARY:Dentist",
+      "BEGIN:VALARM",
+      "ACTION:DISPLAY",
+      "TRIGGER:-PT30M",
+      "DESCRIPTION:Bring the insurance card\\, please",
+      "END:VALARM",
+      "BEGIN:VALARM",
+      "ACTION:DISPLAY",
+      "TRIGGER:-PT5M",
+      "DESCRIPTION:Dentist",
+      "END:VALARM",
+      "END:VEVENT",
+    ]);
+
+    let mut event = ICal.parse("test", &content).unwrap().remove(0);
+    let reminders = vec![
+      Reminder {
+        offset: Duration::minutes(-30),
+        description: Some("Bring the insurance card, please".into()),
+      },
+      // the title, which follows renaming
+      Reminder {
+        offset: Duration::minutes(-5),
+        description: None,
+      },
+    ];
+    assert_eq!(event.reminders, reminders);
+
+    event.title = "Orthodontist".into();
+    let generated = ICal.generate(&event).unwrap();
+    assert!(
+      generated.contains("DESCRIPTION:Bring the insurance card\\, please")
+    );
+    assert!(generated.contains("DESCRIPTION:Orthodontist"));
+
+    let parsed = ICal.parse("test", &generated).unwrap().remove(0);
+    assert_eq!(parsed.reminders, reminders);
+  }
+
+  #[test]
+  fn folded_lines_are_unfolded() {
+    let content = calendar(&[
+      "BEGIN:VEVENT",
+      "UID:folded@example.com",
+      "DTSTAMP:20240301T080000Z",
+      "DTSTART:20240301T090000Z",
+      "DTEND:20240301T100000Z",
+      "SUMMARY:A rather long sum",
+      " mary folded with a space",
+      "DESCRIPTION:First line\\nsecond",
+      "\t line folded with a tab",
+      "END:VEVENT",
+    ]);
+
+    let events = ICal.parse("test", &content).unwrap();
+    assert_eq!(events[0].title, "A rather long summary folded with a space");
+    assert_eq!(
+      events[0].description.as_deref(),
+      Some("First line\nsecond line folded with a tab")
+    );
+  }
+
+  #[test]
+  fn long_summaries_roundtrip_folded() {
+    let title = "Quarterly planning with the whole team, ünïcode included, "
+      .repeat(4)
+      .trim_end()
+      .to_string();
+    assert!(title.len() > 200);
+    let start = DateTime::parse_from_rfc3339("2024-03-01T09:00:00Z").unwrap();
+    let event = EventBuilder::default()
+      .id("folded@example.com")
+      .calendar("test")
+      .title(title.as_str())
+      .start(start)
+      .end(start + Duration::hours(1))
+      .timestamp(start)
+      .created_at(start)
+      .modified_at(start)
+      .build()
+      .unwrap();
+
+    let generated = ICal.generate(&event).unwrap();
+    assert!(generated.lines().all(|line| line.len() <= 75));
+    assert!(generated.contains("\r\n "));
+
+    let mut parsed = ICal.parse("test", &generated).unwrap().remove(0);
+    assert_eq!(parsed.title, title);
+    // DTSTAMP is regenerated on every save
+    parsed.timestamp = event.timestamp;
+    assert_eq!(parsed, event);
+  }
+
+  #[test]
+  fn unknown_properties_roundtrip() {
+    let travel = "X-APPLE-TRAVEL-DURATION;VALUE=DURATION:PT30M";
+    let content = calendar(&[
+      "BEGIN:VEVENT",
+      "UID:travel@example.com",
+      "DTSTAMP:20240301T080000Z",
+      "DTSTART:20240301T090000Z",
+      "DTEND:20240301T100000Z",
+      "SUMMARY:Meeting",
+      travel,
+      "END:VEVENT",
+    ]);
+
+    let events = ICal.parse("test", &content).unwrap();
+    assert_eq!(events[0].extra_properties, vec![travel.to_string()]);
+
+    let generated = ICal.generate(&events[0]).unwrap();
+    assert!(generated.contains(&format!("\r\n{travel}\r\n")));
+
+    let reparsed = ICal.parse("test", &generated).unwrap();
+    assert_eq!(reparsed[0].extra_properties, events[0].extra_properties);
+  }
+
+  #[test]
+  fn generated_calendars_stand_alone() {
+    let start = DateTime::parse_from_rfc3339("2024-03-01T09:00:00Z").unwrap();
+    let event = EventBuilder::default()
+      .id("standalone@example.com")
+      .calendar("test")
+      .title("Meeting")
+      .start(start)
+      .end(start + Duration::hours(1))
+      .build()
+      .unwrap();
+
+    let generated = ICal.generate(&event).unwrap();
+    let prodid = format!("PRODID:{PRODID}");
+    for line in ["VERSION:2.0", prodid.as_str(), "CALSCALE:GREGORIAN"] {
+      assert!(generated.contains(&format!("\r\n{line}\r\n")), "{line}");
+    }
+
+    let parsed = ICal.parse("test", &generated).unwrap();
+    assert_eq!(parsed[0].title, event.title);
+  }
+}
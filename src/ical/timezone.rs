@@ -0,0 +1,313 @@
+use std::{ops::RangeInclusive, str::FromStr};
+
+use anyhow::bail;
+use chrono::{
+  DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, Offset,
+  TimeZone as _, Weekday,
+};
+use chrono_tz::OffsetComponents;
+use ical::parser::ical::component::IcalTimeZone;
+
+use crate::util::{anyhow, local_tz, Result};
+
+// A timezone referred to by a TZID parameter: either an IANA name
+// known to chrono-tz, or one only defined by a VTIMEZONE in the file.
+pub(super) enum TimeZone<'a> {
+  Iana(chrono_tz::Tz),
+  Defined(&'a VTimeZone),
+  // floating times are in whatever timezone the viewer is in
+  Floating,
+}
+
+// the part of a VTIMEZONE component needed to convert local times
+pub(super) struct VTimeZone {
+  id: String,
+  observances: Vec<Observance>,
+}
+
+// a STANDARD or DAYLIGHT sub-component
+struct Observance {
+  onset: NaiveDateTime,
+  // only yearly rules with BYMONTH and BYDAY are supported, which is
+  // how DST transitions are described in practice
+  rule: Option<(u32, i32, Weekday)>,
+  offset: FixedOffset,
+}
+
+impl<'a> TimeZone<'a> {
+  pub(super) fn resolve(
+    tzid: Option<&str>,
+    definitions: &'a [VTimeZone],
+  ) -> Option<Self> {
+    let tzid = match tzid {
+      Some(tzid) => tzid,
+      None => return Some(Self::Floating),
+    };
+
+    if let Some(tz) = iana_timezone(tzid) {
+      return Some(Self::Iana(tz));
+    }
+
+    definitions
+      .iter()
+      .find(|def| def.id == tzid)
+      .map(Self::Defined)
+  }
+
+  // the IANA name to keep when saving the event
+  pub(super) fn iana_name(&self) -> Option<&'static str> {
+    match self {
+      Self::Iana(tz) => Some(tz.name()),
+      _ => None,
+    }
+  }
+
+  pub(super) fn localize(
+    &self,
+    t: NaiveDateTime,
+  ) -> Option<DateTime<FixedOffset>> {
+    match self {
+      Self::Iana(tz) => {
+        // times skipped by a DST transition are moved past the gap
+        let time = tz.from_local_datetime(&t).earliest().or_else(|| {
+          tz.from_local_datetime(&(t + Duration::hours(1))).earliest()
+        })?;
+        Some(time.fixed_offset())
+      }
+      Self::Defined(def) => {
+        let offset = def.offset_at(t)?;
+        offset.from_local_datetime(&t).single()
+      }
+      Self::Floating => local_tz().from_local_datetime(&t).single(),
+    }
+  }
+}
+
+impl VTimeZone {
+  pub(super) fn from_ical(tz: &IcalTimeZone) -> Option<Self> {
+    let id = property(&tz.properties, "TZID")?;
+
+    let observances = tz
+      .transitions
+      .iter()
+      .filter_map(|transition| {
+        let props = &transition.properties;
+        let onset = NaiveDateTime::parse_from_str(
+          &property(props, "DTSTART")?,
+          "%Y%m%dT%H%M%S",
+        )
+        .ok()?;
+        let offset = parse_offset(&property(props, "TZOFFSETTO")?).ok()?;
+        let rule = property(props, "RRULE").and_then(|r| parse_yearly_rule(&r));
+        Some(Observance {
+          onset,
+          rule,
+          offset,
+        })
+      })
+      .collect::<Vec<_>>();
+
+    if observances.is_empty() {
+      log::warn!("Ignoring timezone {id} without observances");
+      return None;
+    }
+
+    Some(Self { id, observances })
+  }
+
+  // the offset of the observance that started last before the time
+  fn offset_at(&self, t: NaiveDateTime) -> Option<FixedOffset> {
+    let latest_onset = |obs: &Observance| -> Option<NaiveDateTime> {
+      let (month, n, weekday) = match obs.rule {
+        Some(rule) => rule,
+        None => return (obs.onset <= t).then_some(obs.onset),
+      };
+
+      [t.year(), t.year() - 1]
+        .into_iter()
+        .filter_map(|year| {
+          let date = nth_weekday(year, month, n, weekday)?;
+          Some(date.and_time(obs.onset.time()))
+        })
+        .find(|onset| *onset <= t && *onset >= obs.onset)
+    };
+
+    let latest = self
+      .observances
+      .iter()
+      .filter_map(|obs| Some((latest_onset(obs)?, obs.offset)))
+      .max_by_key(|(onset, _)| *onset);
+
+    match latest {
+      Some((_, offset)) => Some(offset),
+      // before any onset, assume the earliest observance
+      None => self
+        .observances
+        .iter()
+        .min_by_key(|obs| obs.onset)
+        .map(|obs| obs.offset),
+    }
+  }
+}
+
+// an offset change of an IANA timezone, written as a STANDARD or
+// DAYLIGHT sub-component
+pub(super) struct Transition {
+  // the local time it happens at, in the offset before it
+  pub onset: NaiveDateTime,
+  pub from: FixedOffset,
+  pub to: FixedOffset,
+  pub daylight: bool,
+}
+
+// The offsets of the timezone throughout the years, starting with the
+// one in effect at their beginning. chrono-tz doesn't expose its
+// transitions, they are searched for day by day, then to the minute.
+pub(super) fn transitions(
+  tz: chrono_tz::Tz,
+  years: RangeInclusive<i32>,
+) -> Vec<Transition> {
+  let new_year = |year| {
+    NaiveDate::from_ymd_opt(year, 1, 1).and_then(|d| d.and_hms_opt(0, 0, 0))
+  };
+  let (mut at, end) =
+    match (new_year(*years.start()), new_year(years.end() + 1)) {
+      (Some(start), Some(end)) => (start, end),
+      _ => return vec![],
+    };
+  // the utc offset and whether it's daylight saving time
+  let offset_at = |utc: NaiveDateTime| {
+    let offset = tz.offset_from_utc_datetime(&utc);
+    (offset.fix(), !offset.dst_offset().is_zero())
+  };
+
+  let mut current = offset_at(at);
+  let mut transitions = vec![Transition {
+    onset: at + current.0,
+    from: current.0,
+    to: current.0,
+    daylight: current.1,
+  }];
+
+  while at < end {
+    let next_day = at + Duration::days(1);
+    if offset_at(next_day) != current {
+      let (mut before, mut after) = (0, 24 * 60);
+      while after - before > 1 {
+        let mid = (before + after) / 2;
+        if offset_at(at + Duration::minutes(mid)) == current {
+          before = mid;
+        } else {
+          after = mid;
+        }
+      }
+
+      let changed_at = at + Duration::minutes(after);
+      let next = offset_at(changed_at);
+      transitions.push(Transition {
+        onset: changed_at + current.0,
+        from: current.0,
+        to: next.0,
+        daylight: next.1,
+      });
+      current = next;
+    }
+    at = next_day;
+  }
+
+  transitions
+}
+
+// Some clients prefix the IANA name, e.g.
+// "/mozilla.org/20050126_1/America/New_York".
+fn iana_timezone(tzid: &str) -> Option<chrono_tz::Tz> {
+  let tzid = tzid.trim_matches('"');
+  if let Ok(tz) = chrono_tz::Tz::from_str(tzid) {
+    return Some(tz);
+  }
+
+  let segments: Vec<_> = tzid.split('/').collect();
+  (1..segments.len())
+    .find_map(|i| chrono_tz::Tz::from_str(&segments[i..].join("/")).ok())
+}
+
+fn property(props: &[ical::property::Property], name: &str) -> Option<String> {
+  props
+    .iter()
+    .find(|p| p.name == name)
+    .and_then(|p| p.value.clone())
+}
+
+// e.g. +0100, -0500 or +053000
+fn parse_offset(s: &str) -> Result<FixedOffset> {
+  let (sign, digits) = match s.split_at(1) {
+    ("+", digits) => (1, digits),
+    ("-", digits) => (-1, digits),
+    _ => bail!("invalid utc offset {s}"),
+  };
+
+  let field = |i: usize| -> Result<i32> {
+    Ok(digits.get(i..i + 2).map_or(Ok(0), str::parse)?)
+  };
+  let secs = field(0)? * 3600 + field(2)? * 60 + field(4)?;
+
+  FixedOffset::east_opt(sign * secs)
+    .ok_or_else(|| anyhow!("invalid utc offset {s}"))
+}
+
+// FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU -> (3, -1, Sun)
+fn parse_yearly_rule(rule: &str) -> Option<(u32, i32, Weekday)> {
+  let mut month = None;
+  let mut byday = None;
+
+  for part in rule.split(';') {
+    match part.split_once('=')? {
+      ("FREQ", freq) if freq != "YEARLY" => return None,
+      ("BYMONTH", m) => month = m.parse().ok(),
+      ("BYDAY", d) => byday = Some(d.to_string()),
+      _ => (),
+    }
+  }
+
+  let byday = byday?;
+  let (n, day) = byday.split_at(byday.len().checked_sub(2)?);
+  let n = if n.is_empty() { 1 } else { n.parse().ok()? };
+  let weekday = match day {
+    "MO" => Weekday::Mon,
+    "TU" => Weekday::Tue,
+    "WE" => Weekday::Wed,
+    "TH" => Weekday::Thu,
+    "FR" => Weekday::Fri,
+    "SA" => Weekday::Sat,
+    "SU" => Weekday::Sun,
+    _ => return None,
+  };
+
+  Some((month?, n, weekday))
+}
+
+// the nth (or nth last if negative) weekday of the month
+fn nth_weekday(
+  year: i32,
+  month: u32,
+  n: i32,
+  weekday: Weekday,
+) -> Option<NaiveDate> {
+  if n == 0 {
+    return None;
+  } else if n > 0 {
+    return NaiveDate::from_weekday_of_month_opt(year, month, weekday, n as u8);
+  }
+
+  let (next_year, next_month) = match month {
+    12 => (year + 1, 1),
+    _ => (year, month + 1),
+  };
+  let last_day =
+    NaiveDate::from_ymd_opt(next_year, next_month, 1)?.pred_opt()?;
+  let back = (7 + last_day.weekday().num_days_from_monday()
+    - weekday.num_days_from_monday())
+    % 7;
+  let date = last_day - Duration::days((back + 7 * (-n - 1) as u32) as i64);
+  (date.month() == month).then_some(date)
+}
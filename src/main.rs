@@ -1,7 +1,10 @@
-use std::str::FromStr;
+use std::{
+  path::{Path, PathBuf},
+  str::FromStr,
+};
 
 use anyhow::Context;
-use chrono::{Offset, TimeZone, Utc};
+use chrono::{Duration, Offset, TimeZone, Utc};
 
 use crate::config::{Config, APP_NAME};
 
@@ -12,9 +15,13 @@ mod event;
 mod hook;
 mod ical;
 mod notifier;
+mod recurrence;
 mod util;
 mod widget;
 
+// how far back and ahead events are copied by --import
+const IMPORT_DAYS: i64 = 365;
+
 fn main() -> anyhow::Result<()> {
   // default to log info
   env_logger::builder()
@@ -22,6 +29,8 @@ fn main() -> anyhow::Result<()> {
     .parse_default_env()
     .init();
 
+  let import_dir = import_dir_arg()?;
+
   let config = Config::read_or_initialize()?;
   log::info!("Config loaded {:?}", &config);
 
@@ -34,21 +43,82 @@ fn main() -> anyhow::Result<()> {
     util::local_tz()
   };
 
-  let local_backend = backend::LocalDirBuilder::default()
-    .calendar(&config.calendar_name)
-    .dir(&config.calendar_location)
-    .build()?;
+  let mut app = match &config.caldav_url {
+    Some(url) => {
+      let mut backend = backend::CalDavBuilder::default()
+        .url(url)
+        .calendar(&config.calendar_name)
+        .username(config.caldav_username.clone())
+        .password(config.caldav_password.clone())
+        .build()?;
+      if let Some(dir) = &import_dir {
+        return import(&mut backend, dir);
+      }
 
-  let db_path = {
-    let mut path = dirs::data_dir()
-      .with_context(|| "Cannot find a directory to store data")?;
-    path.push(format!("{APP_NAME}/{APP_NAME}.db"));
-    path
-  };
+      app::App::new(&config, 3, timezone, backend)?
+    }
+    None => {
+      let parse_errors = app::ParseErrors::default();
+      let local_backend = backend::LocalDirBuilder::default()
+        .calendar(&config.calendar_name)
+        .dir(&config.calendar_location)
+        .partitioned(config.partition_by_calendar)
+        .on_parse_error(parse_errors.reporter())
+        .build()?;
+
+      // reload when the files are edited by other programs
+      let watch = |dir: &backend::LocalDir| {
+        dir
+          .watch()
+          .map_err(|e| log::warn!("Not watching calendar directory: {e:?}"))
+          .ok()
+      };
+      let mut watchers: Vec<_> = watch(&local_backend).into_iter().collect();
 
-  let backend = backend::IndexedLocalDir::new(local_backend, db_path)?;
+      let db_path = {
+        let mut path = dirs::data_dir()
+          .with_context(|| "Cannot find a directory to store data")?;
+        path.push(format!("{APP_NAME}/{APP_NAME}.db"));
+        path
+      };
 
-  let mut app = app::App::new(&config, 3, timezone, backend)?;
+      let mut backend = backend::IndexedLocalDir::new(local_backend, db_path)?;
+      if let Some(dir) = &import_dir {
+        return import(&mut backend, dir);
+      }
+
+      let mut app = if config.extra_calendar_locations.is_empty() {
+        app::App::new(&config, 3, timezone, backend)?
+      } else {
+        let mut sources: Vec<Box<dyn backend::Backend>> =
+          vec![Box::new(backend)];
+        for location in &config.extra_calendar_locations {
+          let path = std::path::Path::new(location);
+          anyhow::ensure!(path.is_dir(), "{location} is not a directory");
+          let calendar = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .with_context(|| format!("Invalid calendar location {location}"))?;
+          let dir = backend::LocalDirBuilder::default()
+            .calendar(calendar)
+            .dir(path)
+            .on_parse_error(parse_errors.reporter())
+            .build()?;
+          watchers.extend(watch(&dir));
+          sources.push(Box::new(dir));
+        }
+
+        let backend = backend::MultiBackend::new(sources);
+        app::App::new(&config, 3, timezone, backend)?
+      };
+
+      for watcher in watchers {
+        app.watch_files(watcher);
+      }
+      app.show_parse_errors_of(parse_errors);
+      app
+    }
+  };
 
   app.load_events();
 
@@ -62,3 +132,35 @@ fn main() -> anyhow::Result<()> {
 
   Ok(())
 }
+
+// "malakal --import <dir>" copies the events of a directory of .ics
+// files, e.g. a synchronized subscription, into the calendar
+fn import_dir_arg() -> anyhow::Result<Option<PathBuf>> {
+  let args: Vec<_> = std::env::args().skip(1).collect();
+  match args.as_slice() {
+    [] => Ok(None),
+    [flag, dir] if flag == "--import" => Ok(Some(PathBuf::from(dir))),
+    _ => anyhow::bail!("Usage: {APP_NAME} [--import <dir>]"),
+  }
+}
+
+fn import(target: &mut dyn backend::Backend, dir: &Path) -> anyhow::Result<()> {
+  anyhow::ensure!(dir.is_dir(), "{} is not a directory", dir.display());
+  let calendar = dir
+    .file_name()
+    .map(|name| name.to_string_lossy().into_owned())
+    .with_context(|| format!("Invalid directory {}", dir.display()))?;
+  let mut source = backend::LocalDirBuilder::default()
+    .calendar(calendar)
+    .dir(dir)
+    .build()?;
+
+  let now = util::local_now();
+  let (from, to) = (
+    now - Duration::days(IMPORT_DAYS),
+    now + Duration::days(IMPORT_DAYS),
+  );
+  let count = target.import_from(&mut source, from, to)?;
+  log::info!("Imported {count} events from {}", dir.display());
+  Ok(())
+}
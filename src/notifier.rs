@@ -71,7 +71,7 @@ impl NotifierContext {
 
     context.guards.clear();
     for event in events {
-      if event.start < utc_now() {
+      if event.start < utc_now() || event.all_day {
         continue;
       }
 
@@ -0,0 +1,458 @@
+use anyhow::bail;
+use chrono::{Datelike, Duration, FixedOffset, Months, NaiveDateTime};
+
+use crate::event::{Event, EventId};
+use crate::util::{anyhow, DateTime, Result, Zone};
+
+// upper bound of occurrences to look at per expansion, to guard against
+// endless rules
+const MAX_OCCURRENCES: u32 = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Frequency {
+  Daily,
+  Weekly,
+  Monthly,
+  Yearly,
+}
+
+// The timezone the occurrences keep the wall-clock time of the first
+// one in, so that they stay at the same time across daylight saving
+// time changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SeriesZone {
+  Zone(Zone),
+  // times given in UTC, which has no daylight saving time
+  Fixed(FixedOffset),
+}
+
+impl SeriesZone {
+  fn of(event: &Event) -> Self {
+    match event
+      .timezone
+      .as_deref()
+      .and_then(|tz| tz.parse::<chrono_tz::Tz>().ok())
+    {
+      Some(tz) => Self::Zone(Zone::Named(tz)),
+      // dates are local ones
+      None if event.all_day => Self::Zone(Zone::Local),
+      None => Self::Fixed(*event.start.offset()),
+    }
+  }
+
+  fn naive_local(&self, time: &DateTime) -> NaiveDateTime {
+    let offset = match self {
+      Self::Zone(zone) => zone.offset_at(time),
+      Self::Fixed(offset) => *offset,
+    };
+    time.with_timezone(&offset).naive_local()
+  }
+
+  fn resolve_local(&self, time: NaiveDateTime) -> Option<DateTime> {
+    match self {
+      Self::Zone(zone) => zone.resolve_local(time),
+      Self::Fixed(offset) => time.and_local_timezone(*offset).single(),
+    }
+  }
+}
+
+// A subset of RFC 5545 RRULE: FREQ, INTERVAL, COUNT and UNTIL.
+#[derive(Clone, Debug, PartialEq)]
+struct RecurrenceRule {
+  freq: Frequency,
+  interval: u32,
+  count: Option<u32>,
+  until: Option<DateTime>,
+}
+
+impl RecurrenceRule {
+  // a floating or date-only UNTIL is read in the zone of the series
+  fn parse(s: &str, zone: SeriesZone) -> Result<Self> {
+    let mut freq = None;
+    let mut interval = 1;
+    let mut count = None;
+    let mut until = None;
+
+    for part in s.split(';').filter(|p| !p.is_empty()) {
+      let (key, value) = part
+        .split_once('=')
+        .ok_or_else(|| anyhow!("invalid rrule part {}", part))?;
+
+      match key {
+        "FREQ" => {
+          freq = Some(match value {
+            "DAILY" => Frequency::Daily,
+            "WEEKLY" => Frequency::Weekly,
+            "MONTHLY" => Frequency::Monthly,
+            "YEARLY" => Frequency::Yearly,
+            _ => bail!("unsupported rrule frequency {}", value),
+          })
+        }
+        "INTERVAL" => interval = value.parse::<u32>()?.max(1),
+        "COUNT" => count = Some(value.parse()?),
+        "UNTIL" => until = Some(parse_until(value, zone)?),
+        // WKST only matters for the unsupported BY* parts
+        "WKST" => (),
+        _ => log::warn!("Ignoring unsupported rrule part {part}"),
+      }
+    }
+
+    let freq = freq.ok_or_else(|| anyhow!("rrule without frequency {}", s))?;
+
+    Ok(Self {
+      freq,
+      interval,
+      count,
+      until,
+    })
+  }
+
+  // the start of the nth occurrence, None if it doesn't exist (e.g.
+  // the 31st in a month with 30 days)
+  fn nth_start(
+    &self,
+    start: DateTime,
+    zone: SeriesZone,
+    n: u32,
+  ) -> Option<DateTime> {
+    let steps = n.checked_mul(self.interval)?;
+    let naive = zone.naive_local(&start);
+
+    let naive = match self.freq {
+      Frequency::Daily => naive + Duration::days(steps as i64),
+      Frequency::Weekly => naive + Duration::weeks(steps as i64),
+      Frequency::Monthly => add_months(naive, steps)?,
+      Frequency::Yearly => add_months(naive, steps.checked_mul(12)?)?,
+    };
+
+    zone.resolve_local(naive)
+  }
+
+  // the length of a step for frequencies with a fixed one
+  fn fixed_step(&self) -> Option<Duration> {
+    match self.freq {
+      Frequency::Daily => Some(Duration::days(self.interval as i64)),
+      Frequency::Weekly => Some(Duration::weeks(self.interval as i64)),
+      _ => None,
+    }
+  }
+}
+
+// add months, skipping the dates which don't exist in the month
+fn add_months(t: NaiveDateTime, months: u32) -> Option<NaiveDateTime> {
+  let new_t = t.checked_add_months(Months::new(months))?;
+  (new_t.day() == t.day()).then_some(new_t)
+}
+
+fn parse_until(s: &str, zone: SeriesZone) -> Result<DateTime> {
+  if let Ok(t) = NaiveDateTime::parse_from_str(s, "%Y%m%dT%H%M%SZ") {
+    return Ok(t.and_utc().into());
+  }
+
+  let local = match NaiveDateTime::parse_from_str(s, "%Y%m%dT%H%M%S") {
+    Ok(t) => t,
+    Err(_) => {
+      // an until date includes the whole day
+      let date = chrono::NaiveDate::parse_from_str(s, "%Y%m%d")?;
+      date.and_hms_opt(23, 59, 59).expect("date overflow")
+    }
+  };
+  zone
+    .resolve_local(local)
+    .ok_or_else(|| anyhow!("invalid local time {}", s))
+}
+
+// The id of an occurrence is derived from the id of the series and its
+// original start, so it's unique among the events shown.
+pub(crate) fn occurrence_id(
+  series_id: &EventId,
+  recurrence_id: &DateTime,
+) -> EventId {
+  let time = recurrence_id.naive_utc().format("%Y%m%dT%H%M%SZ");
+  format!("{series_id}_{time}")
+}
+
+// the reverse of occurrence_id
+pub(crate) fn split_occurrence_id(
+  event_id: &EventId,
+) -> Option<(EventId, DateTime)> {
+  let (series_id, time) = event_id.rsplit_once('_')?;
+  let time = NaiveDateTime::parse_from_str(time, "%Y%m%dT%H%M%SZ").ok()?;
+  Some((series_id.to_string(), time.and_utc().into()))
+}
+
+// the id of the series an occurrence or override belongs to, the id
+// of the event itself otherwise
+pub(crate) fn series_id(event: &Event) -> EventId {
+  match event.recurrence_id {
+    Some(_) => split_occurrence_id(&event.id)
+      .map(|(series_id, _)| series_id)
+      .unwrap_or_else(|| event.id.clone()),
+    None => event.id.clone(),
+  }
+}
+
+// Materialize the occurrences of a recurring event which overlap with
+// the from..to interval. The event is returned as is if its recurrence
+// rule is invalid.
+pub(crate) fn expand(
+  event: &Event,
+  from: DateTime,
+  to: DateTime,
+) -> Vec<Event> {
+  let rrule = match &event.rrule {
+    None => return vec![event.clone()],
+    Some(rrule) => rrule,
+  };
+
+  let zone = SeriesZone::of(event);
+  let rule = match RecurrenceRule::parse(rrule, zone) {
+    Ok(rule) => rule,
+    Err(e) => {
+      log::warn!("Failed parsing rrule of {}: {e:?}", event.id);
+      return vec![event.clone()];
+    }
+  };
+
+  let duration = event.end - event.start;
+
+  // skip the occurrences before the range when we don't need to count,
+  // leaving one more for the steps shortened by daylight saving time
+  let first_n = match (rule.fixed_step(), rule.count) {
+    (Some(step), None) if from - duration > event.start => {
+      ((from - duration - event.start).num_seconds() / step.num_seconds())
+        .saturating_sub(1) as u32
+    }
+    _ => 0,
+  };
+
+  let mut occurrences = vec![];
+  for n in first_n..first_n.saturating_add(MAX_OCCURRENCES) {
+    if rule.count.is_some_and(|count| n >= count) {
+      break;
+    }
+
+    let start = match rule.nth_start(event.start, zone, n) {
+      Some(start) => start,
+      None if rule.fixed_step().is_some() => break,
+      None => continue,
+    };
+
+    if rule.until.is_some_and(|until| start > until) || start >= to {
+      break;
+    }
+
+    let end = start + duration;
+    if end <= from || event.exdates.contains(&start) {
+      continue;
+    }
+
+    let mut occurrence = event.clone();
+    occurrence.id = occurrence_id(&event.id, &start);
+    occurrence.start = start;
+    occurrence.end = end;
+    occurrence.rrule = None;
+    occurrence.exdates = vec![];
+    occurrence.recurrence_id = Some(start);
+    occurrences.push(occurrence);
+  }
+
+  occurrences
+}
+
+// Expand the recurring events stored together with their overrides,
+// e.g. the events of a single file. An override (an event with a
+// RECURRENCE-ID) replaces the occurrence generated for the same
+// original start, whether or not that one is in the range, and is
+// only kept if it's in the range itself.
+pub(crate) fn expand_all(
+  events: Vec<Event>,
+  from: DateTime,
+  to: DateTime,
+) -> Vec<Event> {
+  let (overrides, events): (Vec<_>, Vec<_>) = events
+    .into_iter()
+    .partition(|event| event.recurrence_id.is_some());
+
+  let mut occurrences: Vec<_> = events
+    .iter()
+    .flat_map(|event| expand(event, from, to))
+    .filter(|occurrence| !overrides.iter().any(|o| o.id == occurrence.id))
+    .collect();
+  occurrences.extend(
+    overrides
+      .into_iter()
+      .filter(|event| event.visible_in_range(from, to)),
+  );
+  occurrences
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::Timelike;
+
+  use super::*;
+  use crate::event::EventBuilder;
+
+  fn at(s: &str) -> DateTime {
+    DateTime::parse_from_rfc3339(s).unwrap()
+  }
+
+  fn series(start: &str, rrule: &str) -> Event {
+    let start = at(start);
+    EventBuilder::default()
+      .id("series")
+      .calendar("test")
+      .title("Series")
+      .start(start)
+      .end(start + Duration::hours(1))
+      .rrule(Some(rrule.to_string()))
+      .build()
+      .unwrap()
+  }
+
+  fn starts(events: &[Event]) -> Vec<DateTime> {
+    events.iter().map(|e| e.start).collect()
+  }
+
+  fn expand_year(event: &Event) -> Vec<Event> {
+    expand(
+      event,
+      at("2024-01-01T00:00:00Z"),
+      at("2025-01-01T00:00:00Z"),
+    )
+  }
+
+  #[test]
+  fn daily_with_interval_and_count() {
+    let event = series("2024-03-01T09:00:00Z", "FREQ=DAILY;INTERVAL=2;COUNT=3");
+    assert_eq!(
+      starts(&expand_year(&event)),
+      vec![
+        at("2024-03-01T09:00:00Z"),
+        at("2024-03-03T09:00:00Z"),
+        at("2024-03-05T09:00:00Z"),
+      ]
+    );
+  }
+
+  #[test]
+  fn weekly_until() {
+    let event =
+      series("2024-03-01T09:00:00Z", "FREQ=WEEKLY;UNTIL=20240315T090000Z");
+    assert_eq!(
+      starts(&expand_year(&event)),
+      vec![
+        at("2024-03-01T09:00:00Z"),
+        at("2024-03-08T09:00:00Z"),
+        at("2024-03-15T09:00:00Z"),
+      ]
+    );
+  }
+
+  #[test]
+  fn monthly_skips_missing_days() {
+    let event = series("2024-01-31T09:00:00Z", "FREQ=MONTHLY;UNTIL=20240601");
+    assert_eq!(
+      starts(&expand_year(&event)),
+      vec![
+        at("2024-01-31T09:00:00Z"),
+        at("2024-03-31T09:00:00Z"),
+        at("2024-05-31T09:00:00Z"),
+      ]
+    );
+  }
+
+  #[test]
+  fn exdates_are_left_out() {
+    let mut event = series("2024-03-01T09:00:00Z", "FREQ=DAILY;COUNT=3");
+    event.exdates = vec![at("2024-03-02T09:00:00Z")];
+    assert_eq!(
+      starts(&expand_year(&event)),
+      vec![at("2024-03-01T09:00:00Z"), at("2024-03-03T09:00:00Z")]
+    );
+  }
+
+  #[test]
+  fn overrides_replace_occurrences() {
+    let event = series("2024-03-01T09:00:00Z", "FREQ=DAILY;COUNT=3");
+    let original_start = at("2024-03-02T09:00:00Z");
+    let mut moved = event.clone();
+    moved.id = occurrence_id(&event.id, &original_start);
+    moved.rrule = None;
+    moved.recurrence_id = Some(original_start);
+    moved.start = at("2024-03-02T12:00:00Z");
+    moved.end = at("2024-03-02T13:00:00Z");
+
+    let mut occurrences = expand_all(
+      vec![event, moved],
+      at("2024-01-01T00:00:00Z"),
+      at("2025-01-01T00:00:00Z"),
+    );
+    occurrences.sort_by_key(|e| e.start);
+    assert_eq!(
+      starts(&occurrences),
+      vec![
+        at("2024-03-01T09:00:00Z"),
+        at("2024-03-02T12:00:00Z"),
+        at("2024-03-03T09:00:00Z"),
+      ]
+    );
+  }
+
+  #[test]
+  fn occurrences_keep_their_time_across_dst() {
+    // 09:00 in New York, before and after the clocks go forward on the
+    // 10th of March 2024
+    let mut event = series("2024-03-04T14:00:00Z", "FREQ=WEEKLY;COUNT=3");
+    event.timezone = Some("America/New_York".into());
+
+    let occurrences = expand_year(&event);
+    assert_eq!(
+      starts(&occurrences),
+      vec![
+        at("2024-03-04T14:00:00Z"),
+        at("2024-03-11T13:00:00Z"),
+        at("2024-03-18T13:00:00Z"),
+      ]
+    );
+    for occurrence in occurrences {
+      let tz = chrono_tz::America::New_York;
+      assert_eq!(occurrence.start.with_timezone(&tz).hour(), 9);
+    }
+  }
+
+  #[test]
+  fn floating_until_is_in_the_zone_of_the_series() {
+    let mut event =
+      series("2024-03-09T14:00:00Z", "FREQ=DAILY;UNTIL=20240311T090000");
+    event.timezone = Some("America/New_York".into());
+    assert_eq!(
+      starts(&expand_year(&event)),
+      vec![
+        at("2024-03-09T14:00:00Z"),
+        at("2024-03-10T13:00:00Z"),
+        at("2024-03-11T13:00:00Z"),
+      ]
+    );
+  }
+
+  #[test]
+  fn overrides_out_of_range_are_left_out() {
+    let event = series("2024-03-01T09:00:00Z", "FREQ=DAILY;COUNT=3");
+    let original_start = at("2024-03-02T09:00:00Z");
+    let mut moved = event.clone();
+    moved.id = occurrence_id(&event.id, &original_start);
+    moved.rrule = None;
+    moved.recurrence_id = Some(original_start);
+    moved.start = at("2024-04-02T09:00:00Z");
+    moved.end = at("2024-04-02T10:00:00Z");
+
+    let occurrences = expand_all(
+      vec![event, moved],
+      at("2024-03-02T00:00:00Z"),
+      at("2024-03-03T00:00:00Z"),
+    );
+    assert!(occurrences.is_empty());
+  }
+}
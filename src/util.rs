@@ -1,6 +1,11 @@
 pub use anyhow::{anyhow, Result};
 
-use chrono::{Datelike, Duration, FixedOffset, Local, Offset, Utc};
+use chrono::{
+  Datelike, Duration, FixedOffset, Local, LocalResult, NaiveDateTime,
+  NaiveTime, Offset, TimeZone, Utc, Weekday,
+};
+
+use crate::event::{new_event_id, Event, EventBuilder};
 
 pub type DateTime = chrono::DateTime<FixedOffset>;
 pub type Date = chrono::NaiveDate;
@@ -10,6 +15,130 @@ pub fn shared<T>(t: T) -> Shared<T> {
   std::sync::Arc::new(std::sync::Mutex::new(t))
 }
 
+// The timezone the calendar is shown in. Unlike a fixed offset it
+// follows daylight saving time, so that times keep their wall-clock
+// position when moved across a change of the clocks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Zone {
+  Local,
+  Named(chrono_tz::Tz),
+}
+
+impl Zone {
+  // the offset in effect at the instant
+  pub fn offset_at(&self, time: &DateTime) -> FixedOffset {
+    let utc = time.naive_utc();
+    match self {
+      Self::Local => Local.offset_from_utc_datetime(&utc).fix(),
+      Self::Named(tz) => tz.offset_from_utc_datetime(&utc).fix(),
+    }
+  }
+
+  // The wall-clock time in the zone. Times skipped when the clocks go
+  // forward get the offset from before the change, and repeated ones
+  // the earlier offset.
+  pub fn resolve_local(&self, time: NaiveDateTime) -> Option<DateTime> {
+    let offset_of = |t: &NaiveDateTime| match self {
+      Self::Local => Local.offset_from_local_datetime(t).map(|o| o.fix()),
+      Self::Named(tz) => tz.offset_from_local_datetime(t).map(|o| o.fix()),
+    };
+    let offset = match offset_of(&time) {
+      LocalResult::None => offset_of(&(time - Duration::hours(3))),
+      offset => offset,
+    };
+    let offset = match offset {
+      LocalResult::Single(offset) | LocalResult::Ambiguous(offset, _) => offset,
+      LocalResult::None => return None,
+    };
+
+    time.and_local_timezone(offset).single()
+  }
+}
+
+// The source of the current time, so that it can be fixed e.g. to
+// reproduce what the widget shows at a given time.
+pub trait Clock: Send + Sync {
+  fn now(&self) -> DateTime;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> DateTime {
+    local_now()
+  }
+}
+
+// always tells the same time
+#[allow(unused)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FixedClock(pub DateTime);
+
+impl Clock for FixedClock {
+  fn now(&self) -> DateTime {
+    self.0
+  }
+}
+
+// A shared trait object, e.g. a callback, to keep in structs deriving
+// Clone, Debug and PartialEq. Only the clones of one are equal.
+pub struct ArcFn<T: ?Sized>(std::sync::Arc<T>);
+
+impl<T: ?Sized> ArcFn<T> {
+  pub fn from_arc(f: std::sync::Arc<T>) -> Self {
+    Self(f)
+  }
+}
+
+impl<T: ?Sized> std::ops::Deref for ArcFn<T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    &self.0
+  }
+}
+
+impl<T: ?Sized> Clone for ArcFn<T> {
+  fn clone(&self) -> Self {
+    Self(self.0.clone())
+  }
+}
+
+impl<T: ?Sized> std::fmt::Debug for ArcFn<T> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_tuple("ArcFn")
+      .field(&std::any::type_name::<T>())
+      .finish()
+  }
+}
+
+impl<T: ?Sized> PartialEq for ArcFn<T> {
+  fn eq(&self, other: &Self) -> bool {
+    let ptr = |f: &Self| std::sync::Arc::as_ptr(&f.0) as *const ();
+    ptr(self) == ptr(other)
+  }
+}
+
+pub type SharedClock = ArcFn<dyn Clock>;
+
+impl SharedClock {
+  #[allow(unused)]
+  pub fn new(clock: impl Clock + 'static) -> Self {
+    Self::from_arc(std::sync::Arc::new(clock))
+  }
+
+  pub fn now(&self) -> DateTime {
+    self.0.now()
+  }
+}
+
+impl Default for SharedClock {
+  fn default() -> Self {
+    Self::new(SystemClock)
+  }
+}
+
 pub(crate) fn now(tz: &FixedOffset) -> DateTime {
   local_now().with_timezone(tz)
 }
@@ -85,3 +214,313 @@ pub fn end_of_month(date: Date) -> Date {
 
   bom_next_month - Duration::days(1)
 }
+
+// Turn a line like "Lunch with Sam tomorrow 12:30-1:30" into an event.
+//
+// Understood are "today", "tomorrow", weekday names (the next such day,
+// today included), dates like 2024-05-31 and times in 24-hour or
+// 12-hour format, alone or as a range ("9am-5pm", "14:00 to 15:30").
+// Words which may as well belong to the title, like "sun" or "3-4",
+// only count after "on", "at" or "from". The rest becomes the title. Without an end, the event lasts
+// default_duration, and without a time, it's an all-day event. The
+// calendar is left to the caller.
+pub fn parse_quick_add(
+  input: &str,
+  now: DateTime,
+  default_duration: Duration,
+) -> Option<Event> {
+  let mut title: Vec<&str> = vec![];
+  let mut date = None;
+  let mut times: Vec<ClockTime> = vec![];
+
+  let normalize =
+    |word: &str| word.trim_end_matches([',', '.', ';']).to_lowercase();
+
+  let mut words = input.split_whitespace();
+  while let Some(word) = words.next() {
+    let token = normalize(word);
+    // the word introducing the date or time
+    let introduced = title.last().is_some_and(|last| {
+      matches!(last.to_lowercase().as_str(), "at" | "on" | "from" | "@")
+    });
+
+    let recognized = if let Some(d) =
+      parse_quick_add_date(&token, now, introduced)
+    {
+      date.get_or_insert(d);
+      true
+    } else if let Some(range) = parse_time_range(&token, introduced) {
+      times.extend(range);
+      true
+    } else if let Some(time) = ClockTime::parse(&token, false) {
+      times.push(time);
+
+      // "12:30 - 13:30" or "12:30 to 13:30"
+      let mut lookahead = words.clone();
+      if matches!(lookahead.next().map(normalize).as_deref(), Some("-" | "to"))
+      {
+        let end = lookahead.next().map(normalize);
+        if let Some(end) = end.and_then(|end| ClockTime::parse(&end, true)) {
+          times.push(end);
+          words = lookahead;
+        }
+      }
+      true
+    } else {
+      false
+    };
+
+    if recognized {
+      if introduced {
+        title.pop();
+      }
+    } else {
+      title.push(word);
+    }
+  }
+
+  let title = title.join(" ");
+  if title.is_empty() || (date.is_none() && times.is_empty()) {
+    return None;
+  }
+
+  let date = date.unwrap_or_else(|| now.date_naive());
+  let at = |time: NaiveTime| {
+    date
+      .and_time(time)
+      .and_local_timezone(*now.offset())
+      .single()
+  };
+
+  let mut builder = EventBuilder::default();
+  builder
+    .id(new_event_id())
+    .calendar("")
+    .title(title)
+    .timestamp(now)
+    .created_at(now)
+    .modified_at(now);
+
+  match ClockTime::resolve_range(&times) {
+    None => {
+      let start = at(NaiveTime::default())?;
+      builder.start(start).end(start + one_day()).all_day(true);
+    }
+    Some((start, end)) => {
+      let start = at(start)?;
+      let end = match end.and_then(at) {
+        Some(end) if end > start => end,
+        _ => start + default_duration,
+      };
+      builder.start(start).end(end);
+    }
+  }
+
+  builder.build().ok()
+}
+
+fn parse_quick_add_date(
+  token: &str,
+  now: DateTime,
+  introduced: bool,
+) -> Option<Date> {
+  const WEEKDAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+  ];
+
+  let today = now.date_naive();
+  match token {
+    "today" => return Some(today),
+    "tomorrow" => return Some(today + one_day()),
+    _ => (),
+  }
+
+  // "sun" or "wed" are rather words of the title
+  let weekday = token.parse::<Weekday>().ok();
+  let weekday = weekday.filter(|_| introduced || WEEKDAYS.contains(&token));
+  if let Some(weekday) = weekday {
+    let days_ahead = (7 + weekday.num_days_from_monday()
+      - today.weekday().num_days_from_monday())
+      % 7;
+    return Some(today + Duration::days(days_ahead as i64));
+  }
+
+  Date::parse_from_str(token, "%Y-%m-%d").ok()
+}
+
+// "9-10am" or "12:30-1:30", but "3-4" only after "at" or "from"
+fn parse_time_range(token: &str, introduced: bool) -> Option<[ClockTime; 2]> {
+  let (start, end) = token.split_once('-')?;
+  let range = [ClockTime::parse(start, true)?, ClockTime::parse(end, true)?];
+  let bare = !token.contains(':') && range.iter().all(|t| t.pm.is_none());
+  (introduced || !bare).then_some(range)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ClockTime {
+  hour: u32,
+  minute: u32,
+  // None when not given in 12-hour format
+  pm: Option<bool>,
+}
+
+impl ClockTime {
+  // Bare numbers are only accepted in a range, otherwise the title
+  // couldn't contain any.
+  fn parse(s: &str, allow_bare: bool) -> Option<Self> {
+    let (s, pm) = if let Some(s) = s.strip_suffix("am") {
+      (s, Some(false))
+    } else if let Some(s) = s.strip_suffix("pm") {
+      (s, Some(true))
+    } else {
+      (s, None)
+    };
+
+    let (hour, minute) = match s.split_once(':') {
+      Some((hour, minute)) if minute.len() == 2 => (hour, minute),
+      Some(_) => return None,
+      None if pm.is_some() || allow_bare => (s, "00"),
+      None => return None,
+    };
+
+    let is_number = |s: &str| {
+      (1..=2).contains(&s.len()) && s.bytes().all(|b| b.is_ascii_digit())
+    };
+    if !is_number(hour) || !is_number(minute) {
+      return None;
+    }
+
+    let (hour, minute) = (hour.parse().ok()?, minute.parse().ok()?);
+    let max_hour = if pm.is_some() { 12 } else { 23 };
+    if hour > max_hour || minute > 59 || (pm.is_some() && hour == 0) {
+      return None;
+    }
+
+    Some(Self { hour, minute, pm })
+  }
+
+  fn hour_of_day(&self, pm: Option<bool>) -> u32 {
+    match pm {
+      Some(false) => self.hour % 12,
+      Some(true) => self.hour % 12 + 12,
+      None => self.hour,
+    }
+  }
+
+  // the first time is the start, the optional second one the end
+  fn resolve_range(
+    times: &[ClockTime],
+  ) -> Option<(NaiveTime, Option<NaiveTime>)> {
+    let to_time = |hour, minute| NaiveTime::from_hms_opt(hour, minute, 0);
+
+    let start = *times.first()?;
+    let end = match times.get(1) {
+      Some(end) => *end,
+      None => {
+        return Some((
+          to_time(start.hour_of_day(start.pm), start.minute)?,
+          None,
+        ))
+      }
+    };
+
+    let mut end_hour = end.hour_of_day(end.pm);
+    let mut start_hour = start.hour_of_day(start.pm);
+    // "9-10pm": the start shares the half of the day of the end
+    if start.pm.is_none() && start.hour <= 12 {
+      let shared = start.hour_of_day(end.pm);
+      if end.pm.is_some() && shared <= end_hour {
+        start_hour = shared;
+      }
+    }
+
+    // "12:30-1:30": the end is in the afternoon
+    if end.pm.is_none() && end_hour < start_hour && end_hour + 12 < 24 {
+      end_hour += 12;
+    }
+
+    Some((
+      to_time(start_hour, start.minute)?,
+      to_time(end_hour, end.minute),
+    ))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn at(month: u32, day: u32, h: u32, m: u32) -> DateTime {
+    let utc = FixedOffset::east_opt(0).unwrap();
+    utc.with_ymd_and_hms(2024, month, day, h, m, 0).unwrap()
+  }
+
+  #[test]
+  fn quick_add_finds_dates_and_times() {
+    let all_day = |month, day| {
+      let start = at(month, day, 0, 0);
+      (start, start + one_day())
+    };
+    let cases = [
+      (
+        "Lunch with Sam tomorrow 12:30-1:30",
+        "Lunch with Sam",
+        (at(3, 2, 12, 30), at(3, 2, 13, 30)),
+      ),
+      (
+        "Dentist today 9am",
+        "Dentist",
+        (at(3, 1, 9, 0), at(3, 1, 9, 30)),
+      ),
+      (
+        "Review monday 14:00 to 15:30",
+        "Review",
+        (at(3, 4, 14, 0), at(3, 4, 15, 30)),
+      ),
+      ("Party friday", "Party", all_day(3, 1)),
+      ("Gym 6pm-7pm", "Gym", (at(3, 1, 18, 0), at(3, 1, 19, 0))),
+      ("Call 9-10am", "Call", (at(3, 1, 9, 0), at(3, 1, 10, 0))),
+      (
+        "Chapter 3-4 review at 9am",
+        "Chapter 3-4 review",
+        (at(3, 1, 9, 0), at(3, 1, 9, 30)),
+      ),
+      (
+        "Standup at 3-4",
+        "Standup",
+        (at(3, 1, 3, 0), at(3, 1, 4, 0)),
+      ),
+      ("Sun cream today", "Sun cream", all_day(3, 1)),
+      (
+        "Meeting on sat at 10:00",
+        "Meeting",
+        (at(3, 2, 10, 0), at(3, 2, 10, 30)),
+      ),
+      ("Trip 2024-05-31", "Trip", all_day(5, 31)),
+    ];
+
+    // a Friday
+    let now = at(3, 1, 10, 0);
+    for (input, title, (start, end)) in cases {
+      let event = parse_quick_add(input, now, Duration::minutes(30))
+        .unwrap_or_else(|| panic!("{input} not understood"));
+      assert_eq!(event.title, title, "{input}");
+      assert_eq!((event.start, event.end), (start, end), "{input}");
+    }
+  }
+
+  #[test]
+  fn quick_add_needs_a_title_and_a_time() {
+    let now = at(3, 1, 10, 0);
+    for input in ["Just a title", "tomorrow 9am", ""] {
+      let event = parse_quick_add(input, now, Duration::minutes(30));
+      assert!(event.is_none(), "{input}");
+    }
+  }
+}
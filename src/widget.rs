@@ -1,5 +1,9 @@
 pub mod calendar;
 pub mod schedule_ui;
+// a headless harness driving the widget, for testing the interactions
+#[cfg(any(test, feature = "testing"))]
+#[allow(unused)]
+pub mod testing;
 
 pub use calendar::*;
 pub use schedule_ui::*;
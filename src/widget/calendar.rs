@@ -1,7 +1,9 @@
+use std::{collections::HashMap, ops::Range};
+
 use chrono::{Datelike, Duration};
 use derive_builder::Builder;
 
-use eframe::egui::{self, Rect, RichText, Ui};
+use eframe::egui::{self, vec2, Rect, RichText, Ui};
 
 use crate::util::{beginning_of_month, end_of_month, Date};
 
@@ -22,6 +24,18 @@ pub struct Calendar {
 
   #[builder(default = "Vec::new()")]
   highlight_dates: Vec<Date>,
+
+  // the number of events by day, marked with a dot under the days
+  #[builder(default)]
+  event_counts: HashMap<Date, usize>,
+
+  // the dates whose events were last asked to be counted
+  #[builder(default, setter(skip))]
+  counted_dates: Option<Range<Date>>,
+
+  // the scrolling not yet turned into going to another month
+  #[builder(default, setter(skip))]
+  scroll_delta: f32,
 }
 
 pub enum CalendarAction {
@@ -33,6 +47,8 @@ impl Calendar {
   const DAYS_PER_WEEK: usize = 7;
   const WEEK_DAYS: [&'static str; Self::DAYS_PER_WEEK] =
     ["S", "M", "T", "W", "T", "F", "S"];
+  // the scrolling going one month further, a notch of a mouse wheel
+  const SCROLL_STEP: f32 = 50.0;
 
   fn calc_bounding_rect(_ui: &Ui) -> Rect {
     todo!()
@@ -43,7 +59,7 @@ impl Calendar {
 
     self.draw_month_header(ui);
 
-    egui::Grid::new("calendar")
+    let grid = egui::Grid::new("calendar")
       .num_columns(Self::DAYS_PER_WEEK + 1)
       .min_col_width(self.day_square_size[0])
       .max_col_width(self.day_square_size[0])
@@ -53,9 +69,65 @@ impl Calendar {
         action = self.draw_days(ui);
       });
 
+    self.handle_scroll(ui, grid.response.rect);
+
     action
   }
 
+  // scrolling over the days goes to the previous or next month, once
+  // per step so that a touchpad swipe doesn't skip several
+  fn handle_scroll(&mut self, ui: &Ui, rect: Rect) {
+    if !ui.rect_contains_pointer(rect) {
+      self.scroll_delta = 0.0;
+      return;
+    }
+
+    self.scroll_delta += ui.input(|input| input.raw_scroll_delta.y);
+    if self.scroll_delta >= Self::SCROLL_STEP {
+      self.date = month_offset(self.date, -1);
+      self.scroll_delta = 0.0;
+    } else if self.scroll_delta <= -Self::SCROLL_STEP {
+      self.date = month_offset(self.date, 1);
+      self.scroll_delta = 0.0;
+    }
+  }
+
+  // the dates shown, including the days of the adjacent months filling
+  // the first and last weeks
+  pub(crate) fn shown_dates(&self) -> Range<Date> {
+    let bom = beginning_of_month(self.date);
+    let eom = end_of_month(self.date);
+
+    let days_from_previous_month = self.calc_weekday_location(bom);
+    let days_from_next_month =
+      Self::DAYS_PER_WEEK - self.calc_weekday_location(eom);
+
+    let first = bom - Duration::days(days_from_previous_month as i64);
+    let last = eom + Duration::days(days_from_next_month as i64);
+    first..last
+  }
+
+  // the shown dates if their events haven't been asked to be counted
+  // yet, e.g. after going to another month
+  pub(crate) fn take_counts_request(&mut self) -> Option<Range<Date>> {
+    let dates = self.shown_dates();
+    if self.counted_dates.as_ref() == Some(&dates) {
+      return None;
+    }
+
+    self.counted_dates = Some(dates.clone());
+    Some(dates)
+  }
+
+  // count the events again on the next frame, e.g. after they changed
+  pub(crate) fn invalidate_counts(&mut self) {
+    self.counted_dates = None;
+  }
+
+  pub(crate) fn set_event_counts(&mut self, counts: HashMap<Date, usize>) {
+    self.event_counts = counts;
+  }
+
   fn draw_month_header(&mut self, ui: &mut Ui) {
     ui.horizontal(|ui| {
       if ui.button("<<").clicked() {
@@ -87,18 +159,9 @@ impl Calendar {
   fn draw_days(&self, ui: &mut Ui) -> Option<CalendarAction> {
     let mut action = None;
 
-    let bom = beginning_of_month(self.date);
-    let eom = end_of_month(self.date);
-
-    let days_form_previous_month = self.calc_weekday_location(bom);
-    let days_from_next_month =
-      Self::DAYS_PER_WEEK - self.calc_weekday_location(eom);
-
-    let total_days = days_form_previous_month
-      + days_from_next_month
-      + (eom - bom).num_days() as usize;
-
-    let mut date = bom - Duration::days(days_form_previous_month as i64);
+    let dates = self.shown_dates();
+    let total_days = (dates.end - dates.start).num_days() as usize;
+    let mut date = dates.start;
 
     // draw days of the previous month
     for i in 0..total_days {
@@ -131,7 +194,22 @@ impl Calendar {
       text = text.underline();
     }
 
-    if ui.vertical_centered(|ui| ui.button(text)).inner.clicked() {
+    let resp = ui.vertical_centered(|ui| ui.button(text)).inner;
+
+    let count = self.event_counts.get(&date).copied().unwrap_or(0);
+    let resp = if count > 0 {
+      let dot = resp.rect.center_bottom() - vec2(0.0, 3.0);
+      let color = ui.visuals().widgets.inactive.fg_stroke.color;
+      ui.painter().circle_filled(dot, 1.5, color);
+      resp.on_hover_text(match count {
+        1 => "1 event".to_string(),
+        n => format!("{n} events"),
+      })
+    } else {
+      resp
+    };
+
+    if resp.clicked() {
       return Some(CalendarAction::DateClicked(date));
     }
 
@@ -1,12 +1,21 @@
 mod interaction;
 mod layout;
 
-use chrono::{Duration, FixedOffset, NaiveDateTime, NaiveTime, Timelike};
+use std::{
+  collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+  ops::Range,
+};
+
+use chrono::{
+  Datelike, Duration, FixedOffset, NaiveDateTime, NaiveTime, Timelike, Weekday,
+};
 use derive_builder::Builder;
 use eframe::egui::{
-  self, pos2, vec2, Color32, Pos2, Rect, Response, Sense, Ui, Vec2,
+  self, pos2, vec2, Color32, Pos2, Rect, Response, Rounding, Sense, Stroke, Ui,
+  Vec2,
 };
-use uuid::Uuid;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
 
 use self::{
   interaction::History,
@@ -14,8 +23,13 @@ use self::{
 };
 
 use crate::{
-  event::{Event, EventBuilder},
-  util::{now, on_the_same_day, today, Date, DateTime},
+  event::{new_event_id, Event, EventBuilder, EventStatus},
+  ical::ICal,
+  recurrence,
+  util::{
+    on_the_same_day, one_day, parse_quick_add, ArcFn, Date, DateTime,
+    SharedClock, Zone,
+  },
   widget::CalendarBuilder,
 };
 
@@ -36,10 +50,23 @@ pub struct ScheduleUi {
   segment_count: usize,
   #[builder(default = "80.0")]
   segment_height: f32,
+  // the range ctrl-scroll zooms segment_height within
+  #[builder(default = "30.0")]
+  min_segment_height: f32,
+  #[builder(default = "240.0")]
+  max_segment_height: f32,
   #[builder(default = "80.0")]
   time_marker_margin_width: f32,
   #[builder(default = "60.0")]
   day_header_margin_height: f32,
+  // a strip below the day headers for all-day events
+  #[builder(default = "24.0")]
+  all_day_strip_height: f32,
+  // the formats below are written for 24-hour times and rewritten by
+  // time_format when needed
+  #[builder(default)]
+  time_format: TimeFormat,
+
   #[builder(default = "\"%H:%M\"")]
   time_marker_format: &'static str,
   #[builder(default = "\"%F %a\"")]
@@ -47,6 +74,43 @@ pub struct ScheduleUi {
 
   first_day: Date,
 
+  #[builder(default)]
+  view_mode: ViewMode,
+
+  // the first day of the week view and the calendar
+  #[builder(default = "Weekday::Mon")]
+  week_start: Weekday,
+
+  // the days whose columns are shaded
+  #[builder(default = "[Weekday::Sat, Weekday::Sun].into_iter().collect()")]
+  weekend_days: HashSet<Weekday>,
+  // the shading of the weekend columns, faint_bg_color if not set
+  #[builder(default)]
+  weekend_background: Option<Color32>,
+
+  // the ISO 8601 week numbers of the shown days, in the corner above
+  // the time marks
+  #[builder(default = "false")]
+  show_week_numbers: bool,
+
+  // the hours outside are shaded, the range wraps around midnight for
+  // night shifts if the start is after the end
+  #[builder(default)]
+  working_hours: Option<Range<NaiveTime>>,
+
+  // the hours shown at full height, the ones before and after are
+  // compressed into bands which show the whole day when clicked
+  #[builder(default)]
+  visible_time_range: Option<Range<NaiveTime>>,
+  #[builder(default = "24.0")]
+  compressed_band_height: f32,
+  #[builder(default = "false", setter(skip))]
+  all_hours_shown: bool,
+
+  // the width available for day columns, as of the last refit
+  #[builder(default, setter(skip))]
+  day_space_width: f32,
+
   // a small margin on the right of day columns reserved for creating
   // new events
   #[builder(default = "20.0")]
@@ -55,6 +119,13 @@ pub struct ScheduleUi {
   // used to render current time indicator
   current_time: Option<DateTime>,
 
+  // all reads of the current time go through it
+  #[builder(default)]
+  clock: SharedClock,
+
+  #[builder(default = "Color32::RED")]
+  now_indicator_color: Color32,
+
   // used to refresh every second
   #[builder(default = "std::time::Instant::now()", setter(skip))]
   last_update: std::time::Instant,
@@ -64,48 +135,377 @@ pub struct ScheduleUi {
   #[builder(default = "20.0")]
   resizer_width_margin: f32,
 
+  // short events are drawn taller than their duration to stay easy to
+  // grab
+  #[builder(default = "12.0")]
+  min_event_height: f32,
+
+  // how far (in points) a drag on the blank area must move before it
+  // creates an event, so that jittery clicks don't leave tiny events
+  #[builder(default = "6.0")]
+  new_event_drag_threshold: f32,
+
+  // the shortest event created by dragging on the blank area
   #[builder(default = "Duration::minutes(15)")]
-  min_event_duration: Duration,
+  min_new_event_duration: Duration,
+
+  // new events start with this title, and those not created by
+  // dragging last default_event_duration
+  #[builder(default)]
+  default_event_title: String,
+  #[builder(default = "Duration::minutes(15)")]
+  default_event_duration: Duration,
+
+  // the shortest an event can be resized to, shorter events from
+  // elsewhere are stretched to it
+  #[builder(default = "Duration::minutes(15)")]
+  min_resize_duration: Duration,
 
   #[builder(default = "Duration::minutes(15)")]
   snapping_duration: Duration,
 
+  // ticks that take precedence over snapping_duration when the
+  // pointer is close enough, from the strongest to the weakest
+  #[builder(default = "SnapTier::defaults()")]
+  snapping_tiers: Vec<SnapTier>,
+
+  // how close (in points) an edge of a moved or resized event must get
+  // to the start or end of another event to stick to it, 0 to disable
+  #[builder(default = "8.0")]
+  magnet_distance: f32,
+
   #[builder(default = "\"%H:%M\"")]
   event_resizing_hint_format: &'static str,
 
-  #[builder(default = "Color32::LIGHT_BLUE")]
-  new_event_color: Color32,
+  // includes the day as moved events may change their date
+  #[builder(default = "\"%a %H:%M\"")]
+  event_moving_hint_format: &'static str,
+
+  // where the times of resized or moved events are shown
+  #[builder(default)]
+  resizer_hint_anchor: ResizerHintAnchor,
+
+  // format of the duration shown while dragging, see format_duration
+  #[builder(default)]
+  duration_hint_format: Option<&'static str>,
+
+  // styling of the event blocks, following the theme when unset
+  #[builder(default)]
+  event_rounding: Option<Rounding>,
+
+  #[builder(default)]
+  event_stroke: Option<Stroke>,
+
+  // the lines between the days and hours, the snapping ticks in between
+  // are drawn fainter
+  #[builder(default)]
+  grid_stroke: Option<Stroke>,
+
+  #[builder(default)]
+  label_truncation: LabelTruncation,
+
+  // follow the daylight saving time of the zone instead of showing
+  // everything at the fixed timezone offset
+  #[builder(default)]
+  zone: Option<Zone>,
+
+  // draw a dragged event under the pointer instead of at the snapped
+  // time it lands at, which is then shown by a translucent ghost
+  #[builder(default = "false")]
+  dragged_event_follows_pointer: bool,
+
+  // used for the event titles instead of black or white when its
+  // contrast ratio (1 to 21) with the event color is at least
+  // min_text_contrast
+  #[builder(default)]
+  event_text_color: Option<Color32>,
+
+  #[builder(default = "4.5")]
+  min_text_contrast: f32,
+
+  // the layer order used to draw an event being dragged or resized,
+  // so that it's never occluded by the static events it passes over
+  #[builder(default = "egui::Order::Foreground")]
+  interacting_event_order: egui::Order,
 
   timezone: FixedOffset,
 
   new_event_calendar: String,
 
+  // disable creating, moving, resizing and editing events
+  #[builder(default = "false")]
+  read_only: bool,
+
+  // events not matching the filter are dimmed, see matches_filter
+  #[builder(default)]
+  filter: Option<String>,
+
+  #[builder(default = "false")]
+  hide_cancelled: bool,
+
+  #[builder(default)]
+  transparent_events: TransparentEvents,
+
+  // extra items the host app adds to the context menu of events
+  #[builder(default)]
+  context_menu: Option<ContextMenu>,
+
+  // events whose categories are all hidden are not shown
+  #[builder(default)]
+  hidden_categories: BTreeSet<String>,
+
+  // the events of hidden calendars stay loaded but aren't shown
+  #[builder(default)]
+  hidden_calendars: BTreeSet<String>,
+
+  // at most this many overlapping timed events are drawn in a day
+  // column, the others are counted on a chip which expands the day
+  // when clicked
+  #[builder(default)]
+  max_overlapping_events: Option<usize>,
+  #[builder(default, setter(skip))]
+  expanded_days: BTreeSet<Date>,
+
+  // the text typed in the quick-add field, see parse_quick_add
+  #[builder(default, setter(skip))]
+  quick_add_input: String,
+
+  // what happened to the events during the last frame
+  #[builder(default, setter(skip))]
+  emitted_events: Vec<ScheduleEvent>,
+
+  #[builder(default, setter(skip))]
+  focused_event: Option<EventId>,
+
+  // the outcome of the last import or bulk deletion, shown until
+  // dismissed, and its title
+  #[builder(default, setter(skip))]
+  summary: Option<(&'static str, String)>,
+  // the dropped files which couldn't be read, for the summary
+  #[builder(default, setter(skip))]
+  import_failures: usize,
+
   #[builder(default = "false")]
   pub scope_updated: bool,
 
   #[builder(default = "false")]
   pub refresh_requested: bool,
 
+  // for the host to save export_ics() somewhere
+  #[builder(default = "false")]
+  pub export_requested: bool,
+
+  // the range the calendar wants the events of counted by day, for the
+  // host to answer with load_event_counts()
+  #[builder(default, setter(skip))]
+  pub event_counts_requested: Option<(DateTime, DateTime)>,
+
+  // the events of the dropped files, for the host to look up their
+  // stored versions and answer with import_events()
+  #[builder(default, setter(skip))]
+  pub import_requested: Option<Vec<Event>>,
+
+  // the shown days to delete all events of, for the host to delete
+  // them and answer with deleted_events_in_range()
+  #[builder(default, setter(skip))]
+  pub range_deletion_requested: Option<(DateTime, DateTime)>,
+
+  // scroll to the current time on the next frame, enabled by default
+  // to show the current time when the widget is opened
+  #[builder(default = "true")]
+  scroll_to_now_requested: bool,
+
+  // how long (in seconds) scrolling to a time takes, 0 to jump there
+  #[builder(default = "0.3")]
+  scroll_animation_duration: f32,
+
+  #[builder(default, setter(skip))]
+  scroll_animation: Option<ScrollAnimation>,
+
+  // the vertical scroll position, i.e. the distance between the top of
+  // the widget and the top of the visible area
+  #[builder(default, setter(skip))]
+  scroll_offset: f32,
+
+  // the scroll position to restore on the next frame
+  #[builder(default, setter(skip))]
+  restore_scroll_offset: Option<f32>,
+
   #[builder(default = "vec![]")]
   events: Vec<Event>,
 
   #[builder(default, setter(skip))]
   history: History,
 
+  // the events stored under another id than the one they were created
+  // with, for the interaction in progress to follow them
+  #[builder(default, setter(skip))]
+  rekeyed_events: Vec<(EventId, EventId)>,
+
   #[builder(default)]
   calendar: Option<Calendar>,
 }
 
 type EventId = String;
 
-#[derive(Clone, Copy, Debug)]
-struct DraggingEventYOffset(f32);
+#[derive(
+  Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub enum ViewMode {
+  // as many days as fitting in the widget
+  #[default]
+  Day,
+  // seven days from week_start on
+  Week,
+}
+
+// an ongoing scroll to a time, see scroll_to_time
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ScrollAnimation {
+  // the center of the visible area, relative to the widget
+  from: Pos2,
+  to: Pos2,
+  started_at: f64,
+}
+
+// what the user did to the events, for the host to react on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScheduleEvent {
+  // focused, by clicking or with the keyboard
+  Selected(EventId),
+  Created(EventId),
+  Edited(EventId),
+  Deleted(EventId),
+}
+
+// how titles too long for their event are shortened
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LabelTruncation {
+  // "Weekly planning.."
+  #[default]
+  End,
+  // "Weekly..meeting", for titles whose end matters
+  Middle,
+  // over up to the given number of lines, ending with "…"
+  Wrap(usize),
+}
+
+// how the events not taking up busy time (TRANSP:TRANSPARENT) are shown
+#[derive(
+  Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum TransparentEvents {
+  #[default]
+  Shown,
+  Dimmed,
+  Hidden,
+}
+
+// where the hints with the times of a resized or moved event go
+#[derive(
+  Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizerHintAnchor {
+  // on the edges of the event, possibly under the pointer
+  #[default]
+  InPlace,
+  // over the time marks, at the height of the edges
+  Gutter,
+  // at the height of the edges, left of the pointer
+  FollowPointer,
+}
+
+// what an item of a host-supplied context menu asks for
+#[derive(Clone, Debug, PartialEq)]
+pub enum ContextMenuAction {
+  Delete,
+  // replace the event with the edited one
+  Update(Event),
+}
+
+type ContextMenuFn =
+  dyn Fn(&mut Ui, &Event) -> Option<ContextMenuAction> + Send + Sync;
+
+// Renders additional items into the context menu of an event, e.g.
+// "Open in browser". Returns an action when one of them is clicked.
+pub type ContextMenu = ArcFn<ContextMenuFn>;
+
+impl ContextMenu {
+  pub fn new(
+    f: impl Fn(&mut Ui, &Event) -> Option<ContextMenuAction> + Send + Sync + 'static,
+  ) -> Self {
+    Self::from_arc(std::sync::Arc::new(f))
+  }
+
+  fn show(&self, ui: &mut Ui, event: &Event) -> Option<ContextMenuAction> {
+    (**self)(ui, event)
+  }
+}
+
+// whether times are shown like 14:30 or 2:30 PM
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimeFormat {
+  Twelve,
+  #[default]
+  TwentyFour,
+}
+
+impl TimeFormat {
+  // rewrite the 24-hour times of a chrono format string
+  fn apply(self, format: &str) -> String {
+    match self {
+      Self::TwentyFour => format.to_string(),
+      Self::Twelve => format
+        .replace("%H:%M:%S", "%-I:%M:%S %p")
+        .replace("%H:%M", "%-I:%M %p"),
+    }
+  }
+}
+
+// what the widget shows, to be restored the next time the app starts
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ViewState {
+  #[serde_as(as = "DisplayFromStr")]
+  pub first_day: Date,
+  pub view_mode: ViewMode,
+  // the zoom level
+  pub segment_height: f32,
+  pub scroll_offset: f32,
+  // missing from the states saved before calendars could be hidden
+  #[serde(default)]
+  pub hidden_calendars: BTreeSet<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapTier {
+  pub interval: Duration,
+  // the maximal distance for the pointer to be attracted to the tick
+  pub tolerance: Duration,
+}
+
+impl SnapTier {
+  fn defaults() -> Vec<Self> {
+    vec![
+      SnapTier {
+        interval: Duration::hours(1),
+        tolerance: Duration::minutes(5),
+      },
+      SnapTier {
+        interval: Duration::minutes(30),
+        tolerance: Duration::minutes(4),
+      },
+    ]
+  }
+}
 
 #[derive(Debug)]
 enum EventLayoutType {
   // start, end
   Single(f32, f32),
-  #[allow(unused)]
+  // first day, day after the last day
   AllDay([Date; 2]),
 }
 
@@ -124,21 +524,146 @@ impl ScheduleUi {
         .filter(|&e| self.date_to_day(e.start.date_naive()) == Some(day))
         .filter(|&e| matches!(self.layout_type(e), EventLayoutType::Single(..)))
         .map(|e| {
-          if e.end - e.start < self.min_event_duration {
-            let end = e.start + self.min_event_duration;
-            (&e.id, e.start.timestamp(), end.timestamp()).into()
-          } else {
-            (&e.id, e.start.timestamp(), e.end.timestamp()).into()
-          }
+          let end = e.end.max(e.start + self.min_resize_duration);
+          layout::Ev::from((&e.id, e.start.timestamp(), end.timestamp()))
+            // events of higher priority get wider
+            .with_weight(1.0 + e.priority_rank() as f32 / 9.0)
         })
         .collect();
 
       layout.merge(layout::MarkusAlgorithm::compute(events))
     }
 
+    // all-day events share the strip, the layout is used vertically
+    let all_day_events: Vec<layout::Ev> = events
+      .iter()
+      .filter(|&e| !e.deleted)
+      .filter(|&e| matches!(self.layout_type(e), EventLayoutType::AllDay(..)))
+      .map(|e| (&e.id, e.start.timestamp(), e.end.timestamp()).into())
+      .collect();
+    layout.merge(layout::MarkusAlgorithm::compute(all_day_events));
+
     layout
   }
 
+  // Leaves out the least relevant timed events of the overlapping ones
+  // more than max_overlapping_events, unless the day was expanded. Events
+  // drawn one after another are all kept. The events being interacted
+  // with or focused are kept, then those of higher priority and the
+  // longer ones. Returns the number of events over the limit by day,
+  // expanded or not.
+  fn cap_events_per_day(
+    &self,
+    events: &mut Vec<CombinedEvent>,
+  ) -> BTreeMap<usize, usize> {
+    let max = match self.max_overlapping_events {
+      Some(max) => max,
+      None => return BTreeMap::new(),
+    };
+
+    let mut by_day: BTreeMap<usize, Vec<&CombinedEvent>> = BTreeMap::new();
+    for combined_event in events.iter() {
+      let event = combined_event.event();
+      if event.deleted
+        || !matches!(self.layout_type(event), EventLayoutType::Single(..))
+      {
+        continue;
+      }
+      if let Some(day) = self.date_to_day(event.start.date_naive()) {
+        by_day.entry(day).or_default().push(combined_event);
+      }
+    }
+
+    let mut overflow = BTreeMap::new();
+    let mut left_out = HashSet::new();
+    for (day, mut day_events) in by_day {
+      // the groups of events overlapping each other, as laid out side
+      // by side, along with their end
+      day_events.sort_by_key(|e| e.event().start);
+      let mut groups: Vec<(Vec<&CombinedEvent>, DateTime)> = vec![];
+      for combined_event in day_events {
+        let event = combined_event.event();
+        match groups.last_mut() {
+          Some((group, end)) if event.start < *end => {
+            group.push(combined_event);
+            *end = (*end).max(event.end);
+          }
+          _ => groups.push((vec![combined_event], event.end)),
+        }
+      }
+
+      let date = self.first_day + Duration::days(day as i64);
+      let expanded = self.expanded_days.contains(&date);
+      for (mut group, _) in groups {
+        if group.len() <= max {
+          continue;
+        }
+        *overflow.entry(day).or_insert(0) += group.len() - max;
+        if expanded {
+          continue;
+        }
+
+        group.sort_by_key(|e| {
+          let interacting = matches!(e, CombinedEvent::InteractingEvent(_));
+          let focused = self.focused_event.as_ref() == Some(e.event_id());
+          let duration = e.event().end - e.event().start;
+          std::cmp::Reverse((
+            interacting || focused,
+            e.event().priority_rank(),
+            duration,
+          ))
+        });
+        left_out.extend(
+          group[max..]
+            .iter()
+            .filter(|e| !matches!(e, CombinedEvent::InteractingEvent(_)))
+            .map(|e| e.event_id().clone()),
+        );
+      }
+    }
+
+    events.retain(|e| !left_out.contains(e.event_id()));
+    overflow
+  }
+
+  // a "+N more" chip at the bottom of the crowded days, expanding the
+  // day when clicked and collapsing it back once expanded
+  fn show_overflow_chips(
+    &mut self,
+    ui: &mut Ui,
+    rect: Rect,
+    overflow: &BTreeMap<usize, usize>,
+  ) {
+    for (&day, &count) in overflow {
+      let column = self.day_column(day).translate(self.content_offset(rect));
+      let visible = column.intersect(ui.clip_rect());
+      if !visible.is_positive() {
+        continue;
+      }
+
+      let date = self.first_day + Duration::days(day as i64);
+      let expanded = self.expanded_days.contains(&date);
+      let text = if expanded {
+        "Show less".to_string()
+      } else {
+        format!("+{count} more")
+      };
+
+      let height = ui.spacing().interact_size.y;
+      let chip = Rect::from_center_size(
+        pos2(visible.center().x, visible.bottom() - height),
+        vec2((self.day_width / 2.0).min(visible.width()), height),
+      );
+      if ui.put(chip, egui::Button::new(text).small()).clicked() {
+        if expanded {
+          self.expanded_days.remove(&date);
+        } else {
+          self.expanded_days.insert(date);
+        }
+      }
+    }
+  }
+
   fn event_rect(
     &self,
     ui: &Ui,
@@ -153,10 +678,92 @@ impl ScheduleUi {
         let y = [(start - day).clamp(0.0, 1.0), (end - day).clamp(0.0, 1.0)];
         let rect = self.layout_event(widget_rect, day as usize, y, rel_x);
         let margin = ui.style().visuals.clip_rect_margin / 2.0;
+        let mut rect = rect.shrink(margin);
+
+        if rect.height() < self.min_event_height {
+          // grow downwards, or upwards at the end of the day
+          let day_bottom = self
+            .layout_event(widget_rect, day as usize, [y[0], 1.0], rel_x)
+            .bottom()
+            - margin;
+          rect.set_height(self.min_event_height);
+          let overflow = rect.bottom() - day_bottom;
+          if overflow > 0.0 {
+            rect = rect.translate(vec2(0.0, -overflow));
+          }
+        }
+
+        Some(rect)
+      }
+      EventLayoutType::AllDay([start, end]) => {
+        let rel_y = layout.query(&event.id)?;
+        let first = (start - self.first_day).num_days().max(0);
+        let last = (end - self.first_day).num_days().min(self.day_count as i64);
+        if first >= last {
+          return None;
+        }
+
+        let strip_top = -self.all_day_strip_height;
+        let h = self.all_day_strip_height;
+        let min = pos2(first as f32 * self.day_width, strip_top + rel_y[0] * h);
+        let max = pos2(
+          last as f32 * self.day_width - self.new_event_margin,
+          strip_top + rel_y[1] * h,
+        );
+        let rect = Rect::from_min_max(min, max)
+          .translate(self.content_offset(widget_rect));
+        let margin = ui.style().visuals.clip_rect_margin / 2.0;
 
         Some(rect.shrink(margin))
       }
-      _ => unimplemented!(),
+    }
+  }
+
+  // the parts of an event on the days following its start day
+  fn event_continuation_rects(
+    &self,
+    ui: &Ui,
+    layout: &Layout,
+    event: &Event,
+  ) -> Vec<Rect> {
+    let (start, end) = match self.layout_type(event) {
+      EventLayoutType::Single(start, end) => (start, end),
+      EventLayoutType::AllDay(_) => return vec![],
+    };
+
+    let widget_rect = ui.max_rect();
+    let rel_x = layout.query(&event.id).unwrap_or([0.0, 1.0]);
+    let margin = ui.style().visuals.clip_rect_margin / 2.0;
+    let first_day = (start.floor() as i64 + 1).max(0);
+    let last_day = (end.ceil() as i64).min(self.day_count as i64);
+
+    (first_day..last_day)
+      .filter_map(|day| {
+        let y = [0.0, (end - day as f32).clamp(0.0, 1.0)];
+        let rect = self.layout_event(widget_rect, day as usize, y, rel_x);
+        (y[1] > 0.0).then(|| rect.shrink(margin))
+      })
+      .collect()
+  }
+
+  fn draw_event_continuations(&self, ui: &Ui, layout: &Layout, event: &Event) {
+    let visuals = &ui.visuals().widgets.inactive;
+    let rounding = self.event_rounding.unwrap_or(visuals.rounding);
+    let stroke = self.event_stroke.unwrap_or(visuals.bg_stroke);
+    for rect in self.event_continuation_rects(ui, layout, event) {
+      ui.painter()
+        .rect(rect, rounding, event_color(event), stroke);
+    }
+  }
+
+  fn event_text_color(&self, background: Color32) -> Color32 {
+    match self.event_text_color {
+      Some(color)
+        if contrast_ratio(color, background) >= self.min_text_contrast =>
+      {
+        color
+      }
+      _ => contrasting_text_color(background),
     }
   }
 
@@ -187,8 +794,8 @@ impl ScheduleUi {
   // These move_event_{start,end} functions aim to enforce few constraints:
   //
   // 1. event end must be later than event start
-  // 2. event duration must be at least self.min_event_duration long
-  // 3. event date can't be changed
+  // 2. event duration must be at least self.min_resize_duration long
+  // 3. event can't span across days
 
   fn pointer_pos_to_datetime(&self, rel_pos: Pos2) -> Option<DateTime> {
     let day = (rel_pos.x / self.day_width) as i64;
@@ -197,18 +804,18 @@ impl ScheduleUi {
     }
 
     let vert_pos = rel_pos.y / self.content_height();
-    if !(vert_pos > 0.0 && vert_pos < 1.0) {
+    if !(0.0..=1.0).contains(&vert_pos) {
       return None;
     }
 
-    let seconds = SECS_PER_DAY as f32 * vert_pos;
+    let seconds = self.y_to_secs(rel_pos.y);
     let seconds = ((seconds / 60.0).round() * 60.0) as i64;
 
     let date = self.first_day + Duration::days(day);
     let time = date.and_hms_opt(0, 0, 0).expect("date overflow")
       + Duration::seconds(seconds);
 
-    time.and_local_timezone(self.timezone).single()
+    self.resolve_local(time)
   }
 
   fn pointer_pos_to_datetime_snapping(
@@ -227,10 +834,12 @@ impl ScheduleUi {
       return None;
     }
 
-    let seconds = SECS_PER_DAY as f32 * vert_pos;
+    let seconds = self.y_to_secs(rel_pos.y);
     let mut snapped_seconds =
-      (seconds / self.snapping_duration.num_seconds() as f32).floor() as i64
-        * self.snapping_duration.num_seconds();
+      self.snap_to_tiers(seconds).unwrap_or_else(|| {
+        (seconds / self.snapping_duration.num_seconds() as f32).round() as i64
+          * self.snapping_duration.num_seconds()
+      });
 
     if snapped_seconds > SECS_PER_DAY as i64 {
       snapped_seconds = SECS_PER_DAY as i64;
@@ -239,7 +848,50 @@ impl ScheduleUi {
     let date = self.first_day + Duration::days(day);
     let time = date.and_hms_opt(0, 0, 0).expect("date overflow")
       + Duration::seconds(snapped_seconds);
-    time.and_local_timezone(self.timezone).single()
+    self.resolve_local(time)
+  }
+
+  // find the strongest tick within its tolerance
+  fn snap_to_tiers(&self, seconds: f32) -> Option<i64> {
+    self.snapping_tiers.iter().find_map(|tier| {
+      let interval = tier.interval.num_seconds() as f32;
+      if interval <= 0.0 {
+        return None;
+      }
+
+      let nearest = (seconds / interval).round() * interval;
+      let tolerance = tier.tolerance.num_seconds() as f32;
+      ((seconds - nearest).abs() <= tolerance).then_some(nearest as i64)
+    })
+  }
+
+  // the start or end of another event of the day nearest to the
+  // position and within magnet_distance, along with its distance
+  fn nearest_event_edge(
+    &self,
+    rel_pos: Pos2,
+    except: &EventId,
+  ) -> Option<(DateTime, f32)> {
+    if self.magnet_distance <= 0.0 {
+      return None;
+    }
+
+    let day = (rel_pos.x / self.day_width) as i64;
+    let date = self.first_day + Duration::days(day);
+    self
+      .events
+      .iter()
+      .filter(|e| &e.id != except && !e.deleted && !e.all_day)
+      .filter(|e| !self.in_hidden_calendar(e))
+      .flat_map(|e| [e.start, e.end])
+      .map(|time| self.normalize_time(&time))
+      .filter(|time| time.date_naive() == date)
+      .map(|time| {
+        let y = self.secs_to_y(time.num_seconds_from_midnight() as f32);
+        (time, (y - rel_pos.y).abs())
+      })
+      .filter(|(_, distance)| *distance <= self.magnet_distance)
+      .min_by(|a, b| a.1.total_cmp(&b.1))
   }
 
   fn snap_to_nearest(&self, time: &DateTime) -> DateTime {
@@ -252,9 +904,8 @@ impl ScheduleUi {
     let new_time =
       NaiveDateTime::from_timestamp_millis(snapped_timestamp * 1000)
         .expect("date overflow");
-    new_time
-      .and_local_timezone(self.timezone)
-      .single()
+    self
+      .resolve_local(new_time)
       .expect("timezone conversion error")
   }
 
@@ -264,16 +915,15 @@ impl ScheduleUi {
     } else {
       vec2(rect.width() / 4.0, 0.0)
     };
+    // short events get thinner resizers, keeping half of their height
+    // to grab them by
+    let resizer_height = self.resizer_height.min(rect.height() / 4.0);
+
     let mut upper_resizer = rect.shrink2(corner_area);
-    upper_resizer.set_height(self.resizer_height);
+    upper_resizer.set_height(resizer_height);
 
     let mut lower_resizer = rect.shrink2(corner_area);
-    lower_resizer.set_top(rect.bottom() - self.resizer_height);
-
-    if upper_resizer.intersects(lower_resizer) {
-      // overlaps, then we keep only the lower resizer
-      upper_resizer.set_height(0.0);
-    }
+    lower_resizer.set_top(rect.bottom() - resizer_height);
 
     [upper_resizer, lower_resizer]
   }
@@ -288,11 +938,81 @@ impl ScheduleUi {
   }
 
   fn draw_ticks(&self, ui: &mut Ui, rect: Rect) {
+    self.draw_weekends(ui, rect);
+    self.draw_off_hours(ui, rect);
+    self.draw_compressed_bands(ui, rect);
     self.draw_grid(ui, rect);
   }
 
+  fn draw_compressed_bands(&self, ui: &mut Ui, rect: Rect) {
+    let offset = self.content_offset(rect);
+    let painter = ui.painter_at(rect);
+    let visuals = ui.visuals();
+
+    for (band, label) in self.compressed_bands() {
+      let band = band.translate(offset);
+      painter.rect_filled(band, 0.0, visuals.faint_bg_color);
+      painter.text(
+        band.center(),
+        egui::Align2::CENTER_CENTER,
+        label,
+        egui::TextStyle::Small.resolve(ui.style()),
+        visuals.weak_text_color(),
+      );
+    }
+  }
+
+  fn draw_weekends(&self, ui: &mut Ui, rect: Rect) {
+    let offset = self.content_offset(rect);
+    let painter = ui.painter_at(rect);
+    let color = self
+      .weekend_background
+      .unwrap_or(ui.visuals().faint_bg_color);
+
+    for day in 0..self.day_count {
+      let date = self.first_day + Duration::days(day as i64);
+      if self.weekend_days.contains(&date.weekday()) {
+        let column = self.day_column(day).translate(offset);
+        painter.rect_filled(column, 0.0, color);
+      }
+    }
+  }
+
+  // shade the hours outside of the working hours
+  fn draw_off_hours(&self, ui: &mut Ui, rect: Rect) {
+    let working_hours = match &self.working_hours {
+      Some(working_hours) => working_hours,
+      None => return,
+    };
+
+    let offset = self.content_offset(rect);
+    let painter = ui.painter_at(rect);
+    let color = ui.visuals().faint_bg_color;
+
+    let y_of =
+      |t: NaiveTime| self.secs_to_y(t.num_seconds_from_midnight() as f32);
+    let (start, end) = (y_of(working_hours.start), y_of(working_hours.end));
+    let off_hours = if start <= end {
+      vec![(0.0, start), (end, self.content_height())]
+    } else {
+      vec![(end, start)]
+    };
+
+    let x1 = self.day_width * self.day_count as f32;
+    for (y0, y1) in off_hours {
+      if y1 <= y0 {
+        continue;
+      }
+
+      let rect = Rect::from_min_max(pos2(0.0, y0), pos2(x1, y1));
+      painter.rect_filled(rect.translate(offset), 0.0, color);
+    }
+  }
+
   fn draw_grid(&self, ui: &mut Ui, rect: Rect) {
-    let widget_visuals = ui.style().noninteractive();
+    let grid_stroke = self
+      .grid_stroke
+      .unwrap_or(ui.style().noninteractive().bg_stroke);
 
     let offset = self.content_offset(rect);
     let painter = ui.painter_at(rect);
@@ -300,54 +1020,155 @@ impl ScheduleUi {
     // vertical lines
     for day in 0..=self.day_count {
       let x = self.day_width * day as f32;
-      let y0 = 0.0;
-      let y1 = self.segment_height * self.segment_count as f32;
+      let y0 = -self.all_day_strip_height;
+      let y1 = self.content_height();
       let ends = [pos2(x, y0) + offset, pos2(x, y1) + offset];
 
-      painter.line_segment(ends, widget_visuals.bg_stroke);
+      painter.line_segment(ends, grid_stroke);
     }
 
-    // horizontal lines
-    for seg in 0..=self.segment_count {
-      let y = self.segment_height * seg as f32;
+    // separator between the all-day strip and the day headers
+    let x1 = self.day_width * self.day_count as f32;
+    let y = -self.all_day_strip_height;
+    let ends = [pos2(0.0, y) + offset, pos2(x1, y) + offset];
+    painter.line_segment(ends, grid_stroke);
+
+    // horizontal lines, along with the edges of the compressed bands
+    let segment_secs = SECS_PER_DAY as i64 / self.segment_count as i64;
+    let (band_top, band_bottom) = self.band_heights();
+    let band_edges = [
+      0.0,
+      band_top,
+      self.content_height() - band_bottom,
+      self.content_height(),
+    ];
+    let segment_lines = (0..=self.segment_count as i64)
+      .map(|seg| (seg * segment_secs) as f32)
+      .filter(|&secs| self.is_shown_secs(secs))
+      .map(|secs| self.secs_to_y(secs));
+    for y in segment_lines.chain(band_edges) {
       let x0 = 0.0;
       let x1 = self.day_width * self.day_count as f32;
       let ends = [pos2(x0, y) + offset, pos2(x1, y) + offset];
 
-      painter.line_segment(ends, widget_visuals.bg_stroke);
+      painter.line_segment(ends, grid_stroke);
+    }
+
+    // fainter lines for the snapping ticks within a segment
+    for (rank, tier) in self.snapping_tiers.iter().enumerate() {
+      let interval = tier.interval.num_seconds();
+      if interval <= 0 || interval >= segment_secs {
+        continue;
+      }
+
+      let mut stroke = grid_stroke;
+      stroke.color = stroke.color.linear_multiply(0.5 / (rank + 1) as f32);
+
+      let stronger_tiers = &self.snapping_tiers[..rank];
+      for n in 1..(SECS_PER_DAY as i64 / interval) {
+        let secs = n * interval;
+        let drawn = secs % segment_secs == 0
+          || stronger_tiers.iter().any(|t| {
+            t.interval.num_seconds() > 0 && secs % t.interval.num_seconds() == 0
+          });
+        if drawn || !self.is_shown_secs(secs as f32) {
+          continue;
+        }
+
+        let y = self.secs_to_y(secs as f32);
+        let x1 = self.day_width * self.day_count as f32;
+        let ends = [pos2(0.0, y) + offset, pos2(x1, y) + offset];
+        painter.line_segment(ends, stroke);
+      }
     }
   }
 
   fn scroll_horizontally(&mut self, days: i64) {
+    let days = match self.view_mode {
+      ViewMode::Day => days,
+      // scroll by whole weeks
+      ViewMode::Week => days.signum() * 7 * (days.abs() / 7).max(1),
+    };
     self.first_day += Duration::days(days);
     self.mark_scope_updated();
   }
 
+  // show the days around today, scrolled to the current time
+  pub fn goto_today(&mut self) {
+    self.first_day = self.first_day_for(self.today());
+    self.mark_scope_updated();
+    self.scroll_to_now();
+  }
+
+  // a day forward, or a week in the week view
+  pub fn next_period(&mut self) {
+    self.scroll_horizontally(1);
+  }
+
+  pub fn prev_period(&mut self) {
+    self.scroll_horizontally(-1);
+  }
+
+  // the first visible day for showing the given date
+  fn first_day_for(&self, date: Date) -> Date {
+    match self.view_mode {
+      ViewMode::Day => date - Duration::days(self.day_count as i64 / 2),
+      ViewMode::Week => {
+        let days_since_start = (7 + date.weekday().num_days_from_monday()
+          - self.week_start.num_days_from_monday())
+          % 7;
+        date - Duration::days(days_since_start as i64)
+      }
+    }
+  }
+
+  fn set_view_mode(&mut self, view_mode: ViewMode) {
+    if self.view_mode == view_mode {
+      return;
+    }
+
+    let center = self.first_day + Duration::days(self.day_count as i64 / 2);
+    self.view_mode = view_mode;
+    self.refit();
+    self.first_day = self.first_day_for(center);
+    self.mark_scope_updated();
+  }
+
   fn draw_current_time_indicator(&self, ui: &mut Ui, rect: Rect, alpha: f32) {
     let widget_visuals = ui.style().noninteractive();
     let painter = ui.painter_at(rect);
     let offset = self.content_offset(rect);
 
-    if let Some(now) = self.current_time.as_ref() {
-      let y = self.day_progress(now) * self.content_height();
-      let x0 = 0.0;
-      let x1 = rect.width();
+    let now = match self.current_time.as_ref() {
+      Some(now) if self.is_visible(now) => now,
+      _ => return,
+    };
 
-      let p0 = pos2(x0, y) + offset;
-      let p1 = pos2(x1, y) + offset;
-      let mut indicator_stroke = widget_visuals.bg_stroke;
-      indicator_stroke.color = Color32::RED.linear_multiply(alpha);
-      painter.line_segment([p0, p1], indicator_stroke);
-    }
+    let color = self.now_indicator_color.linear_multiply(alpha);
+    let y = self.day_progress(now) * self.content_height();
+    let x0 = 0.0;
+    let x1 = self.content_width();
+
+    let p0 = pos2(x0, y) + offset;
+    let p1 = pos2(x1, y) + offset;
+    let mut indicator_stroke = widget_visuals.bg_stroke;
+    indicator_stroke.color = color;
+    painter.line_segment([p0, p1], indicator_stroke);
+
+    // a small marker pointing at the line from the time gutter
+    let size = 5.0;
+    let marker = vec![p0 - vec2(size, size), p0 - vec2(size, -size), p0];
+    painter.add(egui::Shape::convex_polygon(
+      marker,
+      color,
+      egui::Stroke::NONE,
+    ));
   }
 
   fn time_mark_region(&self) -> Rect {
     Rect::from_min_size(
-      pos2(0.0, self.day_header_margin_height),
-      vec2(
-        self.time_marker_margin_width,
-        self.segment_height * self.segment_count as f32,
-      ),
+      pos2(0.0, self.content_top()),
+      vec2(self.time_marker_margin_width, self.content_height()),
     )
   }
 
@@ -381,7 +1202,12 @@ impl ScheduleUi {
     );
 
     for seg in 0..=self.segment_count {
-      let y = offset.y + seg as f32 * self.segment_height;
+      let secs = (SECS_PER_DAY as usize / self.segment_count * seg) as f32;
+      if !self.is_shown_secs(secs) {
+        continue;
+      }
+
+      let y = offset.y + self.secs_to_y(secs);
       let x = time_mark_region.center().x;
 
       let text = self.time_marker_text(seg).expect("segment out of bound");
@@ -453,21 +1279,197 @@ impl ScheduleUi {
         let mut stroke = widget_visuals.bg_stroke;
         stroke.color = stroke.color.linear_multiply(alpha);
 
-        painter.circle(
-          text_rect.center_bottom() + vec2(0.0, 6.0),
-          2.0,
-          Color32::RED.linear_multiply(alpha),
-          stroke,
-        );
+        painter.circle(
+          text_rect.center_bottom() + vec2(0.0, 6.0),
+          2.0,
+          self.now_indicator_color.linear_multiply(alpha),
+          stroke,
+        );
+      }
+    }
+
+    if let Some(text) = self.week_number_text() {
+      let x = day_mark_region.left() - self.time_marker_margin_width / 2.0;
+      painter.text(
+        pos2(x, day_mark_region.center().y),
+        egui::Align2::CENTER_CENTER,
+        text,
+        egui::TextStyle::Monospace.resolve(ui.style()),
+        widget_visuals.text_color().linear_multiply(alpha),
+      );
+    }
+  }
+
+  // e.g. "W12", or "W12-13" when the shown days span several weeks
+  fn week_number_text(&self) -> Option<String> {
+    if !self.show_week_numbers {
+      return None;
+    }
+
+    // weeks starting on another day than monday are numbered after the
+    // ISO week of their monday
+    let shift = (7 - self.week_start.num_days_from_monday() as i64) % 7;
+    let week_of = |date: Date| (date + Duration::days(shift)).iso_week().week();
+    let first = week_of(self.first_day);
+    let last =
+      week_of(self.first_day + Duration::days(self.day_count as i64 - 1));
+
+    if first == last {
+      Some(format!("W{first}"))
+    } else {
+      Some(format!("W{first}-{last}"))
+    }
+  }
+
+  // zoom the time axis with ctrl-scroll, keeping the time under the
+  // pointer in place
+  fn handle_zoom(&mut self, ui: &Ui) {
+    let zoom = ui.input(|input| input.zoom_delta());
+    if zoom == 1.0 {
+      return;
+    }
+
+    let pointer = match ui.input(|input| input.pointer.hover_pos()) {
+      Some(pointer) => pointer,
+      None => return,
+    };
+
+    let content_top = ui.cursor().min.y + self.content_top();
+    let y = pointer.y - content_top;
+    if !(0.0..=self.content_height()).contains(&y) {
+      return;
+    }
+
+    let old_height = self.segment_height;
+    self.segment_height = (old_height * zoom)
+      .clamp(self.min_segment_height, self.max_segment_height);
+
+    let shift = y * (self.segment_height / old_height - 1.0);
+    ui.scroll_with_delta(vec2(0.0, -shift));
+  }
+
+  // two fingers pan the view as one finger drags the events, pinching
+  // zooms like ctrl-scroll
+  fn handle_touch_scroll(&self, ui: &Ui) {
+    if let Some(touch) = ui.input(|input| input.multi_touch()) {
+      ui.scroll_with_delta(touch.translation_delta);
+    }
+  }
+
+  fn content_height(&self) -> f32 {
+    let (start, end) = self.shown_secs();
+    let (band_top, band_bottom) = self.band_heights();
+    band_top + (end - start) * self.points_per_sec() + band_bottom
+  }
+
+  // the height of a second when shown at full height
+  fn points_per_sec(&self) -> f32 {
+    self.segment_height * self.segment_count as f32 / SECS_PER_DAY as f32
+  }
+
+  // the part of the day shown at full height, in seconds past midnight
+  fn shown_secs(&self) -> (f32, f32) {
+    match &self.visible_time_range {
+      Some(range) if !self.all_hours_shown && range.start < range.end => (
+        range.start.num_seconds_from_midnight() as f32,
+        range.end.num_seconds_from_midnight() as f32,
+      ),
+      _ => (0.0, SECS_PER_DAY as f32),
+    }
+  }
+
+  fn is_shown_secs(&self, secs: f32) -> bool {
+    let (start, end) = self.shown_secs();
+    (start..=end).contains(&secs)
+  }
+
+  // the heights of the bands the hours before and after the shown ones
+  // are compressed into
+  fn band_heights(&self) -> (f32, f32) {
+    let (start, end) = self.shown_secs();
+    let height = |compressed: bool| {
+      if compressed {
+        self.compressed_band_height
+      } else {
+        0.0
       }
+    };
+    (height(start > 0.0), height(end < SECS_PER_DAY as f32))
+  }
+
+  // The mapping between the seconds past midnight and the distance
+  // from the top of the day columns, all conversions between times and
+  // positions go through it and its reverse.
+  fn secs_to_y(&self, secs: f32) -> f32 {
+    let (start, end) = self.shown_secs();
+    let (band_top, band_bottom) = self.band_heights();
+    let secs = secs.clamp(0.0, SECS_PER_DAY as f32);
+
+    if secs < start {
+      secs / start * band_top
+    } else if secs <= end {
+      band_top + (secs - start) * self.points_per_sec()
+    } else {
+      let compressed_secs = SECS_PER_DAY as f32 - end;
+      band_top
+        + (end - start) * self.points_per_sec()
+        + (secs - end) / compressed_secs * band_bottom
     }
   }
 
-  fn content_height(&self) -> f32 {
-    self.segment_height * self.segment_count as f32
+  fn y_to_secs(&self, y: f32) -> f32 {
+    let (start, end) = self.shown_secs();
+    let (band_top, band_bottom) = self.band_heights();
+    let y = y.clamp(0.0, self.content_height());
+    let shown_bottom = band_top + (end - start) * self.points_per_sec();
+
+    if y < band_top {
+      y / band_top * start
+    } else if y <= shown_bottom {
+      start + (y - band_top) / self.points_per_sec()
+    } else {
+      let compressed_secs = SECS_PER_DAY as f32 - end;
+      end + (y - shown_bottom) / band_bottom * compressed_secs
+    }
+  }
+
+  // the bands of compressed hours relative to the top left of the day
+  // columns, along with their labels
+  fn compressed_bands(&self) -> Vec<(Rect, &'static str)> {
+    let (band_top, band_bottom) = self.band_heights();
+    let width = self.content_width();
+    let height = self.content_height();
+
+    let mut bands = vec![];
+    if band_top > 0.0 {
+      let band = Rect::from_min_size(Pos2::ZERO, vec2(width, band_top));
+      bands.push((band, "▲ earlier"));
+    }
+    if band_bottom > 0.0 {
+      let band = Rect::from_min_max(
+        pos2(0.0, height - band_bottom),
+        pos2(width, height),
+      );
+      bands.push((band, "▼ later"));
+    }
+    bands
+  }
+
+  // clicking a band of compressed hours shows the whole day, until
+  // unchecked in the context menu
+  fn handle_compressed_bands(&mut self, ui: &Ui, rect: Rect) {
+    let offset = self.content_offset(rect);
+    for (n, (band, _)) in self.compressed_bands().into_iter().enumerate() {
+      let id = ui.id().with("compressed_band").with(n);
+      let response = ui
+        .interact(band.translate(offset), id, Sense::click())
+        .on_hover_text("Show all hours");
+      if response.clicked() {
+        self.all_hours_shown = true;
+      }
+    }
   }
 
-  #[allow(unused)]
   fn content_width(&self) -> f32 {
     self.day_width * self.day_count as f32
   }
@@ -477,7 +1479,12 @@ impl ScheduleUi {
   }
 
   fn content_offset0(&self) -> Vec2 {
-    vec2(self.time_marker_margin_width, self.day_header_margin_height)
+    vec2(self.time_marker_margin_width, self.content_top())
+  }
+
+  // the distance between the widget top and the beginning of the day
+  fn content_top(&self) -> f32 {
+    self.day_header_margin_height + self.all_day_strip_height
   }
 
   fn day_column(&self, day: usize) -> Rect {
@@ -507,7 +1514,8 @@ impl ScheduleUi {
     }
 
     let time = self.time_marker_time(segment, 0).unwrap();
-    let formatted_time = time.format(self.time_marker_format);
+    let format = self.time_format.apply(self.time_marker_format);
+    let formatted_time = time.format(&format);
 
     Some(format!("{formatted_time}"))
   }
@@ -528,7 +1536,7 @@ impl ScheduleUi {
       Some(day.and_time(offset))
     };
 
-    naive_time.and_then(|t| t.and_local_timezone(self.timezone).single())
+    naive_time.and_then(|t| self.resolve_local(t))
   }
 
   fn desired_size(&self, ui: &Ui) -> Vec2 {
@@ -542,8 +1550,8 @@ impl ScheduleUi {
       self.time_marker_margin_width
         + self.day_width * self.day_count as f32
         + clip_margin,
-      self.day_header_margin_height
-        + self.segment_height * self.segment_count as f32
+      self.content_top()
+        + self.content_height()
         + text_safe_margin
         + clip_margin,
     )
@@ -552,8 +1560,28 @@ impl ScheduleUi {
   pub(crate) fn show_ui(&mut self, ui: &mut Ui) {
     let rect = ui.max_rect();
     let interacting_event = self.get_interacting_event(ui);
-    let combined_events: Vec<CombinedEvent> =
+    let mut combined_events: Vec<CombinedEvent> =
       combine_events(&self.events, interacting_event);
+    if self.hide_cancelled {
+      combined_events.retain(|e| {
+        matches!(e, CombinedEvent::InteractingEvent(_))
+          || e.event().status != Some(EventStatus::Cancelled)
+      });
+    }
+    combined_events.retain(|e| {
+      matches!(e, CombinedEvent::InteractingEvent(_))
+        || !self.in_hidden_categories(e.event())
+    });
+    combined_events.retain(|e| !self.in_hidden_calendar(e.event()));
+    if self.transparent_events == TransparentEvents::Hidden {
+      combined_events.retain(|e| {
+        matches!(e, CombinedEvent::InteractingEvent(_))
+          || !e.event().transparent
+      });
+    }
+
+    // the events of crowded days left out, counted by day
+    let overflow = self.cap_events_per_day(&mut combined_events);
 
     // get response at empty area first (other widgets will steal it)
     let response_on_empty_area = ui.interact(
@@ -562,6 +1590,8 @@ impl ScheduleUi {
       Sense::click_and_drag(),
     );
 
+    self.handle_compressed_bands(ui, rect);
+
     // background: ticks and current time indicator
     self.draw_ticks(ui, rect);
     self.draw_current_time_indicator(ui, rect, 1.0);
@@ -574,7 +1604,12 @@ impl ScheduleUi {
         .as_slice(),
     );
 
-    // main: event buttons
+    // main: event buttons, those of higher priority drawn on top, and
+    // the focused one above all so that alt-click can bring it up
+    combined_events.sort_by_key(|e| {
+      let focused = self.focused_event.as_ref() == Some(e.event_id());
+      (focused, e.event().priority_rank())
+    });
     for combined_event in combined_events {
       match combined_event {
         CombinedEvent::ExistingEvent(event) => {
@@ -585,6 +1620,8 @@ impl ScheduleUi {
         }
       }
     }
+    self.start_pending_interaction(ui);
+    self.show_overflow_chips(ui, rect, &overflow);
 
     // floating: time and day headers
     self.draw_day_marks(ui, rect);
@@ -597,10 +1634,28 @@ impl ScheduleUi {
     self.refocus_edited_event(ui);
     self.handle_hotkeys(ui);
     self.handle_undo(ui);
+
+    self.handle_dropped_files(ui);
+    self.show_summary(ui);
   }
 
   pub(crate) fn show(&mut self, ui: &mut Ui) {
+    self.emitted_events.clear();
+
+    // zoom before allocating so the new size takes effect immediately
+    self.handle_zoom(ui);
+    self.handle_touch_scroll(ui);
+
     let (_id, rect) = ui.allocate_space(self.desired_size(ui));
+    self.scroll_offset = (ui.clip_rect().top() - rect.top()).max(0.0);
+
+    if let Some(offset) = self.restore_scroll_offset.take() {
+      let top = Rect::from_min_size(rect.min + vec2(0.0, offset), Vec2::ZERO);
+      ui.scroll_to_rect(top, Some(egui::Align::TOP));
+    } else if std::mem::take(&mut self.scroll_to_now_requested) {
+      self.scroll_to_time(ui, rect, &self.now());
+    }
+    self.animate_scroll(ui, rect);
 
     if !ui.is_rect_visible(rect) {
       return;
@@ -616,18 +1671,27 @@ impl ScheduleUi {
 
     // commit any event changes
     self.apply_interacting_events(ui);
-    remove_empty_events(&mut self.events);
+    let now = self.now();
+    remove_empty_events(&mut self.events, now);
+    self.track_focused_event(ui);
+  }
+
+  // the events of the last frame, to be read after show()
+  pub fn emitted_events(&self) -> &[ScheduleEvent] {
+    &self.emitted_events
+  }
+
+  pub fn focused_event(&self) -> Option<&EventId> {
+    self.focused_event.as_ref()
   }
 
   pub fn time_range(&self) -> (DateTime, DateTime) {
-    let start = self
-      .first_day
-      .and_hms_opt(0, 0, 0)
-      .expect("date overflow")
-      .and_local_timezone(self.timezone)
-      .single()
-      .expect("date overflow");
-    let end = start + chrono::Duration::days(self.day_count as i64);
+    let midnight = |date: Date| {
+      let time = date.and_hms_opt(0, 0, 0).expect("date overflow");
+      self.resolve_local(time).expect("date overflow")
+    };
+    let start = midnight(self.first_day);
+    let end = midnight(self.first_day + Duration::days(self.day_count as i64));
 
     (start, end)
   }
@@ -641,16 +1705,179 @@ impl ScheduleUi {
     day.num_days() >= 0 && day.num_days() < self.day_count as i64
   }
 
+  // the shown events in a single calendar
+  pub fn export_ics(&self) -> Option<String> {
+    let events: Vec<Event> = self
+      .events
+      .iter()
+      .filter(|e| {
+        !(self.hide_cancelled && e.status == Some(EventStatus::Cancelled))
+      })
+      .filter(|e| !self.in_hidden_categories(e))
+      .filter(|e| !self.in_hidden_calendar(e))
+      .filter(|e| {
+        !(self.transparent_events == TransparentEvents::Hidden && e.transparent)
+      })
+      .cloned()
+      .collect();
+
+    ICal.generate_calendar(&events)
+  }
+
+  pub fn save_state(&self) -> ViewState {
+    ViewState {
+      first_day: self.first_day,
+      view_mode: self.view_mode,
+      segment_height: self.segment_height,
+      scroll_offset: self.scroll_offset,
+      hidden_calendars: self.hidden_calendars.clone(),
+    }
+  }
+
+  pub fn load_state(&mut self, state: ViewState) {
+    self.set_view_mode(state.view_mode);
+    self.first_day = state.first_day;
+    self.segment_height = state
+      .segment_height
+      .clamp(self.min_segment_height, self.max_segment_height);
+    self.restore_scroll_offset = Some(state.scroll_offset);
+    self.hidden_calendars = state.hidden_calendars;
+    self.scroll_to_now_requested = false;
+    self.scroll_animation = None;
+    self.mark_scope_updated();
+  }
+
   pub fn load_events(&mut self, events: Vec<Event>) {
     // avoid new events interfering with history
     self.history.clear();
     self.events = events;
+    self.invalidate_event_counts();
+  }
+
+  // the events changed in the backend, e.g. they were saved
+  pub fn invalidate_event_counts(&mut self) {
+    if let Some(calendar) = &mut self.calendar {
+      calendar.invalidate_counts();
+    }
+  }
+
+  // the number of events by day for the calendar
+  pub fn load_event_counts(&mut self, counts: HashMap<Date, usize>) {
+    if let Some(calendar) = &mut self.calendar {
+      calendar.set_event_counts(counts);
+    }
   }
 
   pub fn events_mut(&mut self) -> &mut Vec<Event> {
     &mut self.events
   }
 
+  pub fn set_filter(&mut self, filter: Option<String>) {
+    // a blank filter matches everything
+    self.filter = filter.filter(|f| !f.trim().is_empty());
+  }
+
+  // case-insensitive search in the title, location and description
+  fn matches_filter(&self, event: &Event) -> bool {
+    let filter = match &self.filter {
+      Some(filter) => filter.trim().to_lowercase(),
+      None => return true,
+    };
+
+    [
+      Some(&event.title),
+      event.location.as_ref(),
+      event.description.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .any(|text| text.to_lowercase().contains(&filter))
+  }
+
+  fn in_hidden_categories(&self, event: &Event) -> bool {
+    !event.categories.is_empty()
+      && event
+        .categories
+        .iter()
+        .all(|c| self.hidden_categories.contains(c))
+  }
+
+  // whether the event is loaded and drawn in the shown days
+  fn is_shown(&self, event_id: &EventId) -> bool {
+    self.events.iter().any(|e| {
+      &e.id == event_id
+        && !e.deleted
+        && self.is_visible(&e.start)
+        && !self.in_hidden_calendar(e)
+    })
+  }
+
+  fn in_hidden_calendar(&self, event: &Event) -> bool {
+    self.hidden_calendars.contains(&event.calendar)
+  }
+
+  pub fn set_calendar_shown(&mut self, calendar: &str, shown: bool) {
+    if shown {
+      self.hidden_calendars.remove(calendar);
+    } else {
+      self.hidden_calendars.insert(calendar.to_string());
+    }
+  }
+
+  // a checkbox per calendar of the loaded events, when they come from
+  // more than one
+  fn show_calendar_toggles(&mut self, ui: &mut Ui) {
+    let calendars: BTreeSet<String> = self
+      .events
+      .iter()
+      .map(|event| event.calendar.clone())
+      .chain(self.hidden_calendars.iter().cloned())
+      .collect();
+    if calendars.len() < 2 {
+      return;
+    }
+
+    ui.label("Calendars:");
+    for calendar in calendars {
+      let mut shown = !self.hidden_calendars.contains(&calendar);
+      if ui.checkbox(&mut shown, calendar.as_str()).changed() {
+        self.set_calendar_shown(&calendar, shown);
+      }
+    }
+    ui.separator();
+  }
+
+  // a chip per category of the loaded events, toggling whether they
+  // are shown
+  fn show_category_chips(&mut self, ui: &mut Ui) {
+    let categories: BTreeSet<&String> = self
+      .events
+      .iter()
+      .flat_map(|event| event.categories.iter())
+      .collect();
+    if categories.is_empty() {
+      return;
+    }
+
+    let mut toggled = None;
+    ui.label("Categories:");
+    ui.horizontal_wrapped(|ui| {
+      for category in categories {
+        let shown = !self.hidden_categories.contains(category);
+        if ui.selectable_label(shown, category.as_str()).clicked() {
+          toggled = Some(category.clone());
+        }
+      }
+    });
+
+    if let Some(category) = toggled {
+      if !self.hidden_categories.remove(&category) {
+        self.hidden_categories.insert(category);
+      }
+    }
+    ui.separator();
+  }
+
   fn mark_scope_updated(&mut self) {
     self.scope_updated = true;
 
@@ -666,6 +1893,10 @@ impl ScheduleUi {
         ui.label("Refreshing events...");
         ui.close_menu();
       }
+      if ui.button("Export to .ics").clicked() {
+        self.export_requested = true;
+        ui.close_menu();
+      }
       ui.separator();
 
       ui.horizontal(|ui| {
@@ -673,15 +1904,13 @@ impl ScheduleUi {
           self.scroll_horizontally(-(self.day_count as i64));
         }
         if ui.button("<").clicked() {
-          self.scroll_horizontally(-1);
+          self.prev_period();
         }
         if ui.button("Today").clicked() {
-          self.first_day =
-            today(&self.timezone) - Duration::days(self.day_count as i64 / 2);
-          self.mark_scope_updated();
+          self.goto_today();
         }
         if ui.button(">").clicked() {
-          self.scroll_horizontally(1);
+          self.next_period();
         }
         if ui.button(">>").clicked() {
           self.scroll_horizontally(self.day_count as i64);
@@ -689,6 +1918,52 @@ impl ScheduleUi {
       });
       ui.separator();
 
+      if !self.read_only {
+        ui.horizontal(|ui| {
+          ui.label("Quick add");
+          let resp = ui.text_edit_singleline(&mut self.quick_add_input);
+          let entered = ui.input(|input| input.key_pressed(egui::Key::Enter));
+          if resp.lost_focus() && entered {
+            self.quick_add();
+          }
+        });
+        ui.menu_button("Delete all shown events", |ui| {
+          if ui.button("Delete").clicked() {
+            self.range_deletion_requested = Some(self.time_range());
+            ui.close_menu();
+          }
+        });
+        ui.separator();
+      }
+
+      ui.horizontal(|ui| {
+        let mut filter = self.filter.clone().unwrap_or_default();
+        ui.label("Filter");
+        ui.text_edit_singleline(&mut filter);
+        if ui.button("Clear").clicked() {
+          filter.clear();
+        }
+        self.set_filter(Some(filter));
+      });
+      ui.separator();
+
+      ui.checkbox(&mut self.hide_cancelled, "Hide cancelled events");
+      if self.visible_time_range.is_some() {
+        ui.checkbox(&mut self.all_hours_shown, "Show all hours");
+      }
+      ui.separator();
+
+      self.show_calendar_toggles(ui);
+      self.show_category_chips(ui);
+
+      ui.horizontal(|ui| {
+        let mut view_mode = self.view_mode;
+        ui.selectable_value(&mut view_mode, ViewMode::Day, "Days");
+        ui.selectable_value(&mut view_mode, ViewMode::Week, "Week");
+        self.set_view_mode(view_mode);
+      });
+      ui.separator();
+
       self.show_calendar(ui);
       ui.separator();
 
@@ -698,6 +1973,178 @@ impl ScheduleUi {
     });
   }
 
+  // add the event described in the quick-add field and show it
+  fn quick_add(&mut self) {
+    let event = parse_quick_add(
+      &self.quick_add_input,
+      self.now(),
+      self.default_event_duration,
+    );
+    let mut event = match event {
+      Some(event) => event,
+      // keep the text for the user to correct
+      None => return,
+    };
+    self.quick_add_input.clear();
+
+    event.calendar = self.new_event_calendar.clone();
+    let start = event.start;
+    self.add_event(event);
+
+    if !self.is_visible(&start) {
+      self.first_day = self.first_day_for(start.date_naive());
+      self.mark_scope_updated();
+    }
+  }
+
+  // import the events of the .ics files dropped onto the window
+  fn handle_dropped_files(&mut self, ui: &Ui) {
+    let files = ui.input(|input| input.raw.dropped_files.clone());
+    if files.is_empty() {
+      return;
+    }
+    if self.read_only {
+      self.summary = Some(("Import", "The calendar is read-only".into()));
+      return;
+    }
+
+    let mut imported = vec![];
+    for file in files {
+      let content = match (&file.bytes, &file.path) {
+        (Some(bytes), _) => String::from_utf8(bytes.to_vec()).ok(),
+        (None, Some(path)) => std::fs::read_to_string(path).ok(),
+        (None, None) => None,
+      };
+      let events = content.and_then(|content| {
+        ICal
+          .parse(&self.new_event_calendar, &content)
+          .map_err(|e| log::warn!("Failed importing {}: {e:#}", file.name))
+          .ok()
+      });
+      match events {
+        Some(events) => imported.extend(events),
+        None => self.import_failures += 1,
+      }
+    }
+
+    if imported.is_empty() {
+      self.import_events(vec![], HashMap::new());
+    } else {
+      self
+        .import_requested
+        .get_or_insert_with(Vec::new)
+        .extend(imported);
+    }
+  }
+
+  // Import the dropped events given the stored versions of their
+  // series. A series is imported along with its overrides, and only
+  // replaces the version shown or stored if it's newer. Events without
+  // LAST-MODIFIED never replace one.
+  pub fn import_events(
+    &mut self,
+    events: Vec<Event>,
+    stored: HashMap<EventId, Event>,
+  ) {
+    let mut by_series: BTreeMap<EventId, Vec<Event>> = BTreeMap::new();
+    for mut event in events {
+      if event.id.is_empty() {
+        event.id = new_event_id();
+      }
+      let series_id = recurrence::series_id(&event);
+      by_series.entry(series_id).or_default().push(event);
+    }
+
+    let (mut imported, mut skipped) = (0, 0);
+    for (series_id, events) in by_series {
+      let count = events.len();
+      if self.import_series(&series_id, events, stored.get(&series_id)) {
+        imported += count;
+      } else {
+        skipped += count;
+      }
+    }
+
+    let failed = std::mem::take(&mut self.import_failures);
+    let mut summary = format!("Imported {imported} events");
+    if skipped > 0 {
+      summary += &format!(", skipped {skipped} already up to date");
+    }
+    if failed > 0 {
+      summary += &format!(", failed reading {failed} files");
+    }
+    self.summary = Some(("Import", summary));
+  }
+
+  // the host deleted the events of range_deletion_requested
+  pub fn deleted_events_in_range(&mut self, deleted: usize, failed: usize) {
+    let mut summary = format!("Deleted {deleted} events");
+    if failed > 0 {
+      summary += &format!(", failed deleting {failed}");
+    }
+    self.summary = Some(("Delete", summary));
+    self.invalidate_event_counts();
+    self.mark_scope_updated();
+  }
+
+  // returns whether the events got added
+  fn import_series(
+    &mut self,
+    series_id: &EventId,
+    events: Vec<Event>,
+    stored: Option<&Event>,
+  ) -> bool {
+    let shown: Vec<_> = self
+      .events
+      .iter()
+      .filter(|e| &recurrence::series_id(e) == series_id)
+      .collect();
+    let existing = !shown.is_empty() || stored.is_some();
+    let last_modified = shown
+      .into_iter()
+      .chain(stored)
+      .filter(|e| e.modified_at_known)
+      .map(|e| e.modified_at)
+      .max();
+
+    let modified_at = events
+      .iter()
+      .all(|e| e.modified_at_known)
+      .then(|| events.iter().map(|e| e.modified_at).max())
+      .flatten();
+    let newer = match (modified_at, last_modified) {
+      (None, _) => !existing,
+      (Some(t), Some(last_modified)) => t > last_modified,
+      (Some(_), None) => true,
+    };
+    if !newer {
+      return false;
+    }
+
+    for mut event in events {
+      event.set_timezone(&self.offset_at(&event.start));
+      self.add_event(event);
+    }
+    true
+  }
+
+  fn show_summary(&mut self, ui: &Ui) {
+    let (title, summary) = match &self.summary {
+      Some(summary) => summary.clone(),
+      None => return,
+    };
+
+    egui::Window::new(title)
+      .collapsible(false)
+      .resizable(false)
+      .show(ui.ctx(), |ui| {
+        ui.label(summary);
+        if ui.button("OK").clicked() {
+          self.summary = None;
+        }
+      });
+  }
+
   fn show_calendar(&mut self, ui: &mut Ui) {
     use super::CalendarAction::*;
 
@@ -708,62 +2155,86 @@ impl ScheduleUi {
       CalendarBuilder::default()
         .date(self.first_day + Duration::days(self.day_count as i64 / 2))
         .current_date(default_date)
-        .weekday_offset(1)
+        .weekday_offset(self.week_start.num_days_from_sunday() as usize)
         .highlight_dates(visible_dates)
         .build()
         .unwrap()
     });
 
-    match calendar.show_ui(ui) {
+    let action = calendar.show_ui(ui);
+    if let Some(dates) = calendar.take_counts_request() {
+      let midnight = |date: Date| date.and_hms_opt(0, 0, 0);
+      let from = midnight(dates.start).and_then(|t| self.resolve_local(t));
+      let to = midnight(dates.end).and_then(|t| self.resolve_local(t));
+      if let (Some(from), Some(to)) = (from, to) {
+        self.event_counts_requested = Some((from, to));
+      }
+    }
+
+    match action {
       None => (),
       Some(DateClicked(date)) => {
-        self.first_day = date - Duration::days(self.day_count as i64 / 2);
+        self.first_day = self.first_day_for(date);
         self.mark_scope_updated();
       }
     }
   }
 
   fn new_event(&self) -> Event {
-    let color = egui::Rgba::from(self.new_event_color);
     let start = self
-      .first_day
-      .and_time(Default::default())
-      .and_local_timezone(self.timezone)
-      .single()
+      .resolve_local(self.first_day.and_time(Default::default()))
       .expect("timezone conversion error");
-    let end = start + self.min_event_duration;
+    let end = start + self.default_event_duration;
     let mut event = EventBuilder::default()
       .id(new_event_id())
       .calendar(self.new_event_calendar.as_str())
-      .title("")
+      .title(self.default_event_title.as_str())
       .description(None)
       .start(start)
       .end(end)
-      .timestamp(now(&self.timezone))
-      .created_at(now(&self.timezone))
-      .modified_at(now(&self.timezone))
-      .color([color.r(), color.g(), color.b()])
+      .timestamp(self.now())
+      .created_at(self.now())
+      .modified_at(self.now())
       .build()
       .unwrap();
 
-    event.mark_changed();
+    event.mark_created(self.now());
     event
   }
 
   fn normalize_time(&self, time: &DateTime) -> DateTime {
-    time.with_timezone(&self.timezone)
+    time.with_timezone(&self.offset_at(time))
+  }
+
+  // the offset shown for the time, which changes with daylight saving
+  // time if the zone is known
+  fn offset_at(&self, time: &DateTime) -> FixedOffset {
+    match self.zone {
+      Some(zone) => zone.offset_at(time),
+      None => self.timezone,
+    }
+  }
+
+  fn resolve_local(&self, time: NaiveDateTime) -> Option<DateTime> {
+    match self.zone {
+      Some(zone) => zone.resolve_local(time),
+      None => time.and_local_timezone(self.timezone).single(),
+    }
   }
 
   fn clone_to_new_event(&self, event: &Event) -> Event {
     let mut new_event = event.clone();
     new_event.id = new_event_id();
-    new_event.mark_changed();
+    // a copy of an occurrence doesn't belong to the series
+    new_event.recurrence_id = None;
+    new_event.sequence = 0;
+    new_event.mark_created(self.now());
     new_event
   }
 
   fn pointer_to_datetime_auto(&self, ui: &Ui, pos: Pos2) -> Option<DateTime> {
-    if ui.input(|input| input.modifiers.shift_only()) {
-      // no snapping when shift is held down
+    if ui.input(|input| input.modifiers.shift_only() || input.modifiers.alt) {
+      // no snapping when shift or alt is held down
       self.pointer_pos_to_datetime(pos)
     } else {
       // enable snapping otherwise
@@ -771,6 +2242,47 @@ impl ScheduleUi {
     }
   }
 
+  // The start time of an event of the duration with its start at the
+  // position. Its start or end sticks to the nearest edge of another
+  // event close enough, in preference to the grid, unless alt is held
+  // down. The duration is zero when resizing, only the start is used.
+  fn pointer_to_datetime_magnetic(
+    &self,
+    ui: &Ui,
+    pos: Pos2,
+    event_id: &EventId,
+    duration: Duration,
+  ) -> Option<DateTime> {
+    if ui.input(|input| input.modifiers.alt) {
+      return self.pointer_to_datetime_auto(ui, pos);
+    }
+
+    let start_edge = self.nearest_event_edge(pos, event_id);
+    let end_edge = match self.pointer_pos_to_datetime(pos) {
+      Some(start) if !duration.is_zero() => {
+        let start = self.normalize_time(&start);
+        let end_secs =
+          start.num_seconds_from_midnight() as i64 + duration.num_seconds();
+        let end_pos = pos2(pos.x, self.secs_to_y(end_secs as f32));
+        self
+          .nearest_event_edge(end_pos, event_id)
+          .map(|(end, distance)| (end - duration, distance))
+      }
+      _ => None,
+    };
+
+    let nearest = match (start_edge, end_edge) {
+      (Some(start), Some(end)) => {
+        Some(if end.1 < start.1 { end } else { start })
+      }
+      (start, end) => start.or(end),
+    };
+    match nearest {
+      Some((time, _)) => Some(time),
+      None => self.pointer_to_datetime_auto(ui, pos),
+    }
+  }
+
   // Need to ensure the ui's max_rect is the rect allocated for the
   // whole widget
   fn relative_pointer_pos(&self, ui: &Ui) -> Option<Pos2> {
@@ -781,69 +2293,172 @@ impl ScheduleUi {
     Some(pointer_pos)
   }
 
+  // the nearest position within the day columns, so that drags past
+  // their edges keep tracking the pointer
+  fn clamp_to_grid(&self, rel_pos: Pos2) -> Pos2 {
+    let max_x = self.day_width * self.day_count as f32 - 1.0;
+    pos2(
+      rel_pos.x.clamp(0.0, max_x),
+      rel_pos.y.clamp(0.0, self.content_height()),
+    )
+  }
+
   fn regularize_events(&mut self) {
-    remove_empty_events(&mut self.events);
+    let now = self.now();
+    remove_empty_events(&mut self.events, now);
 
+    let (zone, timezone) = (self.zone, self.timezone);
     for event in self.events.iter_mut() {
-      event.set_timezone(&self.timezone);
+      // each event gets the offset in effect at its start, to be shown
+      // at its wall-clock time
+      let offset = zone.map_or(timezone, |zone| zone.offset_at(&event.start));
+      event.set_timezone(&offset);
 
-      if event.end - event.start < self.min_event_duration {
+      if event.end - event.start < self.min_resize_duration {
         move_event_end(
           event,
-          event.end + self.min_event_duration,
-          self.min_event_duration,
+          event.end + self.min_resize_duration,
+          self.min_resize_duration,
+          now,
         );
       }
     }
   }
 
-  pub fn scroll_position(&self, time: &DateTime) -> f32 {
-    self.date_time_to_pos(time).y
+  pub fn scroll_to_now(&mut self) {
+    self.scroll_to_now_requested = true;
   }
 
-  pub fn scroll_position_for_now(&self) -> f32 {
-    self.scroll_position(&now(&self.timezone))
+  // center the time in the scroll area containing the widget, the
+  // scroll area clamps the offset at the boundaries
+  fn scroll_to_time(&mut self, ui: &Ui, rect: Rect, time: &DateTime) {
+    self.scroll_animation = Some(ScrollAnimation {
+      from: ui.clip_rect().center() - rect.min.to_vec2(),
+      to: self.date_time_to_pos(time),
+      started_at: ui.input(|input| input.time),
+    });
+  }
+
+  // move towards the target of the scroll a bit on every frame, until
+  // the user scrolls or clicks
+  fn animate_scroll(&mut self, ui: &Ui, rect: Rect) {
+    let animation = match self.scroll_animation {
+      Some(animation) => animation,
+      None => return,
+    };
+
+    let interrupted = ui.input(|input| {
+      input.raw_scroll_delta != Vec2::ZERO || input.pointer.any_pressed()
+    });
+    if interrupted {
+      self.scroll_animation = None;
+      return;
+    }
+
+    let elapsed = (ui.input(|input| input.time) - animation.started_at) as f32;
+    let progress = if self.scroll_animation_duration > 0.0 {
+      (elapsed / self.scroll_animation_duration).clamp(0.0, 1.0)
+    } else {
+      1.0
+    };
+    let eased = egui::emath::easing::cubic_in_out(progress);
+
+    let pos = rect.min + animation.from.lerp(animation.to, eased).to_vec2();
+    let target = Rect::from_center_size(pos, vec2(1.0, 1.0));
+    ui.scroll_to_rect(target, Some(egui::Align::Center));
+
+    if progress < 1.0 {
+      ui.ctx().request_repaint();
+    } else {
+      self.scroll_animation = None;
+    }
   }
 
+  // the position relative to the widget
   fn date_time_to_pos(&self, time: &DateTime) -> Pos2 {
-    let x = (time.date_naive() - self.first_day).num_days() as f32
-      / self.day_width
-      + self.time_marker_margin_width;
-    let y = self.day_progress(time) * self.content_height()
-      + self.day_header_margin_height;
-    pos2(x, y)
+    let time = self.normalize_time(time);
+    let day = (time.date_naive() - self.first_day).num_days() as f32;
+    let x = (day + 0.5) * self.day_width + self.time_marker_margin_width;
+    pos2(x, self.time_to_y(&time))
+  }
+
+  // The mapping between times and positions relative to the top left
+  // of the widget used to draw the events, e.g. to align overlays with
+  // them. It follows zooming, so it must be queried on every frame.
+  pub fn time_to_y(&self, time: &DateTime) -> f32 {
+    self.day_progress(time) * self.content_height() + self.content_top()
+  }
+
+  // the column of the date if visible
+  #[cfg(any(test, feature = "testing"))]
+  pub fn day_rect(&self, date: Date) -> Option<Rect> {
+    let day = self.date_to_day(date)?;
+    Some(self.day_column(day).translate(self.content_offset0()))
   }
 
   fn day_progress(&self, datetime: &DateTime) -> f32 {
     let datetime = self.normalize_time(datetime);
     let seconds_past_midnight = datetime.num_seconds_from_midnight();
-    (seconds_past_midnight as f32 / SECS_PER_DAY as f32).clamp(0.0, 1.0)
+    self.secs_to_y(seconds_past_midnight as f32) / self.content_height()
   }
 
   fn to_normalized_time(&self, time: &DateTime) -> f32 {
     let integer_part =
       (time.naive_local().date() - self.first_day).num_days() as f32;
-    let fraction_part = time.naive_local().num_seconds_from_midnight() as f32
-      / SECS_PER_DAY as f32;
+    let seconds_past_midnight = time.naive_local().num_seconds_from_midnight();
+    let fraction_part =
+      self.secs_to_y(seconds_past_midnight as f32) / self.content_height();
 
     integer_part + fraction_part
   }
 
   fn layout_type(&self, event: &Event) -> EventLayoutType {
+    if event.all_day {
+      let start = event.start.date_naive();
+      // the end is exclusive, but an event must at least cover a day
+      let end = event.end.date_naive().max(start + Duration::days(1));
+      return EventLayoutType::AllDay([start, end]);
+    }
+
     let start = self.to_normalized_time(&event.start);
     let end = self.to_normalized_time(&event.end);
     EventLayoutType::Single(start, end)
   }
 
   pub fn update_current_time(&mut self) {
-    self.current_time = Some(now(&self.timezone));
+    self.current_time = Some(self.now());
+  }
+
+  fn now(&self) -> DateTime {
+    self.normalize_time(&self.clock.now())
+  }
+
+  fn today(&self) -> Date {
+    self.now().date_naive()
   }
 
   pub fn refit_into_ui(&mut self, ui: &Ui) {
-    let day_space_width = ui.max_rect().width()
+    self.day_space_width = ui.max_rect().width()
       - self.time_marker_margin_width
       - ui.visuals().clip_rect_margin;
 
+    self.refit();
+    if self.view_mode == ViewMode::Week {
+      // align to the beginning of the week
+      self.first_day = self.first_day_for(self.first_day);
+    }
+    self.mark_scope_updated()
+  }
+
+  fn refit(&mut self) {
+    let day_space_width = self.day_space_width;
+
+    if self.view_mode == ViewMode::Week {
+      self.day_count = 7;
+      self.day_width = (day_space_width / 7.0).max(self.day_min_width);
+      return;
+    }
+
     let day_count_min = day_space_width / self.day_max_width;
     let day_count_max = day_space_width / self.day_min_width;
     let optimal_day_count =
@@ -858,13 +2473,49 @@ impl ScheduleUi {
       width if width < self.day_min_width => self.day_min_width,
       width => width,
     };
+  }
+}
 
-    self.mark_scope_updated()
+// the color specified by the event, or otherwise derived from its
+// calendar so events of different calendars are distinguishable
+fn event_color(event: &Event) -> Color32 {
+  if let Some([r, g, b]) = event.color {
+    let to_u8 = |x: f32| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+    return Color32::from_rgb(to_u8(r), to_u8(g), to_u8(b));
   }
+
+  // events of the same category share a color, or else those of the
+  // same calendar
+  let key = event.categories.first().unwrap_or(&event.calendar);
+  let hash = key
+    .bytes()
+    .fold(0u32, |h, b| h.wrapping_mul(31).wrapping_add(b as u32));
+  let hue = (hash % 360) as f32 / 360.0;
+  egui::ecolor::Hsva::new(hue, 0.35, 0.6, 1.0).into()
+}
+
+// WCAG contrast ratio, from 1 (same luminance) to 21 (black on white)
+fn contrast_ratio(c1: Color32, c2: Color32) -> f32 {
+  let luminance = |c: Color32| {
+    let c = egui::Rgba::from(c);
+    0.2126 * c.r() + 0.7152 * c.g() + 0.0722 * c.b()
+  };
+
+  let (l1, l2) = (luminance(c1), luminance(c2));
+  (l1.max(l2) + 0.05) / (l1.min(l2) + 0.05)
 }
 
-fn new_event_id() -> EventId {
-  format!("{}", Uuid::new_v4().hyphenated())
+// black or white, whichever is more readable on the background
+fn contrasting_text_color(background: Color32) -> Color32 {
+  let luma = 0.299 * background.r() as f32
+    + 0.587 * background.g() as f32
+    + 0.114 * background.b() as f32;
+
+  if luma > 140.0 {
+    Color32::BLACK
+  } else {
+    Color32::WHITE
+  }
 }
 
 enum CombinedEvent {
@@ -914,17 +2565,18 @@ fn move_event_end(
   event: &mut Event,
   new_end: DateTime,
   min_event_duration: Duration,
+  now: DateTime,
 ) {
-  if new_end < event.start + min_event_duration {
-    return;
-  }
+  // clamp instead of ignoring, so resizing past the start leaves the
+  // shortest event possible
+  let new_end = new_end.max(event.start + min_event_duration);
 
   if !on_the_same_day(event.start, new_end) {
     return;
   }
 
   if event.end != new_end {
-    event.mark_changed();
+    event.mark_changed(now);
     event.end = new_end;
   }
 }
@@ -933,22 +2585,21 @@ fn move_event_start(
   event: &mut Event,
   new_start: DateTime,
   min_event_duration: Duration,
+  now: DateTime,
 ) {
-  if event.end < new_start + min_event_duration {
-    return;
-  }
+  let new_start = new_start.min(event.end - min_event_duration);
 
   if !on_the_same_day(new_start, event.end) {
     return;
   }
 
   if event.start != new_start {
-    event.mark_changed();
+    event.mark_changed(now);
     event.start = new_start;
   }
 }
 
-fn move_event(event: &mut Event, new_start: DateTime) {
+fn move_event(event: &mut Event, new_start: DateTime, now: DateTime) {
   let duration = event.end - event.start;
   let new_end = new_start + duration;
 
@@ -957,16 +2608,124 @@ fn move_event(event: &mut Event, new_start: DateTime) {
   }
 
   if event.start != new_start || event.end != new_end {
-    event.mark_changed();
+    event.mark_changed(now);
     event.start = new_start;
     event.end = new_end;
   }
 }
 
-fn remove_empty_events(events: &mut [Event]) {
+// Pin an event moved to another day to midnight rather than let it
+// cross into the next day, unless it crossed midnight already, e.g. a
+// night shift, which keeps its time of day and duration.
+fn fit_in_day(event: &Event, new_start: DateTime) -> DateTime {
+  if !on_the_same_day(event.start, event.end) {
+    return new_start;
+  }
+
+  let duration = event.end - event.start;
+  let midnight = (new_start.date_naive() + one_day())
+    .and_hms_opt(0, 0, 0)
+    .expect("date overflow");
+  let overflow = new_start.naive_local() + duration - midnight;
+
+  if overflow > Duration::zero() {
+    new_start - overflow
+  } else {
+    new_start
+  }
+}
+
+fn remove_empty_events(events: &mut [Event], now: DateTime) {
   for event in events.iter_mut() {
     if event.title.is_empty() {
-      event.mark_deleted();
+      event.mark_deleted(now);
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use chrono::{NaiveDate, TimeZone};
+
+  use super::*;
+
+  fn at(h: u32, m: u32) -> DateTime {
+    let time = NaiveDate::from_ymd_opt(2024, 3, 1)
+      .and_then(|d| d.and_hms_opt(h, m, 0))
+      .expect("invalid time");
+    utc().from_local_datetime(&time).unwrap()
+  }
+
+  fn utc() -> FixedOffset {
+    FixedOffset::east_opt(0).unwrap()
+  }
+
+  #[test]
+  fn snapping_prefers_stronger_ticks() {
+    let schedule = ScheduleUiBuilder::default()
+      .first_day(at(0, 0).date_naive())
+      .current_time(at(8, 0))
+      .timezone(utc())
+      .new_event_calendar("test")
+      .snapping_duration(Duration::minutes(5))
+      .snapping_tiers(vec![
+        SnapTier {
+          interval: Duration::minutes(30),
+          tolerance: Duration::minutes(10),
+        },
+        SnapTier {
+          interval: Duration::minutes(15),
+          tolerance: Duration::minutes(5),
+        },
+      ])
+      .build()
+      .expect("failed building schedule");
+    let snapped = |h: u32, m: u32| {
+      let y = schedule.day_progress(&at(h, m)) * schedule.content_height();
+      let pos = pos2(schedule.day_width / 2.0, y);
+      schedule.pointer_pos_to_datetime_snapping(pos)
+    };
+
+    // closer to :15, but within the tolerance of :30
+    assert_eq!(snapped(10, 21), Some(at(10, 30)));
+    // out of the tolerance of :30 and :00
+    assert_eq!(snapped(10, 12), Some(at(10, 15)));
+  }
+
+  fn event(id: &str, start: DateTime, end: DateTime) -> CombinedEvent {
+    let event = EventBuilder::default()
+      .id(id)
+      .calendar("test")
+      .title(id)
+      .start(start)
+      .end(end)
+      .build()
+      .expect("failed building event");
+    CombinedEvent::ExistingEvent(event)
+  }
+
+  #[test]
+  fn cap_events_per_day_keeps_the_longest_overlapping_ones() {
+    let schedule = ScheduleUiBuilder::default()
+      .first_day(at(0, 0).date_naive())
+      .current_time(at(8, 0))
+      .timezone(utc())
+      .new_event_calendar("test")
+      .max_overlapping_events(Some(2))
+      .build()
+      .expect("failed building schedule");
+
+    let mut events = vec![
+      event("long", at(9, 0), at(11, 0)),
+      event("short", at(9, 30), at(9, 45)),
+      event("medium", at(10, 0), at(11, 0)),
+      // drawn after the others, not overlapping them
+      event("later", at(13, 0), at(13, 15)),
+    ];
+    let overflow = schedule.cap_events_per_day(&mut events);
+
+    assert_eq!(overflow, BTreeMap::from([(0, 1)]));
+    let kept: Vec<_> = events.iter().map(|e| e.event_id().as_str()).collect();
+    assert_eq!(kept, ["long", "medium", "later"]);
+  }
+}
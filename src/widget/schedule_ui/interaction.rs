@@ -1,22 +1,25 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, marker::PhantomData, sync::Arc};
 
 use bimap::BiMap;
 use chrono::{Duration, Timelike};
 use eframe::egui::{
-  self, text::LayoutJob, CursorIcon, EventFilter, Key, KeyboardShortcut, Label,
-  LayerId, Modifiers, Rect, Response, Sense, Ui,
+  self,
+  text::{Galley, LayoutJob, TextFormat},
+  vec2, Color32, CursorIcon, EventFilter, Key, KeyboardShortcut, Label,
+  LayerId, Modifiers, Rect, Response, Sense, Stroke, Ui, Vec2,
 };
 use egui_autocomplete::AutoCompleteTextEdit;
 use humantime;
 
 use crate::{
-  event::Event,
-  util::{local_now, on_the_same_day, reorder_times, today, DateTime},
+  event::{Event, EventStatus},
+  util::{on_the_same_day, one_day, reorder_times, DateTime},
 };
 
 use super::{
-  layout::Layout, move_event, move_event_end, move_event_start, EventId,
-  ScheduleUi,
+  event_color, fit_in_day, layout::Layout, move_event, move_event_end,
+  move_event_start, ContextMenuAction, EventId, LabelTruncation,
+  ResizerHintAnchor, ScheduleEvent, ScheduleUi, TransparentEvents,
 };
 
 #[derive(Clone, Copy, Debug)]
@@ -27,14 +30,91 @@ enum Direction {
   Down,
 }
 
+// An interaction started on an event while the blocks of a frame are
+// placed. It only begins once they all are, so that it can't replace
+// the interaction on another event before that one is committed.
+#[derive(Clone, Debug)]
+struct PendingInteraction {
+  event: Event,
+  state: FocusedEventState,
+}
+
+const PENDING_INTERACTION: TempSlot<PendingInteraction> =
+  TempSlot::new("pending_interaction");
+
+// A value kept in the temporary memory of egui under a fixed name, for
+// the interactions lasting several frames.
+struct TempSlot<T> {
+  name: &'static str,
+  value: PhantomData<T>,
+}
+
+impl<T: Clone + Send + Sync + 'static> TempSlot<T> {
+  const fn new(name: &'static str) -> Self {
+    Self {
+      name,
+      value: PhantomData,
+    }
+  }
+
+  fn id(&self) -> egui::Id {
+    egui::Id::new(self.name)
+  }
+
+  fn get(&self, ui: &Ui) -> Option<T> {
+    ui.memory(|mem| mem.data.get_temp::<T>(self.id()))
+  }
+
+  fn set(&self, ui: &Ui, value: T) {
+    ui.memory_mut(|mem| mem.data.insert_temp(self.id(), value))
+  }
+
+  fn take(&self, ui: &Ui) -> Option<T> {
+    let value = self.get(ui);
+    self.clear(ui);
+    value
+  }
+
+  fn clear(&self, ui: &Ui) {
+    ui.memory_mut(|mem| mem.data.remove::<T>(self.id()));
+  }
+}
+
+// the grab point relative to the top of the dragged event
 #[derive(Clone, Copy, Debug)]
 struct DraggingEventYOffset(f32);
 
+impl DraggingEventYOffset {
+  // keyed by event so the offset of a clone is kept apart from the one
+  // of the original event
+  fn id(event_id: &EventId) -> egui::Id {
+    egui::Id::new("dragging_event_y_offset").with(event_id)
+  }
+
+  fn get(ui: &Ui, event_id: &EventId) -> Option<f32> {
+    let value = ui.memory(|mem| mem.data.get_temp::<Self>(Self::id(event_id)));
+    value.map(|v| v.0)
+  }
+
+  fn set(ui: &Ui, event_id: &EventId, offset: f32) {
+    let id = Self::id(event_id);
+    ui.memory_mut(|mem| mem.data.insert_temp(id, Self(offset)))
+  }
+
+  fn take(ui: &Ui, event_id: &EventId) -> Option<f32> {
+    let offset = Self::get(ui, event_id);
+    ui.memory_mut(|mem| mem.data.remove::<Self>(Self::id(event_id)));
+    offset
+  }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 enum Change {
   Added { new: Event },
   Removed { old: Event },
   Modified { old: Event, new: Event },
+  // several changes applied and undone together
+  Batch(Vec<Change>),
 }
 
 impl Change {
@@ -45,6 +125,17 @@ impl Change {
       Added { new } => Removed { old: new },
       Removed { old } => Added { new: old },
       Modified { old, new } => Modified { new: old, old: new },
+      Batch(changes) => {
+        Batch(changes.into_iter().rev().map(Change::reverse).collect())
+      }
+    }
+  }
+
+  fn from_changes(mut changes: Vec<Change>) -> Option<Self> {
+    match changes.len() {
+      0 => None,
+      1 => changes.pop(),
+      _ => Some(Change::Batch(changes)),
     }
   }
 
@@ -56,37 +147,65 @@ impl Change {
       .map(|old| Change::Removed { old })
   }
 
-  fn new_changed(events: &[Event], changed_event: Event) -> Self {
-    if let Some(existing) =
-      events.iter().find(|&e| e.id == changed_event.id).cloned()
-    {
-      Change::Modified {
+  // none if the event is committed unchanged
+  fn new_changed(events: &[Event], changed_event: Event) -> Option<Self> {
+    match events.iter().find(|&e| e.id == changed_event.id).cloned() {
+      Some(existing) if existing.same_content(&changed_event) => None,
+      Some(existing) => Some(Change::Modified {
         old: existing,
         new: changed_event,
+      }),
+      None => Some(Change::Added { new: changed_event }),
+    }
+  }
+
+  fn rekey(&mut self, old_id: &EventId, new_id: &EventId) {
+    let events = match self {
+      Change::Added { new } => vec![new],
+      Change::Removed { old } => vec![old],
+      Change::Modified { old, new } => vec![old, new],
+      Change::Batch(changes) => {
+        for change in changes {
+          change.rekey(old_id, new_id);
+        }
+        return;
+      }
+    };
+    for event in events {
+      if &event.id == old_id {
+        event.id = new_id.clone();
       }
-    } else {
-      Change::Added { new: changed_event }
     }
   }
 
-  fn apply(&self, events: &mut Vec<Event>) {
+  fn apply(&self, events: &mut Vec<Event>, now: DateTime) {
     match self.clone() {
       Change::Added { mut new } => {
-        new.mark_changed();
+        new.mark_created(now);
         events.push(new)
       }
       Change::Removed { old } => {
         if let Some(e) = events.iter_mut().find(|e| e.id == old.id) {
-          e.mark_deleted();
+          e.mark_deleted(now);
         }
       }
       Change::Modified { old, mut new } => {
-        new.mark_changed();
-
         if let Some(e) = events.iter_mut().find(|e| e.id == old.id) {
+          // undoing restores the content, not an earlier revision: the
+          // snapshot may predate the last save, which would look like a
+          // change made elsewhere
+          new.modified_at = e.modified_at;
+          new.saved_modified_at = e.saved_modified_at;
+          new.sequence = new.sequence.max(e.sequence);
+          new.mark_changed(now);
           *e = new;
         }
       }
+      Change::Batch(changes) => {
+        for change in changes {
+          change.apply(events, now);
+        }
+      }
     }
   }
 }
@@ -123,6 +242,28 @@ impl EventFocusRegistry {
   fn get_event_rect(ui: &Ui, event_id: &EventId) -> Option<Rect> {
     Self::with_this(ui, |this| this.event_rects.get(event_id).copied())
   }
+
+  fn events_at(ui: &Ui, pos: egui::Pos2) -> Vec<EventId> {
+    Self::with_this(ui, |this| {
+      this
+        .event_rects
+        .iter()
+        .filter(|(_, event_rect)| event_rect.contains(pos))
+        .map(|(event_id, _)| event_id.clone())
+        .collect()
+    })
+  }
+
+  fn events_intersecting(ui: &Ui, rect: Rect) -> Vec<EventId> {
+    Self::with_this(ui, |this| {
+      this
+        .event_rects
+        .iter()
+        .filter(|(_, event_rect)| event_rect.intersects(rect))
+        .map(|(event_id, _)| event_id.clone())
+        .collect()
+    })
+  }
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
@@ -142,6 +283,12 @@ impl History {
   fn pop(&mut self) -> Option<Change> {
     self.changes.pop()
   }
+
+  fn rekey(&mut self, old_id: &EventId, new_id: &EventId) {
+    for change in self.changes.iter_mut() {
+      change.rekey(old_id, new_id);
+    }
+  }
 }
 
 #[derive(Clone, Debug)]
@@ -235,34 +382,126 @@ impl RefocusingEvent {
   }
 }
 
-#[derive(Clone, Debug)]
-struct DeletedEvent {
-  event_id: EventId,
+#[derive(Clone, Debug, Default)]
+struct SelectedEvents {
+  event_ids: Vec<EventId>,
+  // the time offset of the event being dragged, which applies to the
+  // other selected events as well
+  drag_delta: Option<Duration>,
 }
 
-impl DeletedEvent {
+impl SelectedEvents {
   fn id() -> egui::Id {
-    egui::Id::new("deleted_event")
+    egui::Id::new("selected_events")
   }
 
-  fn set(ui: &Ui, event_id: &EventId) {
-    ui.memory_mut(|mem| {
-      mem.data.insert_temp(
-        Self::id(),
-        Self {
-          event_id: event_id.clone(),
-        },
-      )
-    });
+  fn get(ui: &Ui) -> Self {
+    ui.memory(|mem| mem.data.get_temp(Self::id()))
+      .unwrap_or_default()
+  }
+
+  fn save(self, ui: &Ui) {
+    ui.memory_mut(|mem| mem.data.insert_temp(Self::id(), self))
+  }
+
+  fn clear(ui: &Ui) {
+    ui.memory_mut(|mem| mem.data.remove::<Self>(Self::id()))
+  }
+
+  fn contains(ui: &Ui, event_id: &EventId) -> bool {
+    Self::get(ui).event_ids.contains(event_id)
+  }
+
+  fn set(ui: &Ui, event_ids: Vec<EventId>) {
+    let mut this = Self::get(ui);
+    this.event_ids = event_ids;
+    this.save(ui);
+  }
+
+  fn toggle(ui: &Ui, event_id: &EventId) {
+    let mut this = Self::get(ui);
+    if let Some(i) = this.event_ids.iter().position(|id| id == event_id) {
+      this.event_ids.remove(i);
+    } else {
+      this.event_ids.push(event_id.clone());
+    }
+    this.save(ui);
   }
 
-  fn take(ui: &Ui) -> Option<EventId> {
-    let deleted_event = ui.memory(|mem| mem.data.get_temp(Self::id()));
-    ui.memory_mut(|mem| mem.data.remove::<Self>(Self::id()));
-    deleted_event.map(|x: Self| x.event_id)
+  fn set_drag_delta(ui: &Ui, delta: Option<Duration>) {
+    let mut this = Self::get(ui);
+    this.drag_delta = delta;
+    this.save(ui);
+  }
+
+  // the offset to display a selected event with while another
+  // selected event is being dragged
+  fn drag_delta_for(ui: &Ui, event_id: &EventId) -> Option<Duration> {
+    let this = Self::get(ui);
+    this
+      .event_ids
+      .contains(event_id)
+      .then_some(this.drag_delta)?
   }
 }
 
+// the corner where a rubber-band selection started
+const SELECTION_RECT_ORIGIN: TempSlot<egui::Pos2> =
+  TempSlot::new("selection_rect_origin");
+
+// where a drag on the blank area started, until it moved far enough
+// to create an event
+const NEW_EVENT_ORIGIN: TempSlot<egui::Pos2> =
+  TempSlot::new("new_event_origin");
+
+// the event being created by a drag on the blank area, and the time
+// the drag started at
+#[derive(Clone, Debug)]
+struct NewEventDrag {
+  event_id: EventId,
+  init_time: DateTime,
+}
+
+const NEW_EVENT_DRAG: TempSlot<NewEventDrag> = TempSlot::new("new_event_drag");
+
+impl NewEventDrag {
+  // forget a drag that ended without being released on the widget,
+  // e.g. when the window lost focus, along with its half-made event
+  fn abandon(ui: &Ui) {
+    NEW_EVENT_ORIGIN.clear(ui);
+
+    let drag = match NEW_EVENT_DRAG.take(ui) {
+      Some(drag) => drag,
+      None => return,
+    };
+    if let Some(value) = InteractingEvent::get_id(ui, &drag.event_id) {
+      if value.state != FocusedEventState::Editing {
+        InteractingEvent::discard(ui);
+      }
+    }
+  }
+}
+
+// the last grid position of a dragged or resized event, kept for the
+// frames where the pointer is outside the window
+const LAST_DRAG_POS: TempSlot<egui::Pos2> = TempSlot::new("last_drag_pos");
+
+// the event deleted during the frame
+const DELETED_EVENT: TempSlot<EventId> = TempSlot::new("deleted_event");
+
+// the event whose menu was opened by a long touch, and where
+#[derive(Clone, Debug)]
+struct LongPressedEvent {
+  event_id: EventId,
+  pos: egui::Pos2,
+}
+
+const LONG_PRESSED_EVENT: TempSlot<LongPressedEvent> =
+  TempSlot::new("long_pressed_event");
+
+// the event copied with ctrl-c
+const COPIED_EVENT: TempSlot<Event> = TempSlot::new("copied_event");
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum FocusedEventState {
   Editing,
@@ -308,8 +547,15 @@ impl ScheduleUi {
 
     match detect_interaction(resp) {
       None => {
-        if upper.contains(interact_pos) || lower.contains(interact_pos) {
+        let hovered_resizer = [upper, lower]
+          .into_iter()
+          .find(|resizer| resizer.contains(interact_pos));
+        if let Some(resizer) = hovered_resizer {
           ui.output_mut(|out| out.cursor_icon = CursorIcon::ResizeVertical);
+          if resp.hovered() && !ui.memory(|mem| mem.is_anything_being_dragged())
+          {
+            highlight_resizer(ui, resizer);
+          }
         } else if event_rect.contains(interact_pos) {
           ui.output_mut(|out| out.cursor_icon = CursorIcon::Grab);
         }
@@ -318,6 +564,21 @@ impl ScheduleUi {
       Some(Interaction::Clicked)
         if resp.clicked_by(egui::PointerButton::Primary) =>
       {
+        let modifiers = ui.input(|input| input.modifiers);
+        if modifiers.alt {
+          // alt-click reaches the events buried under the clicked one
+          let event_id = EventFocusRegistry::get_event_id(ui, resp.id)?;
+          self.focus_next_stacked(ui, &event_id, interact_pos);
+          return None;
+        }
+        if modifiers.ctrl || modifiers.shift {
+          // modifier-click toggles the event in the selection
+          let event_id = EventFocusRegistry::get_event_id(ui, resp.id)?;
+          SelectedEvents::toggle(ui, &event_id);
+          return None;
+        }
+
+        SelectedEvents::clear(ui);
         Some(Editing)
       }
       Some(Interaction::DragStarted { origin })
@@ -330,18 +591,46 @@ impl ScheduleUi {
           return Some(DraggingEventEnd);
         }
 
-        let offset = DraggingEventYOffset(event_rect.top() - origin.y);
-        ui.memory_mut(|mem| mem.data.insert_temp(egui::Id::NULL, offset));
+        let event_id = EventFocusRegistry::get_event_id(ui, resp.id)?;
+        DraggingEventYOffset::set(ui, &event_id, event_rect.top() - origin.y);
         if ui.input(|input| input.modifiers.ctrl) {
           Some(EventCloning)
         } else {
           Some(Dragging)
         }
       }
+      Some(Interaction::LongPressed { pos }) => {
+        // opens the context menu, see event_context_menu
+        let event_id = EventFocusRegistry::get_event_id(ui, resp.id)?;
+        LONG_PRESSED_EVENT.set(ui, LongPressedEvent { event_id, pos });
+        ui.ctx().request_repaint();
+        None
+      }
       _ => None,
     }
   }
 
+  // focus the event after the focused one among those under the
+  // position, in the order of their ids so that the cycle is stable
+  fn focus_next_stacked(&self, ui: &Ui, clicked: &EventId, pos: egui::Pos2) {
+    let mut stacked: Vec<EventId> = EventFocusRegistry::events_at(ui, pos)
+      .into_iter()
+      // the registry remembers events no longer shown
+      .filter(|id| self.is_shown(id))
+      .collect();
+    stacked.sort();
+
+    let current = match &self.focused_event {
+      Some(focused) if stacked.contains(focused) => focused,
+      _ => clicked,
+    };
+    let next = match stacked.iter().position(|id| id == current) {
+      Some(i) => &stacked[(i + 1) % stacked.len()],
+      None => clicked,
+    };
+    RefocusingEvent::request_focus(ui, next);
+  }
+
   fn interact_event(
     &self,
     ui: &mut Ui,
@@ -352,24 +641,32 @@ impl ScheduleUi {
     let [upper, lower] = self.event_resizer_regions(event_rect);
 
     let resp = self.place_event_button(ui, event_rect, event);
+    let event_id = event.id.clone();
     let commit = match state {
       FocusedEventState::DraggingEventStart => {
-        self.handle_event_resizing(ui, upper, |time| {
-          move_event_start(event, time, self.min_event_duration);
-          event.start
+        self.handle_event_resizing(ui, upper, &event_id, |time| {
+          move_event_start(event, time, self.min_resize_duration, self.now());
+          (event.start, event.end - event.start)
         })
       }
       FocusedEventState::DraggingEventEnd => {
-        self.handle_event_resizing(ui, lower, |time| {
-          move_event_end(event, time, self.min_event_duration);
-          event.end
+        self.handle_event_resizing(ui, lower, &event_id, |time| {
+          move_event_end(event, time, self.min_resize_duration, self.now());
+          (event.end, event.end - event.start)
         })
       }
       FocusedEventState::Dragging => {
-        self.handle_event_dragging(ui, event_rect, |time| {
-          move_event(event, time);
-          (event.start, event.end)
-        })
+        let duration = event.end - event.start;
+        self.handle_event_dragging(
+          ui,
+          event_rect,
+          &event_id,
+          duration,
+          |time| {
+            move_event(event, fit_in_day(event, time), self.now());
+            (event.start, event.end)
+          },
+        )
       }
       _ => unreachable!(),
     };
@@ -381,19 +678,35 @@ impl ScheduleUi {
     &self,
     ui: &mut Ui,
     rect: Rect,
-    set_time: impl FnOnce(DateTime) -> DateTime,
+    event_id: &EventId,
+    set_time: impl FnOnce(DateTime) -> (DateTime, Duration),
   ) -> Option<bool> {
     if !ui.memory(|mem| mem.is_anything_being_dragged()) {
+      LAST_DRAG_POS.clear(ui);
       return Some(true);
     }
 
     ui.output_mut(|out| out.cursor_icon = CursorIcon::ResizeVertical);
+    auto_scroll(ui);
 
-    let pointer_pos = self.relative_pointer_pos(ui).unwrap();
-
-    if let Some(datetime) = self.pointer_to_datetime_auto(ui, pointer_pos) {
-      let updated_time = set_time(datetime);
-      self.show_resizer_hint(ui, rect, updated_time);
+    // the pointer can leave the window mid-drag, hold the last position
+    let pointer_pos = match self.relative_pointer_pos(ui) {
+      Some(pos) => self.clamp_to_grid(pos),
+      None => LAST_DRAG_POS.get(ui)?,
+    };
+    LAST_DRAG_POS.set(ui, pointer_pos);
+
+    let datetime = self.pointer_to_datetime_magnetic(
+      ui,
+      pointer_pos,
+      event_id,
+      Duration::zero(),
+    );
+    if let Some(datetime) = datetime {
+      let (updated_time, duration) = set_time(datetime);
+      let format = self.event_resizing_hint_format;
+      self.show_resizer_hint(ui, rect, updated_time, format);
+      self.show_duration_hint(ui, duration);
     }
 
     None
@@ -403,59 +716,148 @@ impl ScheduleUi {
     &self,
     ui: &mut Ui,
     rect: Rect,
+    event_id: &EventId,
+    duration: Duration,
     set_time: impl FnOnce(DateTime) -> (DateTime, DateTime),
   ) -> Option<bool> {
     if !ui.memory(|mem| mem.is_anything_being_dragged()) {
+      DraggingEventYOffset::take(ui, event_id);
+      LAST_DRAG_POS.clear(ui);
       return Some(true);
     }
 
     ui.output_mut(|out| out.cursor_icon = CursorIcon::Grabbing);
+    auto_scroll(ui);
 
-    let mut pointer_pos = self.relative_pointer_pos(ui).unwrap();
-    if let Some(offset_y) =
-      ui.memory(|mem| mem.data.get_temp::<DraggingEventYOffset>(egui::Id::NULL))
-    {
-      pointer_pos.y += offset_y.0;
-    }
+    // the pointer can leave the window mid-drag, hold the last position
+    let pointer_pos = match self.relative_pointer_pos(ui) {
+      Some(pos) => self.dragged_event_pos(ui, pos, event_id, duration),
+      None => LAST_DRAG_POS.get(ui)?,
+    };
+    LAST_DRAG_POS.set(ui, pointer_pos);
 
-    if let Some(datetime) = self.pointer_to_datetime_auto(ui, pointer_pos) {
+    let datetime =
+      self.pointer_to_datetime_magnetic(ui, pointer_pos, event_id, duration);
+    if let Some(datetime) = datetime {
       let (beg, end) = set_time(datetime);
       let [upper, lower] = self.event_resizer_regions(rect);
-      self.show_resizer_hint(ui, upper, beg);
-      self.show_resizer_hint(ui, lower, end);
+      let format = self.event_moving_hint_format;
+      self.show_resizer_hint(ui, upper, beg, format);
+      self.show_resizer_hint(ui, lower, end, format);
+      self.show_duration_hint(ui, end - beg);
     }
 
     None
   }
 
+  // where the top of the dragged event goes for the pointer position
+  fn dragged_event_pos(
+    &self,
+    ui: &Ui,
+    mut pointer_pos: egui::Pos2,
+    event_id: &EventId,
+    duration: Duration,
+  ) -> egui::Pos2 {
+    let past_bottom = pointer_pos.y > self.content_height();
+    if let Some(offset_y) = DraggingEventYOffset::get(ui, event_id) {
+      pointer_pos.y += offset_y;
+    }
+    // stay at the edges of the grid when dragged past them, the event
+    // keeps the last time when released out there
+    let mut pointer_pos = self.clamp_to_grid(pointer_pos);
+    // end with the day below the grid instead of moving to the next one
+    if past_bottom && duration < one_day() {
+      let latest_start = (one_day() - duration).num_seconds();
+      pointer_pos.y = pointer_pos.y.min(self.secs_to_y(latest_start as f32));
+    }
+    pointer_pos
+  }
+
   pub(super) fn put_non_interacting_event_block(
     &self,
     ui: &mut Ui,
     layout: &Layout,
     event: &Event,
   ) -> Option<()> {
+    // follow the dragged event if both are selected
+    let moved_event;
+    let event = match SelectedEvents::drag_delta_for(ui, &event.id) {
+      None => event,
+      Some(delta) => {
+        let mut e = event.clone();
+        move_event(&mut e, event.start + delta, self.now());
+        moved_event = e;
+        &moved_event
+      }
+    };
+
+    self.draw_event_continuations(ui, layout, event);
+
     let event_rect = self.event_rect(ui, layout, event)?;
 
     let resp = self.place_event_button(ui, event_rect, event);
 
     EventFocusRegistry::register(ui, &event.id, &resp);
 
-    let interaction = self
-      .interact_event_region_keyboard(ui, &resp)
-      .or_else(|| self.interact_event_region(ui, &resp));
+    let interaction = if self.read_only {
+      None
+    } else {
+      self
+        .interact_event_region_keyboard(ui, &resp)
+        .or_else(|| self.interact_event_region(ui, &resp))
+        // all-day events can only be edited
+        .filter(|state| !event.all_day || *state == FocusedEventState::Editing)
+    };
 
     match interaction {
       None => (),
       Some(FocusedEventState::EventCloning) => {
+        // the clone follows the pointer from the same grab point while
+        // the original stays put
         let new_event = self.clone_to_new_event(event);
-        InteractingEvent::set(ui, new_event, FocusedEventState::Dragging);
+        if let Some(offset) = DraggingEventYOffset::take(ui, &event.id) {
+          DraggingEventYOffset::set(ui, &new_event.id, offset);
+        }
+        let state = FocusedEventState::Dragging;
+        PENDING_INTERACTION.set(
+          ui,
+          PendingInteraction {
+            event: new_event,
+            state,
+          },
+        );
+      }
+      Some(state) => {
+        let event = event.clone();
+        PENDING_INTERACTION.set(ui, PendingInteraction { event, state })
       }
-      Some(state) => InteractingEvent::set(ui, event.clone(), state),
     }
 
     Some(())
   }
 
+  // Begin the interaction started while placing the event blocks,
+  // unless another event is still being dragged: its drag ends with
+  // its own commit, which the new interaction mustn't replace.
+  pub(super) fn start_pending_interaction(&self, ui: &Ui) {
+    let pending = match PENDING_INTERACTION.take(ui) {
+      Some(pending) => pending,
+      None => return,
+    };
+
+    let dragging = ui.memory(|mem| mem.is_anything_being_dragged());
+    match InteractingEvent::get(ui) {
+      Some(current)
+        if dragging
+          && current.state != FocusedEventState::Editing
+          && current.event.id != pending.event.id =>
+      {
+        DraggingEventYOffset::take(ui, &pending.event.id);
+      }
+      _ => InteractingEvent::set(ui, pending.event, pending.state),
+    }
+  }
+
   pub(super) fn put_interacting_event_block(
     &self,
     ui: &mut Ui,
@@ -469,19 +871,51 @@ impl ScheduleUi {
     match ie.state {
       Editing => match self.place_event_editor(ui, event_rect, &mut ie.event) {
         None => ie.save(ui),
-        Some(true) => ie.commit(ui),
+        Some(true) => {
+          // a blank title counts as empty, which drops new events and
+          // reverts the edit of saved ones when applied
+          let title = ie.event.title.trim();
+          if title.len() != ie.event.title.len() {
+            ie.event.title = title.to_string();
+          }
+          ie.commit(ui)
+        }
         Some(false) => InteractingEvent::discard(ui),
       },
       _ => {
-        let event_rect = self.event_rect(ui, layout, &ie.event)?;
+        let mut event_rect = self.event_rect(ui, layout, &ie.event)?;
+        if matches!(ie.state, Dragging | EventCloning) {
+          // where the event lands when released
+          self.draw_drag_ghost(ui, event_rect, &ie.event);
+          if self.dragged_event_follows_pointer {
+            event_rect = self.follow_pointer(ui, event_rect, &ie.event.id);
+          }
+        }
 
-        let (_resp, commit) =
-          self.interact_event(ui, event_rect, ie.state, &mut ie.event);
+        // draw on a separate layer so the event stays on top of the
+        // others. A child ui is used to keep the max_rect of the whole
+        // widget, which the pointer calculation relies on.
+        let layer =
+          LayerId::new(self.interacting_event_order, InteractingEvent::id());
+        let max_rect = ui.max_rect();
+        let (_resp, commit) = ui
+          .with_layer_id(layer, |ui| {
+            let mut ui = ui.child_ui(max_rect, *ui.layout());
+            self.interact_event(&mut ui, event_rect, ie.state, &mut ie.event)
+          })
+          .inner;
+
+        if ie.state == Dragging {
+          self.update_selection_drag_delta(ui, &ie.event);
+        }
 
         match commit {
           None => ie.save(ui),
           Some(true) => ie.commit(ui),
-          Some(false) => InteractingEvent::discard(ui),
+          Some(false) => {
+            SelectedEvents::set_drag_delta(ui, None);
+            InteractingEvent::discard(ui)
+          }
         }
       }
     }
@@ -489,12 +923,102 @@ impl ScheduleUi {
     Some(())
   }
 
+  fn draw_drag_ghost(&self, ui: &Ui, rect: Rect, event: &Event) {
+    const OPACITY: f32 = 0.35;
+
+    let visuals = &ui.visuals().widgets.inactive;
+    let rounding = self.event_rounding.unwrap_or(visuals.rounding);
+    let fill = event_color(event).gamma_multiply(OPACITY);
+    ui.painter()
+      .rect(rect, rounding, fill, ui.visuals().selection.stroke);
+  }
+
+  // the rect of the dragged event moved to the unsnapped pointer
+  // position, keeping its column
+  fn follow_pointer(&self, ui: &Ui, rect: Rect, event_id: &EventId) -> Rect {
+    let pointer = match ui.input(|input| input.pointer.hover_pos()) {
+      Some(pointer) => pointer,
+      None => return rect,
+    };
+    let offset_y = DraggingEventYOffset::get(ui, event_id).unwrap_or(0.0);
+    rect.translate(vec2(0.0, pointer.y + offset_y - rect.top()))
+  }
+
+  fn update_selection_drag_delta(&self, ui: &Ui, event: &Event) {
+    if !SelectedEvents::contains(ui, &event.id) {
+      return;
+    }
+
+    let delta = self
+      .events
+      .iter()
+      .find(|e| e.id == event.id)
+      .map(|original| event.start - original.start);
+
+    SelectedEvents::set_drag_delta(ui, delta);
+  }
+
+  // move the other selected events along with the dragged one
+  fn selection_drag_changes(
+    &self,
+    ui: &Ui,
+    dragged_id: &EventId,
+  ) -> Vec<Change> {
+    let mut selected = SelectedEvents::get(ui);
+    let delta = match selected.drag_delta.take() {
+      Some(delta) => delta,
+      None => return vec![],
+    };
+    selected.clone().save(ui);
+
+    self
+      .events
+      .iter()
+      .filter(|e| &e.id != dragged_id && selected.event_ids.contains(&e.id))
+      .filter_map(|e| {
+        let mut new = e.clone();
+        move_event(&mut new, e.start + delta, self.now());
+        (new != *e).then(|| Change::Modified {
+          old: e.clone(),
+          new,
+        })
+      })
+      .collect()
+  }
+
   pub(super) fn handle_hotkeys(&mut self, ui: &Ui) {
-    self.handle_keyboard_focused_event_resize(ui);
-    self.handle_keyboard_focused_event_move(ui);
+    // the keys are typed into the text, pasting included
+    if self.typing_in_text_field(ui) {
+      return;
+    }
+
+    // resizing and moving go first to take the modified arrow keys
+    // before focus moving does
+    if !self.read_only {
+      self.handle_keyboard_focused_event_resize(ui);
+      self.handle_keyboard_focused_event_move(ui);
+    }
     self.handle_keyboard_focus_move(ui);
-    self.handle_keyboard_new_event(ui);
-    self.handle_keyboard_delete_event(ui);
+    self.handle_keyboard_navigation(ui);
+    self.handle_keyboard_copy_event(ui);
+
+    if !self.read_only {
+      self.handle_keyboard_new_event(ui);
+      self.handle_keyboard_delete_event(ui);
+      self.handle_keyboard_paste_event(ui);
+    }
+  }
+
+  // the keyboard goes to a text field, e.g. quick-add or the filter,
+  // rather than to the grid or an event
+  fn typing_in_text_field(&self, ui: &Ui) -> bool {
+    if !ui.ctx().wants_keyboard_input() {
+      return false;
+    }
+
+    let focused = ui.memory(|mem| mem.focus());
+    !focused
+      .is_some_and(|id| EventFocusRegistry::get_event_id(ui, id).is_some())
   }
 
   fn key_direction_input(
@@ -525,6 +1049,25 @@ impl ScheduleUi {
     }
   }
 
+  fn handle_keyboard_navigation(&mut self, ui: &Ui) -> Option<()> {
+    if InteractingEvent::is_interacting(ui) {
+      return None;
+    }
+
+    let pressed =
+      |k| ui.input_mut(|input| input.consume_key(Modifiers::NONE, k));
+
+    if pressed(Key::T) {
+      self.goto_today();
+    } else if pressed(Key::PageDown) {
+      self.next_period();
+    } else if pressed(Key::PageUp) {
+      self.prev_period();
+    }
+
+    Some(())
+  }
+
   fn handle_keyboard_new_event(&mut self, ui: &Ui) -> Option<()> {
     if InteractingEvent::is_interacting(ui) {
       return None;
@@ -535,7 +1078,7 @@ impl ScheduleUi {
     }
 
     let mut event = self.new_event();
-    let today = today(&self.timezone);
+    let today = self.today();
     let last_event_end_in_today = self
       .events
       .iter()
@@ -547,7 +1090,7 @@ impl ScheduleUi {
     let last_event_end =
       self.events.iter().max_by_key(|x| x.end).map(|x| x.end);
     let nearest_snapping = {
-      let t = self.snap_to_nearest(&local_now());
+      let t = self.snap_to_nearest(&self.now());
       self.is_visible(&t).then_some(t)
     };
 
@@ -555,8 +1098,9 @@ impl ScheduleUi {
       .or(nearest_snapping)
       .or(last_event_end)?;
 
-    move_event(&mut event, new_event_start);
-    let position = event.start_position_of_day();
+    move_event(&mut event, new_event_start, self.now());
+    // as laid out, with the hours outside the visible range compressed
+    let position = self.day_progress(&event.start);
 
     InteractingEvent::set(ui, event, FocusedEventState::Editing);
 
@@ -575,13 +1119,89 @@ impl ScheduleUi {
 
     let del_key_pressed = ui
       .input_mut(|mem| mem.consume_key(Modifiers::NONE, Key::X))
-      || ui.input_mut(|mem| mem.consume_key(Modifiers::NONE, Key::Delete));
+      || ui.input_mut(|mem| mem.consume_key(Modifiers::NONE, Key::Delete))
+      || ui.input_mut(|mem| mem.consume_key(Modifiers::NONE, Key::Backspace));
 
     if !del_key_pressed {
       return None;
     }
 
-    DeletedEvent::set(ui, &ev_id);
+    DELETED_EVENT.set(ui, ev_id.clone());
+
+    Some(())
+  }
+
+  fn handle_keyboard_copy_event(&mut self, ui: &Ui) -> Option<()> {
+    if InteractingEvent::is_interacting(ui) {
+      return None;
+    }
+
+    let ui_id = ui.memory(|mem| mem.focus())?;
+    let ev_id = EventFocusRegistry::get_event_id(ui, ui_id)?;
+
+    // ctrl-c usually arrives as a copy event instead of a key press
+    let copy_pressed = ui.input_mut(|input| {
+      input.consume_key(Modifiers::COMMAND, Key::C)
+        || input.events.iter().any(|e| matches!(e, egui::Event::Copy))
+    });
+
+    if !copy_pressed {
+      return None;
+    }
+
+    let event = self.events.iter().find(|e| e.id == ev_id)?;
+    COPIED_EVENT.set(ui, event.clone());
+
+    Some(())
+  }
+
+  // paste at the pointer, or at the same time of day on today (or the
+  // first visible day) if the pointer is not over the grid
+  fn handle_keyboard_paste_event(&mut self, ui: &Ui) -> Option<()> {
+    if InteractingEvent::is_interacting(ui) {
+      return None;
+    }
+
+    let paste_pressed = ui.input_mut(|input| {
+      input.consume_key(Modifiers::COMMAND, Key::V)
+        || input
+          .events
+          .iter()
+          .any(|e| matches!(e, egui::Event::Paste(_)))
+    });
+
+    if !paste_pressed {
+      return None;
+    }
+
+    let copied = COPIED_EVENT.get(ui)?;
+    let mut event = self.clone_to_new_event(&copied);
+
+    let pointer_time = self
+      .relative_pointer_pos(ui)
+      .and_then(|pos| self.pointer_to_datetime_auto(ui, pos));
+
+    let start = match pointer_time {
+      Some(time) if event.all_day => {
+        self.resolve_local(time.date_naive().and_time(copied.start.time()))?
+      }
+      Some(time) => time,
+      None => {
+        let today = self.today();
+        let day = if self.visible_dates().contains(&today) {
+          today
+        } else {
+          self.first_day
+        };
+
+        self.resolve_local(day.and_time(copied.start.time()))?
+      }
+    };
+
+    move_event(&mut event, start, self.now());
+    RefocusingEvent::request_focus(ui, &event.id);
+
+    self.commit_change(Change::Added { new: event });
 
     Some(())
   }
@@ -593,7 +1213,14 @@ impl ScheduleUi {
 
     let ui_id = ui.memory(|mem| mem.focus());
     let ev_id = ui_id.and_then(|id| EventFocusRegistry::get_event_id(ui, id));
-    let events = self.events.as_slice();
+    // the events of hidden calendars can't be focused
+    let events: Vec<Event> = self
+      .events
+      .iter()
+      .filter(|e| !self.in_hidden_calendar(e))
+      .cloned()
+      .collect();
+    let events = events.as_slice();
 
     // focus the first event when there is no event
     let new_focus = match (ev_id, dir) {
@@ -615,6 +1242,7 @@ impl ScheduleUi {
     Some(())
   }
 
+  // nudge the focused event by the snapping duration, or by a day
   fn handle_keyboard_focused_event_move(&mut self, ui: &Ui) -> Option<()> {
     use Direction::*;
 
@@ -622,15 +1250,20 @@ impl ScheduleUi {
     let ev_id = EventFocusRegistry::get_event_id(ui, focused_id)?;
     let dir = self.key_direction_input(ui, Modifiers::CTRL)?;
 
-    let event = self.events.iter_mut().find(|x| x.id == ev_id)?;
-
-    match dir {
-      Left => super::move_event(event, event.start + Duration::days(-1)),
-      Right => super::move_event(event, event.start + Duration::days(1)),
-      Up => super::move_event(event, event.start - self.min_event_duration),
-      Down => super::move_event(event, event.start + self.min_event_duration),
+    let mut event = self.events.iter().find(|x| x.id == ev_id)?.clone();
+    if event.all_day && matches!(dir, Up | Down) {
+      return None;
     }
 
+    let delta = match dir {
+      Left => Duration::days(-1),
+      Right => Duration::days(1),
+      Up => -self.snapping_duration,
+      Down => self.snapping_duration,
+    };
+    move_event(&mut event, event.start + delta, self.now());
+
+    self.commit_keyboard_change(ui, event);
     Some(())
   }
 
@@ -641,34 +1274,42 @@ impl ScheduleUi {
     let ev_id = EventFocusRegistry::get_event_id(ui, focused_id)?;
     let dir = self.key_direction_input(ui, Modifiers::SHIFT)?;
 
-    let event = self.events.iter_mut().find(|x| x.id == ev_id)?;
-
-    match dir {
-      Left => super::move_event_end(
-        event,
-        event.end + Duration::days(-1),
-        self.min_event_duration,
-      ),
-      Right => super::move_event_end(
-        event,
-        event.end + Duration::days(1),
-        self.min_event_duration,
-      ),
-      Up => super::move_event_end(
-        event,
-        event.end - self.min_event_duration,
-        self.min_event_duration,
-      ),
-      Down => super::move_event_end(
-        event,
-        event.end + self.min_event_duration,
-        self.min_event_duration,
-      ),
+    let mut event = self.events.iter().find(|x| x.id == ev_id)?.clone();
+    if event.all_day && matches!(dir, Up | Down) {
+      return None;
     }
 
+    let delta = match dir {
+      Left => Duration::days(-1),
+      Right => Duration::days(1),
+      Up => -self.snapping_duration,
+      Down => self.snapping_duration,
+    };
+    let now = self.now();
+    move_event_end(
+      &mut event,
+      event.end + delta,
+      self.min_resize_duration,
+      now,
+    );
+
+    self.commit_keyboard_change(ui, event);
     Some(())
   }
 
+  // commit like a finished drag, so the change can be undone
+  fn commit_keyboard_change(&self, ui: &Ui, event: Event) {
+    let unchanged = self.events.iter().any(|e| {
+      e.id == event.id && e.start == event.start && e.end == event.end
+    });
+    if unchanged {
+      return;
+    }
+
+    let state = FocusedEventState::Dragging;
+    InteractingEvent { event, state }.commit(ui);
+  }
+
   fn scroll_event_into_view(&mut self, ui: &Ui, event_id: &EventId) {
     let rect = match EventFocusRegistry::get_event_rect(ui, event_id) {
       Some(rect) => rect,
@@ -692,57 +1333,243 @@ impl ScheduleUi {
     rect: Rect,
     event: &Event,
   ) -> Response {
-    let (layout, clipped) = self.shorten_event_label(ui, rect, &event.title);
+    let mut fill = event_color(event);
+    let mut text_color = self.event_text_color(fill);
+    let dimmed_transparent =
+      event.transparent && self.transparent_events == TransparentEvents::Dimmed;
+    if !self.matches_filter(event) || dimmed_transparent {
+      fill = fill.gamma_multiply(DIMMED_OPACITY);
+      text_color = text_color.gamma_multiply(DIMMED_OPACITY);
+    }
+    let font_id = egui::TextStyle::Button.resolve(ui.style());
+    let mut title_format = TextFormat::simple(font_id, text_color);
+    if event.status == Some(EventStatus::Cancelled) {
+      title_format.strikethrough = Stroke::new(1.0, text_color);
+    }
 
-    let button = egui::Button::new(layout).sense(Sense::click_and_drag());
+    let title = if event.times_swapped {
+      format!("⚠ {}", event.title)
+    } else {
+      event.title.clone()
+    };
+    let (layout, _clipped) =
+      self.shorten_event_label(ui, rect, &title, &title_format);
+    let layout = self.add_location_line(
+      ui,
+      rect,
+      layout,
+      event.location.as_deref(),
+      title_format,
+    );
+
+    let mut button = egui::Button::new(layout)
+      .fill(fill)
+      .selected(SelectedEvents::contains(ui, &event.id))
+      .sense(Sense::click_and_drag());
+    if let Some(rounding) = self.event_rounding {
+      button = button.rounding(rounding);
+    }
+    if let Some(stroke) = self.event_stroke {
+      button = button.stroke(stroke);
+    }
     let resp = ui.put(rect, button);
     disable_built_in_keyboard_focus_navigation(&resp);
 
-    if clipped {
-      // text is clipped, show a tooltip
-      resp.clone().on_hover_text(event.title.clone());
+    if event.status == Some(EventStatus::Tentative) {
+      draw_hatching(ui, rect, text_color.gamma_multiply(0.25));
+    }
+    if event.priority_rank() > 0 {
+      // a bar on the left, thicker the higher the priority
+      let width = PRIORITY_BAR_MAX_WIDTH * event.priority_rank() as f32 / 9.0;
+      let bar = Rect::from_min_size(rect.min, vec2(width, rect.height()));
+      ui.painter()
+        .rect_filled(bar, 0.0, text_color.gamma_multiply(0.6));
     }
 
-    Self::event_context_menu(event, &resp);
+    // no tooltip getting in the way of dragging or editing
+    let busy = InteractingEvent::is_interacting(ui)
+      || ui.memory(|mem| mem.is_anything_being_dragged());
+    if !busy {
+      resp.clone().on_hover_ui(|ui| self.event_tooltip(ui, event));
+    }
+
+    self.event_context_menu(ui, event, &resp);
 
     resp
   }
 
-  fn event_context_menu(event: &Event, resp: &Response) {
-    let format_time = |time: DateTime| {
-      if time.second() == 0 {
-        time.format("%H:%M")
+  fn event_tooltip(&self, ui: &mut Ui, event: &Event) {
+    ui.strong(&event.title);
+
+    let time = if event.all_day {
+      let last_day = event.end.date_naive() - Duration::days(1);
+      if last_day <= event.start.date_naive() {
+        event.start.format("%a %b %d").to_string()
       } else {
-        time.format("%H:%M:%S")
+        format!(
+          "{} - {}",
+          event.start.format("%a %b %d"),
+          last_day.format("%a %b %d")
+        )
       }
+    } else {
+      let start_format = self.time_format.apply("%a %b %d %H:%M");
+      let end_format = self.time_format.apply("%H:%M");
+      format!(
+        "{} - {} ({})",
+        event.start.format(&start_format),
+        event.end.format(&end_format),
+        format_duration(event.end - event.start, self.duration_hint_format)
+      )
     };
+    ui.label(time);
 
-    resp.context_menu(|ui| {
-      if let Some(desc) = &event.description {
-        ui.label(desc.to_string());
-      }
+    if event.times_swapped {
+      ui.colored_label(
+        ui.visuals().warn_fg_color,
+        "⚠ ended before it started, its times were swapped",
+      );
+    }
 
-      ui.label(format!(
-        "{}--{} ({})",
-        format_time(event.start),
-        format_time(event.end),
-        (event.end - event.start)
-          .to_std()
-          .map(|d| humantime::format_duration(d).to_string())
-          .unwrap_or_else(|_| "negative duration".to_string())
-      ));
+    if let Some(location) = &event.location {
+      ui.label(format!("@ {location}"));
+    }
 
+    if let Some(description) = &event.description {
       ui.separator();
+      ui.label(description);
+    }
+  }
 
-      if ui.button("Delete").clicked() {
-        DeletedEvent::set(ui, &event.id);
-        ui.close_menu();
+  fn event_context_menu(&self, ui: &Ui, event: &Event, resp: &Response) {
+    resp.context_menu(|ui| self.event_menu_contents(ui, event));
+
+    // touch has no secondary click, a long touch opens the same menu
+    let pos = match LONG_PRESSED_EVENT.get(ui) {
+      Some(pressed) if pressed.event_id == event.id => pressed.pos,
+      _ => return,
+    };
+    egui::Area::new(resp.id.with("long_pressed_menu"))
+      .order(egui::Order::Foreground)
+      .fixed_pos(pos)
+      .constrain(true)
+      .show(ui.ctx(), |ui| {
+        egui::Frame::menu(ui.style())
+          .show(ui, |ui| self.event_menu_contents(ui, event));
+      });
+
+    // a tap outside of the menu and its submenus closes it
+    let pressed_at = ui.input(|input| {
+      let pointer = &input.pointer;
+      pointer.any_pressed().then(|| pointer.interact_pos())
+    });
+    if let Some(pressed_at) = pressed_at {
+      let layer = pressed_at.and_then(|pos| ui.ctx().layer_id_at(pos));
+      if layer.map_or(true, |layer| layer.order != egui::Order::Foreground) {
+        LONG_PRESSED_EVENT.clear(ui);
       }
+    }
+  }
+
+  fn event_menu_contents(&self, ui: &mut Ui, event: &Event) {
+    // closes the menu whether it was opened by a click or a long touch
+    let close_menu = |ui: &mut Ui| {
+      LONG_PRESSED_EVENT.clear(ui);
+      ui.close_menu();
+    };
+
+    let format_time = |time: DateTime| {
+      let format = if time.second() == 0 {
+        "%H:%M"
+      } else {
+        "%H:%M:%S"
+      };
+      time.format(&self.time_format.apply(format)).to_string()
+    };
+
+    if let Some(desc) = &event.description {
+      ui.label(desc.to_string());
+    }
+
+    if let Some(location) = &event.location {
+      ui.label(format!("@ {location}"));
+    }
 
-      if ui.button("Close menu").clicked() {
-        ui.close_menu();
+    ui.label(format!(
+      "{}--{} ({})",
+      format_time(event.start),
+      format_time(event.end),
+      (event.end - event.start)
+        .to_std()
+        .map(|d| humantime::format_duration(d).to_string())
+        .unwrap_or_else(|_| "negative duration".to_string())
+    ));
+
+    if let Some(context_menu) = &self.context_menu {
+      ui.separator();
+      let action = context_menu.show(ui, event);
+      match action {
+        // the host's items can't change a read-only schedule
+        _ if self.read_only => (),
+        Some(ContextMenuAction::Delete) => {
+          DELETED_EVENT.set(ui, event.id.clone());
+          close_menu(ui);
+        }
+        Some(ContextMenuAction::Update(new_event)) => {
+          let state = FocusedEventState::Dragging;
+          InteractingEvent {
+            event: new_event,
+            state,
+          }
+          .commit(ui);
+          close_menu(ui);
+        }
+        None => (),
       }
-    });
+    }
+
+    if self.read_only {
+      return;
+    }
+
+    ui.separator();
+
+    if ui.button("Duplicate").clicked() {
+      // place the copy right after the event
+      let mut new_event = self.clone_to_new_event(event);
+      move_event(&mut new_event, event.end, self.now());
+      self.commit_keyboard_change(ui, new_event);
+      close_menu(ui);
+    }
+
+    if !event.all_day {
+      ui.menu_button("Duration", |ui| {
+        for minutes in [15, 30, 60, 90] {
+          let duration = Duration::minutes(minutes);
+          if ui.button(format_duration(duration, None)).clicked() {
+            let mut new_event = event.clone();
+            let end = new_event.start + duration;
+            move_event_end(
+              &mut new_event,
+              end,
+              self.min_resize_duration,
+              self.now(),
+            );
+            self.commit_keyboard_change(ui, new_event);
+            close_menu(ui);
+          }
+        }
+      });
+    }
+
+    if ui.button("Delete").clicked() {
+      DELETED_EVENT.set(ui, event.id.clone());
+      close_menu(ui);
+    }
+
+    if ui.button("Close menu").clicked() {
+      close_menu(ui);
+    }
   }
 
   fn shorten_event_label(
@@ -750,16 +1577,24 @@ impl ScheduleUi {
     ui: &mut Ui,
     rect: Rect,
     label: &str,
-  ) -> (impl Into<egui::WidgetText>, bool) {
-    let font_id = egui::TextStyle::Button.resolve(ui.style());
-    let color = ui.visuals().text_color();
-
+    format: &TextFormat,
+  ) -> (Arc<Galley>, bool) {
     let layout_job = |text| {
-      let mut j = LayoutJob::simple_singleline(text, font_id.clone(), color);
+      let mut j = LayoutJob::single_section(text, format.clone());
+      j.break_on_newline = false;
       j.wrap.max_width = rect.shrink2(ui.spacing().button_padding).width();
       j
     };
 
+    if let LabelTruncation::Wrap(max_rows) = self.label_truncation {
+      let mut job = layout_job(label.into());
+      job.wrap.max_rows = max_rows.max(1);
+      job.wrap.overflow_character = Some('…');
+      let galley = ui.fonts(|fonts| fonts.layout_job(job));
+      let clipped = galley.elided;
+      return (galley, clipped);
+    }
+
     let job = layout_job(label.into());
 
     // first we attempt to fit the entire label within the button
@@ -769,8 +1604,17 @@ impl ScheduleUi {
     }
 
     // if that fails, we try to fit the shrunk label with an ellipsis
-    for n in (0..(label.len().saturating_sub(3))).rev() {
-      let text = format!("{}..", &label[0..n]);
+    let chars: Vec<char> = label.chars().collect();
+    for n in (0..chars.len().saturating_sub(1)).rev() {
+      let text = match self.label_truncation {
+        LabelTruncation::Middle => {
+          let tail = n / 2;
+          let head: String = chars[..n - tail].iter().collect();
+          let tail: String = chars[chars.len() - tail..].iter().collect();
+          format!("{head}..{tail}")
+        }
+        _ => format!("{}..", chars[..n].iter().collect::<String>()),
+      };
       let galley = ui.fonts(|fonts| fonts.layout_job(layout_job(text)));
       if galley.rows.len() <= 1 {
         return (galley, true);
@@ -781,6 +1625,48 @@ impl ScheduleUi {
     (galley, false)
   }
 
+  // show the location as a second line if there is room for it
+  fn add_location_line(
+    &self,
+    ui: &Ui,
+    rect: Rect,
+    title: Arc<Galley>,
+    location: Option<&str>,
+    title_format: TextFormat,
+  ) -> Arc<Galley> {
+    let location = match location {
+      Some(location) if !location.is_empty() => location,
+      _ => return title,
+    };
+    // a wrapped title takes the room already
+    if title.rows.len() > 1 {
+      return title;
+    }
+
+    let small_font_id = egui::TextStyle::Small.resolve(ui.style());
+    let padding = ui.spacing().button_padding;
+    let needed_height = ui.fonts(|fonts| {
+      fonts.row_height(&title_format.font_id) + fonts.row_height(&small_font_id)
+    }) + padding.y * 2.0;
+
+    if rect.height() < needed_height {
+      return title;
+    }
+
+    let color = title_format.color;
+    let mut job = LayoutJob::default();
+    job.append(title.text(), 0.0, title_format.clone());
+    job.append("\n", 0.0, title_format);
+    let location_format =
+      TextFormat::simple(small_font_id, color.gamma_multiply(0.8));
+    job.append(location, 0.0, location_format);
+    job.wrap.max_width = rect.shrink2(padding).width();
+    job.wrap.max_rows = 2;
+    job.wrap.break_anywhere = true;
+
+    ui.fonts(|fonts| fonts.layout_job(job))
+  }
+
   fn auto_suggest_event_titles(&self) -> Vec<String> {
     let mut titles = self
       .events
@@ -825,6 +1711,13 @@ impl ScheduleUi {
       return Some(false);
     }
 
+    // Switching to another app takes the focus from the whole window,
+    // keep editing until it's back instead of committing a half-typed
+    // title.
+    if !ui.input(|input| input.focused) {
+      return None;
+    }
+
     if resp.lost_focus() || resp.clicked_elsewhere() || anything_else_dragging {
       return Some(true);
     }
@@ -834,13 +1727,67 @@ impl ScheduleUi {
     None
   }
 
-  fn show_resizer_hint(&self, ui: &mut Ui, rect: Rect, time: DateTime) {
+  fn show_resizer_hint(
+    &self,
+    ui: &mut Ui,
+    rect: Rect,
+    time: DateTime,
+    format: &str,
+  ) {
+    const POINTER_GAP: f32 = 16.0;
+
     let layer_id = egui::Id::new("resizer_hint");
     let layer = LayerId::new(egui::Order::Tooltip, layer_id);
 
-    let text = format!("{}", time.format(self.event_resizing_hint_format));
-    let label = Label::new(egui::RichText::new(text).monospace());
+    let format = self.time_format.apply(format);
+    let text = format!("{}", time.format(&format));
+    let mut text = egui::RichText::new(text).monospace();
+
+    let rect = match self.resizer_hint_anchor {
+      ResizerHintAnchor::InPlace => rect,
+      ResizerHintAnchor::Gutter => {
+        let left = ui.max_rect().left();
+        let right = left + self.time_marker_margin_width;
+        Rect::from_x_y_ranges(left..=right, rect.y_range())
+      }
+      ResizerHintAnchor::FollowPointer => {
+        let pointer = match ui.input(|input| input.pointer.hover_pos()) {
+          Some(pointer) => pointer,
+          None => return,
+        };
+        let right = pointer.x - POINTER_GAP;
+        Rect::from_x_y_ranges(right - rect.width()..=right, rect.y_range())
+      }
+    };
+    // away from the event, the hint covers whatever is below it
+    if self.resizer_hint_anchor != ResizerHintAnchor::InPlace {
+      text = text.background_color(ui.visuals().extreme_bg_color);
+    }
+
+    ui.with_layer_id(layer, |ui| ui.put(rect, Label::new(text)));
+  }
+
+  // a badge next to the pointer with the length of the event
+  fn show_duration_hint(&self, ui: &mut Ui, duration: Duration) {
+    const BADGE_OFFSET: Vec2 = vec2(16.0, 16.0);
+    const BADGE_SIZE: Vec2 = vec2(64.0, 18.0);
+
+    let pointer = match ui.input(|input| input.pointer.hover_pos()) {
+      Some(pointer) => pointer,
+      None => return,
+    };
+
+    let layer_id = egui::Id::new("duration_hint");
+    let layer = LayerId::new(egui::Order::Tooltip, layer_id);
+
+    let text = format_duration(duration, self.duration_hint_format);
+    let label = Label::new(
+      egui::RichText::new(format!(" {text} "))
+        .monospace()
+        .background_color(ui.visuals().extreme_bg_color),
+    );
 
+    let rect = Rect::from_min_size(pointer + BADGE_OFFSET, BADGE_SIZE);
     ui.with_layer_id(layer, |ui| ui.put(rect, label));
   }
 
@@ -851,47 +1798,90 @@ impl ScheduleUi {
   ) -> Option<()> {
     use FocusedEventState::Editing;
 
-    let id = response.id;
     let interaction = detect_interaction(response);
 
+    if self.handle_selection_rect(ui, interaction) {
+      return Some(());
+    }
+
     match interaction {
+      None if !ui.memory(|mem| mem.is_anything_being_dragged()) => {
+        NewEventDrag::abandon(ui);
+      }
       None => (),
+      Some(Interaction::Clicked)
+        if !self.read_only && response.double_clicked() =>
+      {
+        // an event of the default length at the clicked time
+        let pointer_pos = self.relative_pointer_pos(ui)?;
+        let start = self.pointer_to_datetime_auto(ui, pointer_pos)?;
+        let mut event = self.new_event();
+        let start = fit_in_day(&event, start);
+        move_event(&mut event, start, self.now());
+
+        SelectedEvents::clear(ui);
+        InteractingEvent::set(ui, event, Editing);
+        return Some(());
+      }
       Some(Interaction::Clicked)
         if response.clicked_by(egui::PointerButton::Primary) =>
       {
         InteractingEvent::discard(ui);
+        SelectedEvents::clear(ui);
         return Some(());
       }
-      Some(Interaction::DragStarted { .. })
-        if response.dragged_by(egui::PointerButton::Primary) =>
+      Some(Interaction::DragStarted { origin })
+        if !self.read_only
+          && response.dragged_by(egui::PointerButton::Primary) =>
       {
-        let mut event = self.new_event();
-        let pointer_pos = self.relative_pointer_pos(ui)?;
-        let init_time = self.pointer_to_datetime_auto(ui, pointer_pos)?;
-        let new_state =
-          self.assign_new_event_dates(ui, init_time, &mut event)?;
-
-        ui.memory_mut(|mem| mem.data.insert_temp(id, event.id.clone()));
-        ui.memory_mut(|mem| mem.data.insert_temp(id, init_time));
-
-        InteractingEvent::set(ui, event, new_state);
-
+        // the event is only created once the pointer moved far enough
+        NEW_EVENT_DRAG.clear(ui);
+        NEW_EVENT_ORIGIN.set(ui, origin);
         return Some(());
       }
       Some(Interaction::DragReleased) => {
-        let event_id = ui.memory(|mem| mem.data.get_temp(id))?;
-        let mut value = InteractingEvent::get_id(ui, &event_id)?;
+        // drags too short to create an event leave nothing behind
+        NEW_EVENT_ORIGIN.clear(ui);
+        let drag = NEW_EVENT_DRAG.take(ui)?;
+        let mut value = InteractingEvent::get_id(ui, &drag.event_id)?;
         value.state = Editing;
         value.save(ui);
       }
+      Some(Interaction::Dragged)
+        if response.dragged_by(egui::PointerButton::Primary)
+          && NEW_EVENT_ORIGIN.get(ui).is_some() =>
+      {
+        let origin = NEW_EVENT_ORIGIN.get(ui)?;
+        let pointer = ui.input(|input| input.pointer.interact_pos())?;
+        if pointer.distance(origin) < self.new_event_drag_threshold {
+          return Some(());
+        }
+        NEW_EVENT_ORIGIN.clear(ui);
+
+        let mut event = self.new_event();
+        let origin_pos = origin - self.content_offset(ui.max_rect());
+        let init_time = self.pointer_to_datetime_auto(ui, origin_pos)?;
+        let new_state =
+          self.assign_new_event_dates(ui, init_time, &mut event)?;
+
+        let event_id = event.id.clone();
+        NEW_EVENT_DRAG.set(
+          ui,
+          NewEventDrag {
+            event_id,
+            init_time,
+          },
+        );
+        InteractingEvent::set(ui, event, new_state);
+      }
       Some(Interaction::Dragged)
         if response.dragged_by(egui::PointerButton::Primary) =>
       {
-        let event_id: String = ui.memory(|mem| mem.data.get_temp(id))?;
-        let init_time = ui.memory(|mem| mem.data.get_temp(id))?;
-        let mut value = InteractingEvent::get_id(ui, &event_id)?;
+        auto_scroll(ui);
+        let drag = NEW_EVENT_DRAG.get(ui)?;
+        let mut value = InteractingEvent::get_id(ui, &drag.event_id)?;
         let new_state =
-          self.assign_new_event_dates(ui, init_time, &mut value.event)?;
+          self.assign_new_event_dates(ui, drag.init_time, &mut value.event)?;
         value.state = new_state;
         value.save(ui);
       }
@@ -902,6 +1892,54 @@ impl ScheduleUi {
     Some(())
   }
 
+  // Ctrl-drag on the blank area selects the events touched by the
+  // rectangle. Returns whether the interaction was taken.
+  fn handle_selection_rect(
+    &self,
+    ui: &Ui,
+    interaction: Option<Interaction>,
+  ) -> bool {
+    match interaction {
+      Some(Interaction::DragStarted { origin })
+        if ui.input(|input| input.modifiers.ctrl) =>
+      {
+        SELECTION_RECT_ORIGIN.set(ui, origin);
+        true
+      }
+      Some(Interaction::Dragged) => {
+        let origin = match SELECTION_RECT_ORIGIN.get(ui) {
+          Some(origin) => origin,
+          None => return false,
+        };
+        let pointer = match ui.input(|input| input.pointer.hover_pos()) {
+          Some(pointer) => pointer,
+          None => return true,
+        };
+
+        let rect = Rect::from_two_pos(origin, pointer);
+        let selection = ui.visuals().selection;
+        ui.painter().rect(
+          rect,
+          0.0,
+          selection.bg_fill.gamma_multiply(0.3),
+          selection.stroke,
+        );
+
+        let event_ids = EventFocusRegistry::events_intersecting(ui, rect)
+          .into_iter()
+          // the registry remembers events no longer shown
+          .filter(|id| self.is_shown(id))
+          .collect();
+        SelectedEvents::set(ui, event_ids);
+        true
+      }
+      Some(Interaction::DragReleased) => {
+        SELECTION_RECT_ORIGIN.take(ui).is_some()
+      }
+      _ => false,
+    }
+  }
+
   fn assign_new_event_dates(
     &self,
     ui: &Ui,
@@ -916,15 +1954,24 @@ impl ScheduleUi {
     let (mut start, mut end) = (init_time, new_time);
     let reordered = reorder_times(&mut start, &mut end);
 
+    // too short a drag still makes an event of the minimum length
+    if end - start < self.min_new_event_duration {
+      if reordered {
+        start = end - self.min_new_event_duration;
+      } else {
+        end = start + self.min_new_event_duration;
+      }
+    }
+
     // the event crossed the day boundary, we need to pick a direction
     // based on the initial drag position
     if !on_the_same_day(start, end) {
       if self.day_progress(&init_time) < 0.5 {
         start = init_time;
-        end = init_time + self.min_event_duration;
+        end = init_time + self.min_new_event_duration;
       } else {
         end = init_time;
-        start = init_time - self.min_event_duration;
+        start = init_time - self.min_new_event_duration;
       }
     };
 
@@ -943,27 +1990,111 @@ impl ScheduleUi {
   }
 
   pub(super) fn apply_interacting_events(&mut self, ui: &Ui) {
+    for (old_id, new_id) in std::mem::take(&mut self.rekeyed_events) {
+      if let Some(mut value) = InteractingEvent::get_id(ui, &old_id) {
+        value.event.id = new_id;
+        value.save(ui);
+      }
+    }
+
     if let Some(event) = InteractingEvent::take_commited_event(ui) {
       if event.title.is_empty() {
-        DeletedEvent::set(ui, &event.id);
+        // only the events not saved yet are dropped
+        let unsaved = self.events.iter().any(|e| e.id == event.id && e.created);
+        if unsaved {
+          DELETED_EVENT.set(ui, event.id.clone());
+        }
       } else {
         RefocusingEvent::request_focus(ui, &event.id);
 
-        let change = Change::new_changed(&self.events, event);
-        change.apply(&mut self.events);
-        self.history.save(change);
+        let mut changes = self.selection_drag_changes(ui, &event.id);
+        if let Some(change) = Change::new_changed(&self.events, event) {
+          changes.insert(0, change);
+        }
+        if let Some(change) = Change::from_changes(changes) {
+          self.commit_change(change);
+        }
       }
     }
 
-    // commit deleted event
-    if let Some(event_id) = DeletedEvent::take(ui) {
-      if let Some(change) = Change::new_removed(&self.events, &event_id) {
-        change.apply(&mut self.events);
-        self.history.save(change);
+    // commit deleted event, along with the rest of the selection
+    if let Some(event_id) = DELETED_EVENT.take(ui) {
+      let selected = SelectedEvents::get(ui).event_ids;
+      if !selected.contains(&event_id) {
+        self.remove_event(&event_id);
+        return;
+      }
+
+      SelectedEvents::clear(ui);
+      let changes = selected
+        .iter()
+        .filter_map(|id| Change::new_removed(&self.events, id))
+        .collect();
+      if let Some(change) = Change::from_changes(changes) {
+        self.commit_change(change);
       }
     }
   }
 
+  // whether a drag or an interaction with an event is still kept in
+  // the memory
+  #[cfg(test)]
+  pub(crate) fn interaction_left(ctx: &egui::Context) -> bool {
+    ctx.memory(|mem| {
+      mem
+        .data
+        .get_temp::<NewEventDrag>(NEW_EVENT_DRAG.id())
+        .is_some()
+        || mem
+          .data
+          .get_temp::<InteractingEvent>(InteractingEvent::id())
+          .is_some()
+    })
+  }
+
+  // the event whose menu a long touch opened
+  #[cfg(test)]
+  pub(crate) fn long_pressed_event(ctx: &egui::Context) -> Option<EventId> {
+    let id = LONG_PRESSED_EVENT.id();
+    let pressed = ctx.memory(|mem| mem.data.get_temp::<LongPressedEvent>(id));
+    pressed.map(|pressed| pressed.event_id)
+  }
+
+  // Add or replace an event from outside the widget. Like the changes
+  // made interactively it can be undone and gets saved by the app.
+  // Returns whether an event with the same id already existed.
+  pub fn add_event(&mut self, event: Event) -> bool {
+    let existed = self.events.iter().any(|e| e.id == event.id && !e.deleted);
+
+    if let Some(change) = Change::new_changed(&self.events, event) {
+      self.commit_change(change);
+    }
+    existed
+  }
+
+  // The backend stored a created event under another id. The event
+  // keeps its place in the undo history and in the interaction.
+  pub fn rekey_event(&mut self, old_id: &EventId, new_id: EventId) {
+    for event in self.events.iter_mut().filter(|e| &e.id == old_id) {
+      event.id = new_id.clone();
+    }
+    self.history.rekey(old_id, &new_id);
+    self.rekeyed_events.push((old_id.clone(), new_id));
+  }
+
+  // returns whether the event existed
+  pub fn remove_event(&mut self, event_id: &EventId) -> bool {
+    let existed = self.events.iter().any(|e| &e.id == event_id && !e.deleted);
+    if !existed {
+      return false;
+    }
+
+    if let Some(change) = Change::new_removed(&self.events, event_id) {
+      self.commit_change(change);
+    }
+    true
+  }
+
   pub(super) fn refocus_edited_event(&self, ui: &Ui) {
     RefocusingEvent::apply_focus(ui);
   }
@@ -977,9 +2108,67 @@ impl ScheduleUi {
     }
 
     if let Some(change) = self.history.pop() {
-      change.reverse().apply(&mut self.events)
+      let change = change.reverse();
+      self.emit_change(&change);
+      let now = self.now();
+      change.apply(&mut self.events, now);
     }
   }
+
+  // apply a change made by the user, keeping it for undo
+  fn commit_change(&mut self, change: Change) {
+    self.emit_change(&change);
+    let now = self.now();
+    change.apply(&mut self.events, now);
+    self.history.save(change);
+  }
+
+  fn emit_change(&mut self, change: &Change) {
+    let emitted = match change {
+      Change::Added { new } => ScheduleEvent::Created(new.id.clone()),
+      Change::Removed { old } => ScheduleEvent::Deleted(old.id.clone()),
+      Change::Modified { new, .. } => ScheduleEvent::Edited(new.id.clone()),
+      Change::Batch(changes) => {
+        for change in changes {
+          self.emit_change(change);
+        }
+        return;
+      }
+    };
+    self.emitted_events.push(emitted);
+  }
+
+  pub(super) fn track_focused_event(&mut self, ui: &Ui) {
+    let focused = ui
+      .memory(|mem| mem.focus())
+      .and_then(|id| EventFocusRegistry::get_event_id(ui, id));
+    if focused == self.focused_event {
+      return;
+    }
+
+    if let Some(event_id) = &focused {
+      let emitted = ScheduleEvent::Selected(event_id.clone());
+      self.emitted_events.push(emitted);
+    }
+    self.focused_event = focused;
+  }
+}
+
+// compact like "1h30m", "45m" or "2h", unless a format is given with
+// the placeholders %h (hours), %m (minutes) or %M (zero-padded minutes)
+fn format_duration(duration: Duration, format: Option<&str>) -> String {
+  let hours = duration.num_hours();
+  let minutes = duration.num_minutes() % 60;
+
+  match format {
+    Some(format) => format
+      .replace("%h", &hours.to_string())
+      .replace("%M", &format!("{minutes:02}"))
+      .replace("%m", &minutes.to_string()),
+    None if hours == 0 => format!("{minutes}m"),
+    None if minutes == 0 => format!("{hours}h"),
+    None => format!("{hours}h{minutes}m"),
+  }
 }
 
 fn find_nearest_event(events: &[Event], now: &DateTime) -> Option<EventId> {
@@ -991,17 +2180,79 @@ fn find_nearest_event(events: &[Event], now: &DateTime) -> Option<EventId> {
     .map(|x| x.id.clone())
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 enum Interaction {
   Clicked,
   DragStarted { origin: egui::Pos2 },
   DragReleased,
   Dragged,
+  // a touch held in place
+  LongPressed { pos: egui::Pos2 },
 }
 
 // https://docs.rs/egui/latest/src/egui/input_state.rs.html#11-15
 const MAX_CLICK_DIST: f32 = 6.0;
 const MAX_CLICK_DURATION: f64 = 0.6;
+// fingers jitter more than mice
+const MAX_TOUCH_DIST: f32 = 16.0;
+
+// Scroll the view while the pointer is dragging near its top or bottom
+// edge, faster the closer it is, to reach the times out of view.
+fn auto_scroll(ui: &Ui) {
+  const MARGIN: f32 = 40.0;
+  // in points per second, at the very edge
+  const MAX_SPEED: f32 = 800.0;
+
+  let pointer = match ui.input(|input| input.pointer.hover_pos()) {
+    Some(pointer) => pointer,
+    None => return,
+  };
+  let visible = ui.clip_rect();
+  let proximity = if pointer.y < visible.top() + MARGIN {
+    (visible.top() + MARGIN - pointer.y) / MARGIN
+  } else if pointer.y > visible.bottom() - MARGIN {
+    -(pointer.y - (visible.bottom() - MARGIN)) / MARGIN
+  } else {
+    return;
+  };
+
+  let dt = ui.input(|input| input.stable_dt);
+  let speed = proximity.clamp(-1.0, 1.0) * MAX_SPEED;
+  ui.scroll_with_delta(vec2(0.0, speed * dt));
+  // keep scrolling while the pointer stays still
+  ui.ctx().request_repaint();
+}
+
+// a faint band showing where the event can be resized
+fn highlight_resizer(ui: &Ui, rect: Rect) {
+  const OPACITY: f32 = 0.25;
+
+  let color = ui.visuals().widgets.hovered.fg_stroke.color;
+  ui.painter().rect_filled(
+    rect,
+    ui.visuals().widgets.hovered.rounding,
+    color.gamma_multiply(OPACITY),
+  );
+}
+
+// diagonal stripes over the rect, e.g. for tentative events
+fn draw_hatching(ui: &Ui, rect: Rect, color: Color32) {
+  const SPACING: f32 = 8.0;
+
+  let painter = ui.painter_at(rect);
+  let stroke = Stroke::new(1.0, color);
+  let mut x = rect.left() - rect.height();
+  while x < rect.right() {
+    let from = egui::pos2(x, rect.bottom());
+    let to = egui::pos2(x + rect.height(), rect.top());
+    painter.line_segment([from, to], stroke);
+    x += SPACING;
+  }
+}
+
+// for events not matching the filter, or transparent ones when dimmed
+const DIMMED_OPACITY: f32 = 0.3;
+const PRIORITY_BAR_MAX_WIDTH: f32 = 5.0;
 
 fn detect_interaction(response: &Response) -> Option<Interaction> {
   use Interaction::*;
@@ -1045,22 +2296,41 @@ fn detect_interaction(response: &Response) -> Option<Interaction> {
   }
 
   if !get_flag() && response.dragged() {
+    let touch = response.ctx.input(|input| input.any_touches());
+    let max_dist = if touch {
+      MAX_TOUCH_DIST
+    } else {
+      MAX_CLICK_DIST
+    };
+
     let origin = pointer.press_origin().unwrap();
     if let Some(pos) = pointer.hover_pos() {
       let dx = (pos - origin).length_sq();
-      if dx > MAX_CLICK_DIST * MAX_CLICK_DIST {
+      if dx > max_dist * max_dist {
         set_flag(true);
         return Some(DragStarted { origin });
       }
     }
 
+    // holding a touch opens the menu instead of starting a drag, which
+    // fingers do by moving
     let dt = response.ctx.input(|input| input.time)
       - pointer.press_start_time().unwrap();
     if dt > MAX_CLICK_DURATION {
       set_flag(true);
+      if touch {
+        return Some(LongPressed { pos: origin });
+      }
       return Some(DragStarted { origin });
     }
 
+    // nothing else repaints while the finger is held still
+    if touch {
+      let remaining = MAX_CLICK_DURATION - dt;
+      let remaining = std::time::Duration::from_secs_f64(remaining);
+      response.ctx.request_repaint_after(remaining);
+    }
+
     return None;
   }
 
@@ -9,11 +9,24 @@ pub struct Ev<'a> {
   // span
   start: i64,
   end: i64,
+  // the share of the width relative to the overlapping events
+  weight: f32,
 }
 
 impl<'a> From<(&'a EventId, i64, i64)> for Ev<'a> {
   fn from((id, start, end): (&'a EventId, i64, i64)) -> Self {
-    Ev { id, start, end }
+    Ev {
+      id,
+      start,
+      end,
+      weight: 1.0,
+    }
+  }
+}
+
+impl<'a> Ev<'a> {
+  pub fn with_weight(self, weight: f32) -> Self {
+    Self { weight, ..self }
   }
 }
 
@@ -50,7 +63,9 @@ impl LayoutAlgorithm for NaiveAlgorithm {
     // EventId => (col, total_cols)
     let mut columns: HashMap<&EventId, (usize, usize)> = HashMap::new();
 
-    events.sort_by_key(|e| e.start - e.end);
+    // break ties by id so the layout doesn't depend on the order of
+    // the events
+    events.sort_by_key(|e| (e.start - e.end, e.id));
 
     let tree: IntervalTree<i64, &String> =
       events.iter().map(|e| (e.start..e.end, e.id)).collect();
@@ -62,7 +77,7 @@ impl LayoutAlgorithm for NaiveAlgorithm {
 
       let range = e.start..e.end;
       let mut siblings: Vec<_> = tree.query(range).collect();
-      siblings.sort_by_key(|e| e.range.start);
+      siblings.sort_by_key(|e| (e.range.start, e.value));
 
       let num_cols = siblings.len();
       for (n, Element { value: id, .. }) in siblings.into_iter().enumerate() {
@@ -88,7 +103,9 @@ pub struct MarkusAlgorithm;
 
 impl LayoutAlgorithm for MarkusAlgorithm {
   fn compute(mut events: Vec<Ev<'_>>) -> Layout {
-    events.sort_by_key(|e| e.start);
+    // longer events first among those starting together, ties broken by
+    // id so the columns don't depend on the order of the events
+    events.sort_by(|a, b| (a.start, b.end, a.id).cmp(&(b.start, a.end, b.id)));
 
     let ev_map: HashMap<_, _> = events.iter().map(|e| (e.id, e)).collect();
 
@@ -136,11 +153,18 @@ impl LayoutAlgorithm for MarkusAlgorithm {
       groups.push((group, group_width));
     }
 
+    // a column is as wide as the heaviest event in it needs
     let mut layout = HashMap::new();
     for (group, width) in groups {
+      let mut col_weights = vec![0.0f32; width];
+      for (id, &col) in &group {
+        col_weights[col] = col_weights[col].max(ev_map[id].weight);
+      }
+      let total: f32 = col_weights.iter().sum();
+
       for (id, col) in group {
-        let x0 = col as f32 / width as f32;
-        let x1 = (col + 1) as f32 / width as f32;
+        let x0 = col_weights[..col].iter().sum::<f32>() / total;
+        let x1 = x0 + col_weights[col] / total;
         layout.insert(id.clone(), [x0, x1]);
       }
     }
@@ -0,0 +1,720 @@
+use eframe::egui::{
+  self, vec2, Key, Modifiers, PointerButton, Pos2, RawInput, Rect,
+  TouchDeviceId, TouchId, TouchPhase,
+};
+
+use crate::{
+  event::Event,
+  util::DateTime,
+  widget::{ScheduleEvent, ScheduleUi},
+};
+
+// big enough for the whole day to be shown without scrolling
+const SCREEN_SIZE: egui::Vec2 = vec2(1600.0, 2400.0);
+const FRAME_TIME: f64 = 1.0 / 60.0;
+// frames a drag is spread over, so it passes the drag thresholds
+const DRAG_STEPS: usize = 8;
+// longer than a press can last and still be a click
+const LONG_TOUCH_SECS: f64 = 1.0;
+
+// Drives a ScheduleUi without a window, like the app does on every
+// repaint, so that the interactions can be tested headlessly. The
+// input is queued and delivered with the next frame.
+pub struct Harness {
+  ctx: egui::Context,
+  pub schedule: ScheduleUi,
+  input: Vec<egui::Event>,
+  modifiers: Modifiers,
+  time: f64,
+  // the top left of the widget as of the last frame
+  origin: Pos2,
+  refitted: bool,
+  // what the widget emitted over all frames
+  emitted: Vec<ScheduleEvent>,
+  // what was painted in the last frame, from the bottom up
+  shapes: Vec<egui::epaint::ClippedShape>,
+}
+
+impl Harness {
+  pub fn new(schedule: ScheduleUi) -> Self {
+    let mut harness = Self {
+      ctx: egui::Context::default(),
+      schedule,
+      input: vec![],
+      modifiers: Modifiers::NONE,
+      time: 0.0,
+      origin: Pos2::ZERO,
+      refitted: false,
+      emitted: vec![],
+      shapes: vec![],
+    };
+
+    // lay out the widget once so that positions can be queried
+    harness.run_frame();
+    harness
+  }
+
+  pub fn run_frame(&mut self) {
+    let raw_input = RawInput {
+      screen_rect: Some(Rect::from_min_size(Pos2::ZERO, SCREEN_SIZE)),
+      time: Some(self.time),
+      modifiers: self.modifiers,
+      events: std::mem::take(&mut self.input),
+      ..Default::default()
+    };
+    self.time += FRAME_TIME;
+
+    let Self {
+      ctx,
+      schedule,
+      origin,
+      refitted,
+      emitted,
+      ..
+    } = self;
+    let output = ctx.run(raw_input, |ctx| {
+      egui::CentralPanel::default().show(ctx, |ui| {
+        egui::ScrollArea::both().show(ui, |ui| {
+          if !std::mem::replace(refitted, true) {
+            schedule.refit_into_ui(ui);
+          }
+
+          *origin = ui.cursor().min;
+          schedule.show(ui);
+          emitted.extend_from_slice(schedule.emitted_events());
+        });
+      });
+    });
+    self.shapes = output.shapes;
+  }
+
+  // the texts painted in the last frame, from the bottom up
+  pub fn painted_texts(&self) -> Vec<String> {
+    fn collect(shape: &egui::Shape, texts: &mut Vec<String>) {
+      match shape {
+        egui::Shape::Text(text) => texts.push(text.galley.text().to_owned()),
+        egui::Shape::Vec(shapes) => {
+          shapes.iter().for_each(|shape| collect(shape, texts))
+        }
+        _ => (),
+      }
+    }
+
+    let mut texts = vec![];
+    for clipped in &self.shapes {
+      collect(&clipped.shape, &mut texts);
+    }
+    texts
+  }
+
+  // let the time pass, e.g. to avoid clicks counting as double clicks
+  pub fn wait(&mut self, secs: f64) {
+    self.time += secs;
+    self.run_frame();
+  }
+
+  pub fn events(&mut self) -> &[Event] {
+    self.schedule.events_mut()
+  }
+
+  pub fn emitted(&self) -> &[ScheduleEvent] {
+    &self.emitted
+  }
+
+  // the position of the time in its day column, if the day is shown
+  pub fn pos_of(&self, time: &DateTime) -> Option<Pos2> {
+    let day = self.schedule.day_rect(time.date_naive())?;
+    let y = self.schedule.time_to_y(time);
+    Some(self.origin + vec2(day.center().x, y))
+  }
+
+  pub fn set_modifiers(&mut self, modifiers: Modifiers) {
+    self.modifiers = modifiers;
+  }
+
+  pub fn move_to(&mut self, pos: Pos2) {
+    self.input.push(egui::Event::PointerMoved(pos));
+    self.run_frame();
+  }
+
+  pub fn press(&mut self, pos: Pos2, button: PointerButton) {
+    self.input.push(egui::Event::PointerMoved(pos));
+    self.input.push(self.pointer_button(pos, button, true));
+    self.run_frame();
+  }
+
+  pub fn release(&mut self, pos: Pos2, button: PointerButton) {
+    self.input.push(egui::Event::PointerMoved(pos));
+    self.input.push(self.pointer_button(pos, button, false));
+    self.run_frame();
+  }
+
+  pub fn click(&mut self, pos: Pos2) {
+    self.press(pos, PointerButton::Primary);
+    self.release(pos, PointerButton::Primary);
+  }
+
+  pub fn double_click(&mut self, pos: Pos2) {
+    self.click(pos);
+    self.click(pos);
+  }
+
+  // drag with the primary button between two positions, moving the
+  // pointer over several frames in between
+  pub fn drag_pos(&mut self, from: Pos2, to: Pos2) {
+    self.press(from, PointerButton::Primary);
+    for step in 1..=DRAG_STEPS {
+      let t = step as f32 / DRAG_STEPS as f32;
+      self.move_to(from.lerp(to, t));
+    }
+    self.release(to, PointerButton::Primary);
+  }
+
+  // Drag from the position of a time to the one of another, e.g. to
+  // create an event on the blank area or to move one. Returns false if
+  // either time isn't shown.
+  pub fn drag(&mut self, from: &DateTime, to: &DateTime) -> bool {
+    match (self.pos_of(from), self.pos_of(to)) {
+      (Some(from), Some(to)) => {
+        self.drag_pos(from, to);
+        true
+      }
+      _ => false,
+    }
+  }
+
+  // hold a finger still on the position, which opens the context menu
+  // of an event
+  pub fn long_touch(&mut self, pos: Pos2) {
+    self.input.push(touch(pos, TouchPhase::Start));
+    self.press(pos, PointerButton::Primary);
+    self.wait(LONG_TOUCH_SECS);
+    self.input.push(touch(pos, TouchPhase::End));
+    self.release(pos, PointerButton::Primary);
+  }
+
+  pub fn key(&mut self, key: Key) {
+    for pressed in [true, false] {
+      self.input.push(egui::Event::Key {
+        key,
+        physical_key: None,
+        pressed,
+        repeat: false,
+        modifiers: self.modifiers,
+      });
+    }
+    self.run_frame();
+  }
+
+  pub fn type_text(&mut self, text: &str) {
+    self.input.push(egui::Event::Text(text.into()));
+    self.run_frame();
+  }
+
+  fn pointer_button(
+    &self,
+    pos: Pos2,
+    button: PointerButton,
+    pressed: bool,
+  ) -> egui::Event {
+    egui::Event::PointerButton {
+      pos,
+      button,
+      pressed,
+      modifiers: self.modifiers,
+    }
+  }
+}
+
+// the integrations send the pointer events of a finger along with these
+fn touch(pos: Pos2, phase: TouchPhase) -> egui::Event {
+  egui::Event::Touch {
+    device_id: TouchDeviceId(0),
+    id: TouchId(0),
+    phase,
+    pos,
+    force: None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::{Duration, FixedOffset, NaiveDate, TimeZone};
+
+  use super::*;
+  use crate::{
+    event::EventBuilder,
+    util::{FixedClock, SharedClock, Zone},
+    widget::{ScheduleUiBuilder, ViewMode, ViewState},
+  };
+
+  fn at(h: u32, m: u32) -> DateTime {
+    let time = NaiveDate::from_ymd_opt(2024, 3, 1)
+      .and_then(|d| d.and_hms_opt(h, m, 0))
+      .expect("invalid time");
+    utc().from_local_datetime(&time).unwrap()
+  }
+
+  fn utc() -> FixedOffset {
+    FixedOffset::east_opt(0).unwrap()
+  }
+
+  fn harness(events: Vec<Event>) -> Harness {
+    let schedule = ScheduleUiBuilder::default()
+      .first_day(at(0, 0).date_naive())
+      .current_time(at(8, 0))
+      .clock(SharedClock::new(FixedClock(at(8, 0))))
+      .timezone(utc())
+      .new_event_calendar("test")
+      .events(events)
+      .build()
+      .expect("failed building schedule");
+    Harness::new(schedule)
+  }
+
+  #[test]
+  fn dragging_an_event_moves_it() {
+    let event = EventBuilder::default()
+      .id("dragged")
+      .calendar("test")
+      .title("Dragged")
+      .start(at(9, 0))
+      .end(at(10, 0))
+      .build()
+      .expect("failed building event");
+    let mut harness = harness(vec![event]);
+
+    assert!(harness.drag(&at(9, 30), &at(13, 30)));
+
+    let events = harness.events();
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].start, events[0].end), (at(13, 0), at(14, 0)));
+  }
+
+  #[test]
+  fn clicking_right_after_a_drag_commits_once() {
+    let event = EventBuilder::default()
+      .id("dragged")
+      .calendar("test")
+      .title("Dragged")
+      .start(at(9, 0))
+      .end(at(10, 0))
+      .build()
+      .expect("failed building event");
+    let mut harness = harness(vec![event]);
+
+    assert!(harness.drag(&at(9, 30), &at(13, 30)));
+    let pos = harness.pos_of(&at(13, 30)).unwrap();
+    harness.click(pos);
+    harness.wait(1.0);
+
+    let edited = ScheduleEvent::Edited("dragged".into());
+    let commits = harness.emitted().iter().filter(|&e| e == &edited);
+    assert_eq!(commits.count(), 1);
+    let events = harness.events();
+    assert_eq!((events[0].start, events[0].end), (at(13, 0), at(14, 0)));
+  }
+
+  #[test]
+  fn dragging_past_the_bottom_stops_at_the_end_of_the_day() {
+    let event = EventBuilder::default()
+      .id("dragged")
+      .calendar("test")
+      .title("Dragged")
+      .start(at(22, 0))
+      .end(at(23, 0))
+      .build()
+      .expect("failed building event");
+    let mut harness = harness(vec![event]);
+
+    let from = harness.pos_of(&at(22, 30)).unwrap();
+    let below = harness.pos_of(&at(23, 30)).unwrap() + vec2(0.0, 200.0);
+    harness.drag_pos(from, below);
+
+    let midnight = at(0, 0) + Duration::days(1);
+    let events = harness.events();
+    assert_eq!((events[0].start, events[0].end), (at(23, 0), midnight));
+  }
+
+  #[test]
+  fn dragging_out_of_the_window_keeps_the_event() {
+    let event = EventBuilder::default()
+      .id("dragged")
+      .calendar("test")
+      .title("Dragged")
+      .start(at(9, 0))
+      .end(at(10, 0))
+      .build()
+      .expect("failed building event");
+    let mut harness = harness(vec![event]);
+
+    let from = harness.pos_of(&at(9, 30)).unwrap();
+    let to = harness.pos_of(&at(11, 30)).unwrap();
+    harness.press(from, PointerButton::Primary);
+    for step in 1..=DRAG_STEPS {
+      let t = step as f32 / DRAG_STEPS as f32;
+      harness.move_to(from.lerp(to, t));
+    }
+    harness.input.push(egui::Event::PointerGone);
+    harness.run_frame();
+    harness.release(to, PointerButton::Primary);
+
+    let events = harness.events();
+    assert_eq!((events[0].start, events[0].end), (at(11, 0), at(12, 0)));
+  }
+
+  #[test]
+  fn dragging_on_the_blank_area_creates_an_event() {
+    let mut harness = harness(vec![]);
+
+    assert!(harness.drag(&at(11, 0), &at(12, 0)));
+
+    let events = harness.events();
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].start, events[0].end), (at(11, 0), at(12, 0)));
+  }
+
+  #[test]
+  fn dragged_events_are_painted_on_top() {
+    let event = |id: &str, title: &str, hour: u32| {
+      EventBuilder::default()
+        .id(id)
+        .calendar("test")
+        .title(title)
+        .start(at(hour, 0))
+        .end(at(hour + 1, 0))
+        .build()
+        .unwrap()
+    };
+    // the later events are painted over the earlier ones
+    let mut harness = harness(vec![
+      event("dragged", "Dragged", 9),
+      event("other", "Other", 13),
+    ]);
+
+    let from = harness.pos_of(&at(9, 30)).unwrap();
+    let to = harness.pos_of(&at(13, 30)).unwrap();
+    harness.press(from, PointerButton::Primary);
+    for step in 1..=DRAG_STEPS {
+      harness.move_to(from.lerp(to, step as f32 / DRAG_STEPS as f32));
+    }
+
+    let texts = harness.painted_texts();
+    let last = |title: &str| texts.iter().rposition(|text| text == title);
+    assert!(last("Other").is_some(), "{texts:?}");
+    assert!(last("Dragged") > last("Other"), "{texts:?}");
+  }
+
+  #[test]
+  fn deleting_the_focused_event() {
+    for key in [Key::Delete, Key::Backspace] {
+      let event = EventBuilder::default()
+        .id("focused")
+        .calendar("test")
+        .title("Standup")
+        .start(at(9, 0))
+        .end(at(10, 0))
+        .build()
+        .unwrap();
+      let mut harness = harness(vec![event]);
+
+      // focuses the event nearest to the clock
+      harness.key(Key::ArrowDown);
+      harness.run_frame();
+      assert_eq!(
+        harness.schedule.focused_event().map(String::as_str),
+        Some("focused")
+      );
+      harness.key(key);
+
+      assert!(harness.events().iter().all(|e| e.deleted), "{key:?}");
+    }
+  }
+
+  #[test]
+  fn resizing_past_the_other_edge_keeps_the_min_duration() {
+    // the end dragged above the start, and the start below the end, by
+    // their resizers just inside the event
+    let cases = [
+      (at(10, 0), -2.0, at(8, 0), "09:15"),
+      (at(9, 0), 2.0, at(11, 0), "09:45"),
+    ];
+    for (edge, offset, to, hint) in cases {
+      let event = EventBuilder::default()
+        .id("resized")
+        .calendar("test")
+        .title("Standup")
+        .start(at(9, 0))
+        .end(at(10, 0))
+        .build()
+        .unwrap();
+      let mut harness = harness(vec![event]);
+
+      let from = harness.pos_of(&edge).unwrap() + vec2(0.0, offset);
+      let to = harness.pos_of(&to).unwrap();
+      harness.press(from, PointerButton::Primary);
+      for step in 1..=DRAG_STEPS {
+        harness.move_to(from.lerp(to, step as f32 / DRAG_STEPS as f32));
+      }
+      // the hint is painted over everything else
+      let texts = harness.painted_texts();
+      assert_eq!(texts.last().map(String::as_str), Some(hint), "{texts:?}");
+      harness.release(to, PointerButton::Primary);
+
+      let event = &harness.events()[0];
+      assert_eq!(event.end - event.start, Duration::minutes(15), "{hint}");
+    }
+  }
+
+  #[test]
+  fn clones_keep_the_grab_point() {
+    let event = EventBuilder::default()
+      .id("original")
+      .calendar("test")
+      .title("Standup")
+      .start(at(9, 0))
+      .end(at(10, 0))
+      .build()
+      .unwrap();
+    let mut harness = harness(vec![event]);
+
+    // grabbed 20 minutes below the start
+    harness.set_modifiers(Modifiers::CTRL);
+    assert!(harness.drag(&at(9, 20), &at(13, 20)));
+    harness.set_modifiers(Modifiers::NONE);
+
+    let events = harness.events();
+    assert_eq!(events.len(), 2);
+    let original = events.iter().find(|e| e.id == "original").unwrap();
+    assert_eq!((original.start, original.end), (at(9, 0), at(10, 0)));
+    let clone = events.iter().find(|e| e.id != "original").unwrap();
+    assert_eq!((clone.start, clone.end), (at(13, 0), at(14, 0)));
+  }
+
+  fn five_minute_event() -> Event {
+    EventBuilder::default()
+      .id("short")
+      .calendar("test")
+      .title("Short")
+      .start(at(9, 0))
+      .end(at(9, 5))
+      .build()
+      .unwrap()
+  }
+
+  #[test]
+  fn five_minute_events_are_clickable() {
+    let mut harness = harness(vec![five_minute_event()]);
+
+    // in the middle of the block, drawn taller than five minutes
+    let pos = harness.pos_of(&at(9, 0)).unwrap() + vec2(0.0, 7.0);
+    harness.click(pos);
+    harness.set_modifiers(Modifiers::COMMAND);
+    harness.key(Key::A);
+    harness.set_modifiers(Modifiers::NONE);
+    harness.type_text("Renamed");
+    harness.key(Key::Enter);
+
+    assert_eq!(harness.events()[0].title, "Renamed");
+  }
+
+  #[test]
+  fn five_minute_events_keep_their_resizers() {
+    let mut harness = harness(vec![five_minute_event()]);
+
+    let from = harness.pos_of(&at(9, 0)).unwrap() + vec2(0.0, 12.0);
+    let to = harness.pos_of(&at(10, 0)).unwrap();
+    harness.drag_pos(from, to);
+
+    let events = harness.events();
+    assert_eq!((events[0].start, events[0].end), (at(9, 0), at(10, 0)));
+  }
+
+  #[test]
+  fn blank_titles_drop_new_events() {
+    let mut harness = harness(vec![]);
+
+    harness.key(Key::N);
+    harness.run_frame();
+    harness.key(Key::Enter);
+
+    assert!(harness.events().is_empty());
+  }
+
+  #[test]
+  fn blank_titles_revert_saved_events() {
+    let event = EventBuilder::default()
+      .id("saved")
+      .calendar("test")
+      .title("Standup")
+      .start(at(9, 0))
+      .end(at(10, 0))
+      .build()
+      .unwrap();
+    let mut harness = harness(vec![event]);
+
+    let pos = harness.pos_of(&at(9, 30)).unwrap();
+    harness.click(pos);
+    // select the whole title and type over it
+    harness.set_modifiers(Modifiers::COMMAND);
+    harness.key(Key::A);
+    harness.set_modifiers(Modifiers::NONE);
+    harness.type_text("  ");
+    harness.key(Key::Enter);
+
+    let events = harness.events();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].title, "Standup");
+    assert!(!events[0].deleted);
+  }
+
+  #[test]
+  fn new_events_follow_the_clock() {
+    let mut harness = harness(vec![]);
+
+    harness.key(Key::N);
+    harness.run_frame();
+    harness.type_text("Focus");
+    harness.key(Key::Enter);
+
+    let events = harness.events();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].title, "Focus");
+    assert_eq!(events[0].start, at(8, 0));
+    assert_eq!(events[0].created_at, at(8, 0));
+    assert_eq!(events[0].modified_at, at(8, 0));
+  }
+
+  #[test]
+  fn view_state_roundtrips() {
+    let mut harness = harness(vec![]);
+    let mut state = harness.schedule.save_state();
+    state.first_day = NaiveDate::from_ymd_opt(2024, 3, 4).unwrap();
+    state.view_mode = ViewMode::Week;
+    state.scroll_offset = 240.0;
+
+    let saved = toml::to_string(&state).expect("failed saving");
+    let loaded: ViewState = toml::from_str(&saved).expect("failed loading");
+    assert_eq!(loaded, state);
+
+    harness.schedule.load_state(loaded);
+    harness.run_frame();
+    let restored = harness.schedule.save_state();
+    assert_eq!(restored.first_day, state.first_day);
+    assert_eq!(restored.view_mode, state.view_mode);
+    assert_eq!(restored.segment_height, state.segment_height);
+  }
+
+  #[test]
+  fn interrupted_drags_leave_nothing_behind() {
+    let mut harness = harness(vec![]);
+
+    let from = harness.pos_of(&at(11, 0)).unwrap();
+    let to = harness.pos_of(&at(12, 0)).unwrap();
+    harness.press(from, PointerButton::Primary);
+    for step in 1..=DRAG_STEPS {
+      let t = step as f32 / DRAG_STEPS as f32;
+      harness.move_to(from.lerp(to, t));
+    }
+    assert!(ScheduleUi::interaction_left(&harness.ctx));
+
+    // e.g. the window lost focus
+    harness.ctx.memory_mut(|mem| mem.stop_dragging());
+    harness.run_frame();
+    harness.release(to, PointerButton::Primary);
+
+    assert!(!ScheduleUi::interaction_left(&harness.ctx));
+    assert!(harness.events().is_empty());
+  }
+
+  #[test]
+  fn dragging_less_than_the_threshold_creates_nothing() {
+    let mut harness = harness(vec![]);
+
+    let from = harness.pos_of(&at(11, 0)).unwrap();
+    harness.drag_pos(from, from + vec2(0.0, 4.0));
+
+    assert!(harness.events().is_empty());
+  }
+
+  #[test]
+  fn dragging_over_spring_forward_keeps_the_wall_time() {
+    let tz = "America/New_York".parse::<chrono_tz::Tz>().unwrap();
+    // clocks went forward on the night to March 10
+    let at = |day, h, m| {
+      let time = tz.with_ymd_and_hms(2024, 3, day, h, m, 0);
+      time.unwrap().fixed_offset()
+    };
+    let event = EventBuilder::default()
+      .id("dragged")
+      .calendar("test")
+      .title("Dragged")
+      .start(at(9, 9, 0))
+      .end(at(9, 10, 0))
+      .build()
+      .expect("failed building event");
+    let schedule = ScheduleUiBuilder::default()
+      .first_day(at(9, 0, 0).date_naive())
+      .current_time(at(9, 8, 0))
+      .timezone(*at(9, 8, 0).offset())
+      .zone(Some(Zone::Named(tz)))
+      .new_event_calendar("test")
+      .events(vec![event])
+      .build()
+      .expect("failed building schedule");
+    let mut harness = Harness::new(schedule);
+
+    assert!(harness.drag(&at(9, 9, 30), &at(10, 9, 30)));
+
+    let events = harness.events();
+    assert_eq!(events[0].start, at(10, 9, 0));
+    assert_eq!(events[0].end, at(10, 10, 0));
+  }
+
+  #[test]
+  fn created_events_take_their_stored_id() {
+    let mut harness = harness(vec![]);
+    assert!(harness.drag(&at(11, 0), &at(12, 0)));
+    let event_id = harness.events()[0].id.clone();
+    assert!(harness.events()[0].created);
+
+    harness.schedule.rekey_event(&event_id, "stored".into());
+    harness.run_frame();
+
+    let events = harness.events();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].id, "stored");
+  }
+
+  #[test]
+  fn long_touches_open_the_menu_despite_jitter() {
+    let event = EventBuilder::default()
+      .id("touched")
+      .calendar("test")
+      .title("Touched")
+      .start(at(9, 0))
+      .end(at(10, 0))
+      .build()
+      .expect("failed building event");
+    let mut harness = harness(vec![event]);
+
+    // farther than a click may move, not as far as a finger jitters
+    let pos = harness.pos_of(&at(9, 30)).unwrap();
+    let jittered = pos + vec2(8.0, 6.0);
+    harness.input.push(touch(pos, TouchPhase::Start));
+    harness.press(pos, PointerButton::Primary);
+    harness.input.push(touch(jittered, TouchPhase::Move));
+    harness.move_to(jittered);
+    harness.wait(LONG_TOUCH_SECS);
+
+    let pressed = ScheduleUi::long_pressed_event(&harness.ctx);
+    assert_eq!(pressed.as_deref(), Some("touched"));
+
+    harness.input.push(touch(jittered, TouchPhase::End));
+    harness.release(jittered, PointerButton::Primary);
+
+    let events = harness.events();
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].start, events[0].end), (at(9, 0), at(10, 0)));
+  }
+}